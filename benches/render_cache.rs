@@ -0,0 +1,97 @@
+#[cfg(windows)]
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+#[cfg(windows)]
+use windows_strings::HSTRING;
+#[cfg(windows)]
+use winevttest::model::{EventRenderContext, WindowsEventLogQuery, WindowsEventRender};
+#[cfg(windows)]
+use winevttest::render_cache::{set_render_caching, with_render_context};
+
+/// Channel whose events are rendered, it must not be empty.
+#[cfg(windows)]
+static CHANNEL: &str = "Application";
+
+/// Number of times each event is rendered, like a sink rendering several parts of an event.
+#[cfg(windows)]
+const RENDERS_PER_EVENT: u64 = 10;
+
+/// Look up a cached render context, as done for every rendered event.
+///
+/// Run with `cargo bench --bench render_cache`.
+#[cfg(windows)]
+fn lookup(c: &mut Criterion) {
+    let paths: Vec<HSTRING> = [
+        "Event/System/Provider/@Name",
+        "Event/System/EventID",
+        "Event/System/TimeCreated/@SystemTime",
+        "Event/EventData/Data[@Name='TargetUserName']",
+    ]
+    .iter()
+    .map(|path| HSTRING::from(*path))
+    .collect();
+    let pointers: Vec<_> = paths.iter().map(|path| path.as_ptr()).collect();
+
+    c.bench_function("render context lookup", |b| {
+        b.iter(|| {
+            with_render_context(
+                0,
+                &pointers,
+                || Ok(EventRenderContext::create_null()),
+                |context| black_box(context.as_ptr()).to_owned(),
+            )
+            .unwrap()
+        })
+    });
+}
+
+/// Render the system and user properties of the newest events of `CHANNEL`, with and without cached render contexts
+/// and scratch buffers.
+///
+/// Only the rendering is timed, not reading the events.
+#[cfg(windows)]
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    for (name, caching) in [("uncached", false), ("cached", true)] {
+        group.bench_function(name, |b| {
+            set_render_caching(caching);
+            b.iter_custom(|iters| {
+                let mut remaining = iters;
+                let mut elapsed = Duration::ZERO;
+                while remaining > 0 {
+                    let query = WindowsEventLogQuery::new(None, CHANNEL, None, true).unwrap();
+                    let read = query
+                        .read_events(
+                            |event| {
+                                for _ in 0..RENDERS_PER_EVENT.min(remaining) {
+                                    let start = Instant::now();
+                                    black_box(event.render_system_context().unwrap());
+                                    black_box(event.render_user_context().unwrap());
+                                    elapsed += start.elapsed();
+                                    remaining -= 1;
+                                }
+                            },
+                            512,
+                            1000,
+                        )
+                        .unwrap();
+                    assert!(read > 0, "The {} log has no events to render", CHANNEL);
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+    set_render_caching(true);
+}
+
+#[cfg(windows)]
+criterion_group!(benches, lookup, render);
+#[cfg(windows)]
+criterion_main!(benches);
+
+// The render cache is only built on Windows.
+#[cfg(not(windows))]
+fn main() {}
@@ -0,0 +1,398 @@
+use std::path::Path;
+#[cfg(windows)]
+use std::ptr::null;
+
+#[cfg(windows)]
+use windows_result::{Error as WindowsError, HRESULT};
+#[cfg(windows)]
+use windows_strings::HSTRING;
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::{ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS, FALSE, TRUE};
+#[cfg(windows)]
+use windows_sys::Win32::System::EventLog::*;
+
+use crate::channel::{ChannelConfig, ChannelSetting, LogInfo};
+#[cfg(windows)]
+use crate::channel::{ChannelType, LogRetention};
+#[cfg(windows)]
+use crate::conversions::*;
+use crate::maintenance::{ArchivedLog, ClearedLog, ExportLogOptions, ExportedLog};
+#[cfg(windows)]
+use crate::model::{
+    get_variant_property, EventVariantValue, OwnedWindowsEventHandle, WindowsEventHandle,
+};
+#[cfg(windows)]
+use crate::session::{session_handle, session_target, RemoteSession};
+
+#[cfg(windows)]
+static NULL_EVT_HANDLE: EVT_HANDLE = 0 as EVT_HANDLE;
+
+/// Operations of the event log service that are not tied to individual events.
+///
+/// `WindowsEventLogBackend` implements the trait on top of the Windows Event Log API. Logic built on top of it can be
+/// tested on any platform by implementing the trait on a fake.
+pub trait EventLogBackend {
+    /// Get the paths of all registered channels.
+    fn channel_paths(&self) -> Result<Vec<String>, String>;
+
+    /// Get information about the log file of a channel.
+    fn log_info(&self, channel: &str) -> Result<LogInfo, String>;
+
+    /// Get the configuration of a channel.
+    fn channel_config(&self, channel: &str) -> Result<ChannelConfig, String>;
+
+    /// Write settings of a channel, in the given order, and save the configuration.
+    fn set_channel_config(&self, channel: &str, settings: &[ChannelSetting]) -> Result<(), String>;
+
+    /// Export the events of a channel to a log file.
+    fn export_log(
+        &self,
+        channel: &str,
+        target_path: &Path,
+        options: &ExportLogOptions,
+    ) -> Result<ExportedLog, String>;
+
+    /// Add localized publisher metadata to an exported log file, so it can be viewed on other computers.
+    fn archive_exported_log(
+        &self,
+        log_file_path: &Path,
+        locale: u32,
+    ) -> Result<ArchivedLog, String>;
+
+    /// Remove all events from a channel, optionally saving them to `backup_path` first.
+    fn clear_log(&self, channel: &str, backup_path: Option<&Path>) -> Result<ClearedLog, String>;
+}
+
+/// Backend using the Windows Event Log service of the local computer or of a remote session.
+#[cfg(windows)]
+pub struct WindowsEventLogBackend {
+    session: Option<RemoteSession>,
+}
+
+#[cfg(windows)]
+impl WindowsEventLogBackend {
+    pub fn new(session: Option<&RemoteSession>) -> Self {
+        Self {
+            session: session.cloned(),
+        }
+    }
+
+    fn session_handle(&self) -> EVT_HANDLE {
+        session_handle(self.session.as_ref())
+    }
+
+    fn error(&self, action: &str, error: WindowsError) -> String {
+        format!(
+            "Failed to {} on {}: {:?}",
+            action,
+            session_target(self.session.as_ref()),
+            error.message()
+        )
+    }
+
+    fn open_channel_config(&self, channel: &str) -> Result<OwnedWindowsEventHandle, String> {
+        let channel_path = HSTRING::from(channel);
+        let handle =
+            unsafe { EvtOpenChannelConfig(self.session_handle(), channel_path.as_ptr(), 0) };
+
+        if handle == NULL_EVT_HANDLE {
+            return Err(self.error(
+                &format!("open configuration of channel '{}'", channel),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        Ok(OwnedWindowsEventHandle::new(handle))
+    }
+}
+
+#[cfg(windows)]
+impl EventLogBackend for WindowsEventLogBackend {
+    fn channel_paths(&self) -> Result<Vec<String>, String> {
+        let handle = unsafe { EvtOpenChannelEnum(self.session_handle(), 0) };
+
+        if handle == NULL_EVT_HANDLE {
+            return Err(self.error("enumerate channels", WindowsError::from_win32()));
+        }
+
+        let handle = OwnedWindowsEventHandle::new(handle);
+        let mut paths = Vec::new();
+        let mut buffer: Vec<u16> = vec![0; 256];
+
+        loop {
+            let mut buffer_used: u32 = 0;
+
+            if unsafe {
+                EvtNextChannelPath(
+                    *handle.get_handle(),
+                    buffer.len() as u32,
+                    buffer.as_mut_ptr(),
+                    &mut buffer_used,
+                )
+            } == TRUE
+            {
+                paths.push(buffer.as_ptr().win_into());
+                continue;
+            }
+
+            let last_error = WindowsError::from_win32();
+
+            if last_error.code() == HRESULT::from_win32(ERROR_NO_MORE_ITEMS) {
+                break;
+            } else if last_error.code() == HRESULT::from_win32(ERROR_INSUFFICIENT_BUFFER) {
+                buffer.resize(buffer_used as usize, 0);
+            } else {
+                return Err(self.error("enumerate channels", last_error));
+            }
+        }
+
+        Ok(paths)
+    }
+
+    fn log_info(&self, channel: &str) -> Result<LogInfo, String> {
+        let channel_path = HSTRING::from(channel);
+        let handle = unsafe {
+            EvtOpenLog(
+                self.session_handle(),
+                channel_path.as_ptr(),
+                EvtOpenChannelPath,
+            )
+        };
+
+        if handle == NULL_EVT_HANDLE {
+            return Err(self.error(
+                &format!("open log of channel '{}'", channel),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        let handle = OwnedWindowsEventHandle::new(handle);
+        let property = |property_id: EVT_LOG_PROPERTY_ID| {
+            get_variant_property(|size, buffer, used| unsafe {
+                EvtGetLogInfo(*handle.get_handle(), property_id, size, buffer, used)
+            })
+            .map_err(|error| {
+                self.error(
+                    &format!("get log information of channel '{}'", channel),
+                    error,
+                )
+            })
+        };
+
+        Ok(LogInfo {
+            creation_time: property(EvtLogCreationTime)?.as_datetime(),
+            last_access_time: property(EvtLogLastAccessTime)?.as_datetime(),
+            last_write_time: property(EvtLogLastWriteTime)?.as_datetime(),
+            file_size: property(EvtLogFileSize)?.as_u64(),
+            attributes: property(EvtLogAttributes)?.as_u32(),
+            record_count: property(EvtLogNumberOfLogRecords)?.as_u64(),
+            oldest_record_number: property(EvtLogOldestRecordNumber)?.as_u64(),
+            full: property(EvtLogFull)?.as_bool(),
+        })
+    }
+
+    fn channel_config(&self, channel: &str) -> Result<ChannelConfig, String> {
+        let handle = self.open_channel_config(channel)?;
+        let property = |property_id: EVT_CHANNEL_CONFIG_PROPERTY_ID| {
+            get_variant_property(|size, buffer, used| unsafe {
+                EvtGetChannelConfigProperty(
+                    *handle.get_handle(),
+                    property_id,
+                    0,
+                    size,
+                    buffer,
+                    used,
+                )
+            })
+            .map_err(|error| {
+                self.error(
+                    &format!("get configuration of channel '{}'", channel),
+                    error,
+                )
+            })
+        };
+        let string_property = |property_id| -> Result<Option<String>, String> {
+            Ok(match property(property_id)? {
+                EventVariantValue::String(value) if !value.is_empty() => Some(value),
+                _ => None,
+            })
+        };
+
+        Ok(ChannelConfig {
+            enabled: property(EvtChannelConfigEnabled)?
+                .as_bool()
+                .unwrap_or(false),
+            channel_type: property(EvtChannelConfigType)?
+                .as_u32()
+                .and_then(ChannelType::from_raw),
+            classic: property(EvtChannelConfigClassicEventlog)?
+                .as_bool()
+                .unwrap_or(false),
+            owning_publisher: string_property(EvtChannelConfigOwningPublisher)?,
+            max_size: property(EvtChannelLoggingConfigMaxSize)?
+                .as_u64()
+                .unwrap_or(0),
+            retention: LogRetention::from_flags(
+                property(EvtChannelLoggingConfigRetention)?
+                    .as_bool()
+                    .unwrap_or(false),
+                property(EvtChannelLoggingConfigAutoBackup)?
+                    .as_bool()
+                    .unwrap_or(false),
+            ),
+            log_file_path: string_property(EvtChannelLoggingConfigLogFilePath)?,
+        })
+    }
+
+    fn set_channel_config(&self, channel: &str, settings: &[ChannelSetting]) -> Result<(), String> {
+        let handle = self.open_channel_config(channel)?;
+        let set_property = |property_id: EVT_CHANNEL_CONFIG_PROPERTY_ID, value: EVT_VARIANT| {
+            if unsafe { EvtSetChannelConfigProperty(*handle.get_handle(), property_id, 0, &value) }
+                == FALSE
+            {
+                return Err(self.error(
+                    &format!("set configuration of channel '{}'", channel),
+                    WindowsError::from_win32(),
+                ));
+            }
+            Ok(())
+        };
+
+        for setting in settings {
+            match *setting {
+                ChannelSetting::Enabled(enabled) => {
+                    set_property(EvtChannelConfigEnabled, bool_variant(enabled))?
+                }
+                ChannelSetting::MaxSize(max_size) => {
+                    set_property(EvtChannelLoggingConfigMaxSize, u64_variant(max_size))?
+                }
+                ChannelSetting::Retention(retention) => {
+                    let (retain, auto_backup) = retention.as_flags();
+                    set_property(EvtChannelLoggingConfigRetention, bool_variant(retain))?;
+                    set_property(EvtChannelLoggingConfigAutoBackup, bool_variant(auto_backup))?;
+                }
+            }
+        }
+
+        if unsafe { EvtSaveChannelConfig(*handle.get_handle(), 0) } == FALSE {
+            return Err(self.error(
+                &format!("save configuration of channel '{}'", channel),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn export_log(
+        &self,
+        channel: &str,
+        target_path: &Path,
+        options: &ExportLogOptions,
+    ) -> Result<ExportedLog, String> {
+        let channel_path = HSTRING::from(channel);
+        let query = options.query.as_deref().map(HSTRING::from);
+        let target = HSTRING::from(target_path);
+
+        let mut flags = EvtExportLogChannelPath;
+        if options.tolerate_query_errors {
+            flags |= EvtExportLogTolerateQueryErrors;
+        }
+        if options.overwrite {
+            flags |= EvtExportLogOverwrite;
+        }
+
+        if unsafe {
+            EvtExportLog(
+                self.session_handle(),
+                channel_path.as_ptr(),
+                query.as_ref().map_or(null(), |q| q.as_ptr()),
+                target.as_ptr(),
+                flags,
+            )
+        } == FALSE
+        {
+            return Err(self.error(
+                &format!(
+                    "export channel '{}' to '{}'",
+                    channel,
+                    target_path.display()
+                ),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        Ok(ExportedLog {
+            channel: channel.to_owned(),
+            target_path: target_path.to_owned(),
+            query: options.query.clone(),
+        })
+    }
+
+    fn archive_exported_log(
+        &self,
+        log_file_path: &Path,
+        locale: u32,
+    ) -> Result<ArchivedLog, String> {
+        let path = HSTRING::from(log_file_path);
+
+        if unsafe { EvtArchiveExportedLog(self.session_handle(), path.as_ptr(), locale, 0) }
+            == FALSE
+        {
+            return Err(self.error(
+                &format!("archive exported log '{}'", log_file_path.display()),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        Ok(ArchivedLog {
+            path: log_file_path.to_owned(),
+            locale,
+        })
+    }
+
+    fn clear_log(&self, channel: &str, backup_path: Option<&Path>) -> Result<ClearedLog, String> {
+        let channel_path = HSTRING::from(channel);
+        let backup = backup_path.map(HSTRING::from);
+
+        if unsafe {
+            EvtClearLog(
+                self.session_handle(),
+                channel_path.as_ptr(),
+                backup.as_ref().map_or(null(), |b| b.as_ptr()),
+                0,
+            )
+        } == FALSE
+        {
+            return Err(self.error(
+                &format!("clear channel '{}'", channel),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        Ok(ClearedLog {
+            channel: channel.to_owned(),
+            backup_path: backup_path.map(Path::to_owned),
+        })
+    }
+}
+
+#[cfg(windows)]
+fn bool_variant(value: bool) -> EVT_VARIANT {
+    EVT_VARIANT {
+        Anonymous: EVT_VARIANT_0 {
+            BooleanVal: if value { TRUE } else { FALSE },
+        },
+        Count: 0,
+        Type: EvtVarTypeBoolean as u32,
+    }
+}
+
+#[cfg(windows)]
+fn u64_variant(value: u64) -> EVT_VARIANT {
+    EVT_VARIANT {
+        Anonymous: EVT_VARIANT_0 { UInt64Val: value },
+        Count: 0,
+        Type: EvtVarTypeUInt64 as u32,
+    }
+}
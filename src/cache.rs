@@ -0,0 +1,119 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Map holding at most `capacity` entries, evicting the least recently used entry when a new one is inserted into a
+/// full cache.
+///
+/// Lookups and insertions are `O(1)`, evictions `O(n)`. The cache is meant for a small number of expensive values,
+/// like open Windows handles, where dropping an evicted value releases its resources.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (u64, V)>,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    /// Create an empty cache. A capacity of zero is treated as one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    /// Look up a value and mark it as most recently used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tick += 1;
+        let tick = self.tick;
+
+        self.entries.get_mut(key).map(|entry| {
+            entry.0 = tick;
+            &entry.1
+        })
+    }
+
+    /// Insert a value as most recently used.
+    ///
+    /// Returns the value that had to make room for it: either the previous value of `key`, or the least recently
+    /// used entry if the cache was full.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tick += 1;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.0 = self.tick;
+            return Some(std::mem::replace(&mut entry.1, value));
+        }
+
+        let evicted = if self.entries.len() >= self.capacity {
+            self.evict_least_recently_used()
+        } else {
+            None
+        };
+
+        self.entries.insert(key, (self.tick, value));
+        evicted
+    }
+
+    /// Look up a value, creating and inserting it with `create` if it is not cached yet.
+    ///
+    /// Errors of `create` are returned as they are and nothing is inserted.
+    pub fn get_or_try_insert_with<E, F>(&mut self, key: K, create: F) -> Result<&V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        if !self.entries.contains_key(&key) {
+            let value = create()?;
+            self.insert(key.clone(), value);
+        }
+
+        Ok(self.get(&key).expect("value was inserted above"))
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.remove(key).map(|(_, value)| value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict_least_recently_used(&mut self) -> Option<V> {
+        let key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (tick, _))| *tick)
+            .map(|(key, _)| key.clone())?;
+
+        self.remove(&key)
+    }
+}
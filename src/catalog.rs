@@ -0,0 +1,712 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::manifest::{format_field_value, InstrumentationManifest};
+use crate::message_template::expand_message;
+use crate::pe::{classic_message_id, MessageTable};
+#[cfg(windows)]
+use crate::publisher::PublisherMetadata;
+use crate::variant::EventVariantValue;
+
+/// Version of the on-disk catalog format. Catalogs of other versions are rejected on load.
+pub static MESSAGE_CATALOG_FORMAT_VERSION: u32 = 1;
+
+/// Culture used when a string is not available in the requested one.
+static FALLBACK_LOCALE: &str = "en-US";
+
+/// Cultures of the locale IDs (LCIDs) accepted by the rendering functions.
+static LOCALE_NAMES: &[(u32, &str)] = &[
+    (0x0405, "cs-CZ"),
+    (0x0406, "da-DK"),
+    (0x0407, "de-DE"),
+    (0x0408, "el-GR"),
+    (0x0409, "en-US"),
+    (0x040b, "fi-FI"),
+    (0x040c, "fr-FR"),
+    (0x040e, "hu-HU"),
+    (0x0410, "it-IT"),
+    (0x0411, "ja-JP"),
+    (0x0412, "ko-KR"),
+    (0x0413, "nl-NL"),
+    (0x0414, "nb-NO"),
+    (0x0415, "pl-PL"),
+    (0x0416, "pt-BR"),
+    (0x0419, "ru-RU"),
+    (0x041d, "sv-SE"),
+    (0x041f, "tr-TR"),
+    (0x0804, "zh-CN"),
+    (0x0404, "zh-TW"),
+    (0x0809, "en-GB"),
+    (0x0816, "pt-PT"),
+    (0x0c0a, "es-ES"),
+];
+
+/// Get the culture name of a locale ID, e.g. `en-US` for `0x409`.
+pub fn locale_name(locale: u32) -> Option<&'static str> {
+    LOCALE_NAMES
+        .iter()
+        .find(|(id, _)| *id == locale)
+        .map(|(_, name)| *name)
+}
+
+/// Get the locale ID of a culture name, e.g. `0x409` for `en-US`.
+pub fn locale_id(name: &str) -> Option<u32> {
+    LOCALE_NAMES
+        .iter()
+        .find(|(_, culture)| culture.eq_ignore_ascii_case(name))
+        .map(|(id, _)| *id)
+}
+
+/// Kind of a named value of a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogNameKind {
+    Level,
+    Task,
+    Opcode,
+    Keyword,
+    Channel,
+}
+
+/// Message template of an event in one culture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogMessage {
+    /// Event ID. For classic event sources, the message ID including the qualifiers.
+    pub event_id: u32,
+    pub version: u8,
+    pub locale: String,
+    pub template: String,
+}
+
+/// Display name of a level, task, opcode, keyword or channel in one culture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogName {
+    pub kind: CatalogNameKind,
+    pub value: u64,
+    pub locale: String,
+    pub name: String,
+}
+
+/// String of a provider's parameter file, inserted for `%%NNNN`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogParameter {
+    pub id: u32,
+    pub locale: String,
+    pub value: String,
+}
+
+/// Field of an event template, as far as it matters for formatting the event message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogField {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_type: Option<String>,
+    /// Name of the value map used to render the field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map: Option<String>,
+}
+
+/// Template fields of an event, in the order of its user data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogTemplate {
+    pub event_id: u32,
+    pub version: u8,
+    pub fields: Vec<CatalogField>,
+}
+
+/// Value map (`valueMap` or `bitMap`) of a provider, with its strings in all cultures.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogValueMap {
+    pub bitmap: bool,
+    pub entries: Vec<CatalogMapEntry>,
+}
+
+/// String of a value map entry in one culture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogMapEntry {
+    pub value: u64,
+    pub locale: String,
+    pub name: String,
+}
+
+/// Entry of a `CatalogProvider`, grouped by the key it is looked up with.
+trait CatalogEntry {
+    type Key: Clone + Eq + Hash + Ord;
+
+    fn key(&self) -> Self::Key;
+}
+
+impl CatalogEntry for CatalogMessage {
+    type Key = u32;
+
+    fn key(&self) -> u32 {
+        self.event_id
+    }
+}
+
+impl CatalogEntry for CatalogName {
+    type Key = (CatalogNameKind, u64);
+
+    fn key(&self) -> (CatalogNameKind, u64) {
+        (self.kind, self.value)
+    }
+}
+
+impl CatalogEntry for CatalogTemplate {
+    type Key = u32;
+
+    fn key(&self) -> u32 {
+        self.event_id
+    }
+}
+
+impl CatalogEntry for CatalogParameter {
+    type Key = u32;
+
+    fn key(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Entries grouped by key, stored in catalog files as one list sorted by key.
+mod entry_list {
+    use super::*;
+
+    pub fn serialize<T, S>(
+        entries: &HashMap<T::Key, Vec<T>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: CatalogEntry + Serialize,
+        S: Serializer,
+    {
+        let mut keys: Vec<&T::Key> = entries.keys().collect();
+        keys.sort();
+        serializer.collect_seq(keys.into_iter().flat_map(|key| &entries[key]))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<HashMap<T::Key, Vec<T>>, D::Error>
+    where
+        T: CatalogEntry + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let mut entries: HashMap<T::Key, Vec<T>> = HashMap::new();
+        for entry in Vec::<T>::deserialize(deserializer)? {
+            entries.entry(entry.key()).or_default().push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogProvider {
+    pub name: String,
+    /// Provider GUID formatted like `{xxxxxxxx-...}`. Classic event sources have none.
+    pub guid: Option<String>,
+    /// Message templates by event ID, in all versions and cultures.
+    #[serde(default, with = "entry_list")]
+    pub messages: HashMap<u32, Vec<CatalogMessage>>,
+    /// Display names by kind and value, in all cultures.
+    #[serde(default, with = "entry_list")]
+    pub names: HashMap<(CatalogNameKind, u64), Vec<CatalogName>>,
+    /// Parameter strings by ID, in all cultures.
+    #[serde(default, with = "entry_list")]
+    pub parameters: HashMap<u32, Vec<CatalogParameter>>,
+    /// Template fields by event ID, in all versions. Only known for providers added from a manifest.
+    #[serde(default, with = "entry_list")]
+    pub templates: HashMap<u32, Vec<CatalogTemplate>>,
+    /// Value maps by name.
+    #[serde(default)]
+    pub maps: BTreeMap<String, CatalogValueMap>,
+}
+
+impl CatalogProvider {
+    /// Get the message template of an event.
+    ///
+    /// If the exact version is not known, the highest version of the event is used. If the template is not
+    /// available in `locale`, `en-US` or any other culture is used.
+    pub fn message(&self, event_id: u32, version: u8, locale: &str) -> Option<&str> {
+        let candidates = self.messages.get(&event_id)?;
+
+        let version = if candidates.iter().any(|message| message.version == version) {
+            version
+        } else {
+            candidates.iter().map(|message| message.version).max()?
+        };
+
+        preferred_locale(
+            candidates
+                .iter()
+                .filter(|message| message.version == version),
+            |message| &message.locale,
+            locale,
+        )
+        .map(|message| message.template.as_str())
+    }
+
+    /// Get the template fields of an event. Like for messages, the highest version is used if the exact version is
+    /// not known.
+    pub fn template(&self, event_id: u32, version: u8) -> Option<&CatalogTemplate> {
+        let candidates = self.templates.get(&event_id)?;
+
+        candidates
+            .iter()
+            .find(|template| template.version == version)
+            .or_else(|| candidates.iter().max_by_key(|template| template.version))
+    }
+
+    /// Get the values of a value map with their strings in `locale`, falling back like `message`.
+    pub fn map_entries(&self, map: &str, locale: &str) -> Option<(bool, Vec<(u64, &str)>)> {
+        let map = self.maps.get(map)?;
+
+        let mut values: Vec<u64> = map.entries.iter().map(|entry| entry.value).collect();
+        values.sort_unstable();
+        values.dedup();
+
+        let entries = values
+            .into_iter()
+            .filter_map(|value| {
+                preferred_locale(
+                    map.entries.iter().filter(|entry| entry.value == value),
+                    |entry| &entry.locale,
+                    locale,
+                )
+                .map(|entry| (value, entry.name.as_str()))
+            })
+            .collect();
+
+        Some((map.bitmap, entries))
+    }
+
+    pub fn name(&self, kind: CatalogNameKind, value: u64, locale: &str) -> Option<&str> {
+        preferred_locale(
+            self.names.get(&(kind, value))?.iter(),
+            |name| &name.locale,
+            locale,
+        )
+        .map(|name| name.name.as_str())
+    }
+
+    pub fn parameter(&self, id: u32, locale: &str) -> Option<&str> {
+        preferred_locale(
+            self.parameters.get(&id)?.iter(),
+            |parameter| &parameter.locale,
+            locale,
+        )
+        .map(|parameter| parameter.value.as_str())
+    }
+
+    /// Add a message template, replacing the template of the same event, version and culture.
+    pub fn set_message(&mut self, event_id: u32, version: u8, locale: &str, template: &str) {
+        let message = CatalogMessage {
+            event_id,
+            version,
+            locale: locale.to_owned(),
+            template: template.to_owned(),
+        };
+
+        let messages = self.messages.entry(event_id).or_default();
+        match messages
+            .iter_mut()
+            .find(|existing| existing.version == version && existing.locale == locale)
+        {
+            Some(existing) => *existing = message,
+            None => messages.push(message),
+        }
+    }
+
+    /// Add a display name, replacing the name of the same value and culture.
+    pub fn set_name(&mut self, kind: CatalogNameKind, value: u64, locale: &str, name: &str) {
+        let entry = CatalogName {
+            kind,
+            value,
+            locale: locale.to_owned(),
+            name: name.to_owned(),
+        };
+
+        let names = self.names.entry((kind, value)).or_default();
+        match names.iter_mut().find(|existing| existing.locale == locale) {
+            Some(existing) => *existing = entry,
+            None => names.push(entry),
+        }
+    }
+
+    /// Set the template fields of an event, replacing the fields of the same version.
+    pub fn set_template(&mut self, event_id: u32, version: u8, fields: Vec<CatalogField>) {
+        let template = CatalogTemplate {
+            event_id,
+            version,
+            fields,
+        };
+
+        let templates = self.templates.entry(event_id).or_default();
+        match templates
+            .iter_mut()
+            .find(|existing| existing.version == version)
+        {
+            Some(existing) => *existing = template,
+            None => templates.push(template),
+        }
+    }
+
+    /// Add a value map entry, replacing the string of the same map, value and culture.
+    pub fn set_map_entry(&mut self, map: &str, bitmap: bool, value: u64, locale: &str, name: &str) {
+        let entry = CatalogMapEntry {
+            value,
+            locale: locale.to_owned(),
+            name: name.to_owned(),
+        };
+
+        let map = self.maps.entry(map.to_owned()).or_default();
+        map.bitmap = bitmap;
+        match map
+            .entries
+            .iter_mut()
+            .find(|existing| existing.value == value && existing.locale == locale)
+        {
+            Some(existing) => *existing = entry,
+            None => map.entries.push(entry),
+        }
+    }
+
+    /// Add a parameter string, replacing the string of the same ID and culture.
+    pub fn set_parameter(&mut self, id: u32, locale: &str, value: &str) {
+        let parameter = CatalogParameter {
+            id,
+            locale: locale.to_owned(),
+            value: value.to_owned(),
+        };
+
+        let parameters = self.parameters.entry(id).or_default();
+        match parameters
+            .iter_mut()
+            .find(|existing| existing.locale == locale)
+        {
+            Some(existing) => *existing = parameter,
+            None => parameters.push(parameter),
+        }
+    }
+}
+
+/// Pick the entry in `locale`, then in `en-US`, then in any culture.
+fn preferred_locale<'a, T, I, L>(entries: I, entry_locale: L, locale: &str) -> Option<&'a T>
+where
+    I: Iterator<Item = &'a T>,
+    L: Fn(&T) -> &String,
+{
+    entries.min_by_key(|entry| {
+        let entry_locale = entry_locale(entry);
+        if entry_locale.eq_ignore_ascii_case(locale) {
+            0
+        } else if entry_locale.eq_ignore_ascii_case(FALLBACK_LOCALE) {
+            1
+        } else {
+            2
+        }
+    })
+}
+
+/// Portable catalog of event messages and display names, used to format events of providers that are not installed
+/// on the local computer.
+///
+/// Catalogs are filled from the publisher metadata of a live host, from instrumentation manifests or from the message
+/// tables of PE files, and stored as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageCatalog {
+    pub format_version: u32,
+    pub providers: Vec<CatalogProvider>,
+}
+
+impl Default for MessageCatalog {
+    fn default() -> Self {
+        Self {
+            format_version: MESSAGE_CATALOG_FORMAT_VERSION,
+            providers: Vec::new(),
+        }
+    }
+}
+
+impl MessageCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let catalog: Self = serde_json::from_str(json)
+            .map_err(|error| format!("Failed to parse message catalog: {}", error))?;
+
+        if catalog.format_version != MESSAGE_CATALOG_FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported message catalog format version {}, expected {}",
+                catalog.format_version, MESSAGE_CATALOG_FORMAT_VERSION
+            ));
+        }
+
+        Ok(catalog)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|error| format!("Failed to serialize message catalog: {}", error))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read '{}': {}", path.display(), error))?;
+
+        Self::from_json(&json).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_json()?)
+            .map_err(|error| format!("Failed to write '{}': {}", path.display(), error))
+    }
+
+    /// Find a provider by name (case insensitive) or by GUID.
+    pub fn provider(&self, name_or_guid: &str) -> Option<&CatalogProvider> {
+        let guid = name_or_guid.trim_matches(['{', '}']);
+
+        self.providers.iter().find(|provider| {
+            provider.name.eq_ignore_ascii_case(name_or_guid)
+                || provider.guid.as_deref().is_some_and(|provider_guid| {
+                    provider_guid
+                        .trim_matches(['{', '}'])
+                        .eq_ignore_ascii_case(guid)
+                })
+        })
+    }
+
+    /// Get a provider by name, adding it if it is not in the catalog yet.
+    pub fn provider_mut(&mut self, name: &str, guid: Option<&str>) -> &mut CatalogProvider {
+        let position = match self
+            .providers
+            .iter()
+            .position(|provider| provider.name.eq_ignore_ascii_case(name))
+        {
+            Some(position) => position,
+            None => {
+                self.providers.push(CatalogProvider {
+                    name: name.to_owned(),
+                    ..Default::default()
+                });
+                self.providers.len() - 1
+            }
+        };
+
+        let provider = &mut self.providers[position];
+        if let Some(guid) = guid.filter(|guid| !guid.is_empty()) {
+            provider.guid = Some(guid.to_owned());
+        }
+        provider
+    }
+
+    /// Add the messages and names of all providers of an instrumentation manifest, in all of its cultures.
+    pub fn add_manifest(&mut self, manifest: &InstrumentationManifest) {
+        let cultures: Vec<String> = manifest.cultures().into_iter().map(str::to_owned).collect();
+
+        for manifest_provider in &manifest.providers {
+            let provider =
+                self.provider_mut(&manifest_provider.name, Some(&manifest_provider.guid));
+
+            for event in &manifest_provider.events {
+                if let Some(template) = manifest_provider.template(event) {
+                    let fields = template
+                        .fields
+                        .iter()
+                        .map(|field| CatalogField {
+                            out_type: field.out_type.clone(),
+                            map: field.map.clone(),
+                        })
+                        .collect();
+                    provider.set_template(event.value as u32, event.version, fields);
+                }
+            }
+
+            for culture in &cultures {
+                for event in &manifest_provider.events {
+                    if let Some(template) = localized(manifest, &event.message, culture) {
+                        provider.set_message(event.value as u32, event.version, culture, template);
+                    }
+                }
+
+                let named_values = [
+                    (CatalogNameKind::Level, &manifest_provider.levels),
+                    (CatalogNameKind::Task, &manifest_provider.tasks),
+                    (CatalogNameKind::Opcode, &manifest_provider.opcodes),
+                    (CatalogNameKind::Keyword, &manifest_provider.keywords),
+                ];
+                for (kind, entries) in named_values {
+                    for entry in entries {
+                        if let Some(name) = localized(manifest, &entry.message, culture) {
+                            provider.set_name(kind, entry.value, culture, name);
+                        }
+                    }
+                }
+
+                for channel in &manifest_provider.channels {
+                    if let (Some(value), Some(name)) = (
+                        channel.value,
+                        localized(manifest, &channel.message, culture),
+                    ) {
+                        provider.set_name(CatalogNameKind::Channel, value, culture, name);
+                    }
+                }
+
+                for map in manifest_provider.maps.values() {
+                    for (value, reference) in &map.entries {
+                        if let Some(name) = manifest.localized_string(reference, culture) {
+                            provider.set_map_entry(&map.name, map.bitmap, *value, culture, name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add the messages of a message table, e.g. of the event message file of a classic event source.
+    ///
+    /// Message IDs are stored as they are, so classic events are found by their qualifiers and event ID.
+    pub fn add_message_table(&mut self, provider: &str, table: &MessageTable) {
+        let locale = table_locale(table);
+        let provider = self.provider_mut(provider, None);
+
+        for (message_id, template) in &table.messages {
+            provider.set_message(*message_id, 0, &locale, template);
+        }
+    }
+
+    /// Add the strings of a parameter message file, used for `%%NNNN` insertions.
+    pub fn add_parameter_table(&mut self, provider: &str, table: &MessageTable) {
+        let locale = table_locale(table);
+        let provider = self.provider_mut(provider, None);
+
+        for (id, value) in &table.messages {
+            provider.set_parameter(*id, &locale, value);
+        }
+    }
+
+    /// Add the messages and names of a publisher installed on a live host.
+    ///
+    /// `locale` is the culture the metadata was opened in, e.g. `en-US`.
+    #[cfg(windows)]
+    pub fn add_publisher_metadata(
+        &mut self,
+        metadata: &PublisherMetadata,
+        locale: &str,
+    ) -> Result<(), String> {
+        let guid = metadata
+            .guid()?
+            .map(|guid| crate::variant::format_guid(&guid));
+        let events = metadata.events()?;
+        let named_values = [
+            (CatalogNameKind::Level, metadata.levels()?),
+            (CatalogNameKind::Task, metadata.tasks()?),
+            (CatalogNameKind::Opcode, metadata.opcodes()?),
+            (CatalogNameKind::Keyword, metadata.keywords()?),
+            (CatalogNameKind::Channel, metadata.channels()?),
+        ];
+
+        let provider = self.provider_mut(metadata.provider_name(), guid.as_deref());
+
+        for event in events {
+            if let Some(template) = &event.message {
+                provider.set_message(event.id, event.version, locale, template);
+            }
+        }
+
+        for (kind, entries) in named_values {
+            for entry in entries {
+                if let Some(name) = &entry.message {
+                    provider.set_name(kind, entry.value, locale, name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Format the message of an event from its user data, in template order (as returned by `render_user_context`).
+    ///
+    /// Events of classic event sources are looked up by their message ID (`qualifiers` and `event_id`) first. Fields
+    /// of events added from a manifest are rendered with their value maps and output types, like
+    /// `InstrumentationManifest::format_event_message` does.
+    pub fn format_event_message(
+        &self,
+        provider: &str,
+        event_id: u16,
+        qualifiers: u16,
+        version: u8,
+        locale: &str,
+        values: &[EventVariantValue],
+    ) -> Option<String> {
+        let provider = self.provider(provider)?;
+
+        let template = match qualifiers {
+            0 => None,
+            qualifiers => {
+                provider.message(classic_message_id(qualifiers, event_id), version, locale)
+            }
+        }
+        .or_else(|| provider.message(event_id as u32, version, locale))?;
+
+        let fields = provider
+            .template(event_id as u32, version)
+            .map_or(&[][..], |template| template.fields.as_slice());
+        let insertions: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let field = fields.get(index);
+                let map = field
+                    .and_then(|field| field.map.as_deref())
+                    .and_then(|map| provider.map_entries(map, locale));
+                format_field_value(
+                    value,
+                    field.and_then(|field| field.out_type.as_deref()),
+                    map,
+                )
+            })
+            .collect();
+
+        Some(expand_message(template, &insertions, |id| {
+            provider.parameter(id, locale).map(str::to_owned)
+        }))
+    }
+}
+
+fn localized<'a>(
+    manifest: &'a InstrumentationManifest,
+    reference: &'a Option<String>,
+    culture: &str,
+) -> Option<&'a str> {
+    reference
+        .as_deref()
+        .and_then(|reference| manifest.localized_string(reference, culture))
+}
+
+fn table_locale(table: &MessageTable) -> String {
+    locale_name(table.language as u32)
+        .map(str::to_owned)
+        .unwrap_or_else(|| format!("0x{:04x}", table.language))
+}
+
+/// Catalog used by `render_message` for events of providers that are not installed.
+static MESSAGE_CATALOG: RwLock<Option<Arc<MessageCatalog>>> = RwLock::new(None);
+
+/// Set or remove the catalog used to format events of providers that are not installed.
+pub fn set_message_catalog(catalog: Option<MessageCatalog>) {
+    let mut current = MESSAGE_CATALOG
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = catalog.map(Arc::new);
+}
+
+pub fn message_catalog() -> Option<Arc<MessageCatalog>> {
+    MESSAGE_CATALOG
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
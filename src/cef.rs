@@ -0,0 +1,678 @@
+use std::io::{BufWriter, Write};
+
+use crate::ecs::{parse_ip, parse_pid};
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+
+static SECURITY_PROVIDER: &str = "Microsoft-Windows-Security-Auditing";
+static SYSMON_PROVIDER: &str = "Microsoft-Windows-Sysmon";
+static EVENTLOG_PROVIDER: &str = "Microsoft-Windows-Eventlog";
+
+/// Field of an event with a standard key in CEF or LEEF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    SourceUserName,
+    SourceUserId,
+    SourceDomain,
+    SourceAddress,
+    SourcePort,
+    SourceHost,
+    SourceProcessName,
+    SourceProcessId,
+    DestinationUserName,
+    DestinationUserId,
+    DestinationDomain,
+    DestinationAddress,
+    DestinationPort,
+    DestinationHost,
+    DestinationProcessName,
+    DestinationProcessId,
+    Protocol,
+    FilePath,
+    FileHash,
+    CommandLine,
+    LogonType,
+}
+
+impl Field {
+    /// CEF key, and the label key and label of custom fields like `cs1`.
+    fn cef_key(&self) -> (&'static str, Option<(&'static str, &'static str)>) {
+        match self {
+            Field::SourceUserName => ("suser", None),
+            Field::SourceUserId => ("suid", None),
+            Field::SourceDomain => ("sntdom", None),
+            Field::SourceAddress => ("src", None),
+            Field::SourcePort => ("spt", None),
+            Field::SourceHost => ("shost", None),
+            Field::SourceProcessName => ("sproc", None),
+            Field::SourceProcessId => ("spid", None),
+            Field::DestinationUserName => ("duser", None),
+            Field::DestinationUserId => ("duid", None),
+            Field::DestinationDomain => ("dntdom", None),
+            Field::DestinationAddress => ("dst", None),
+            Field::DestinationPort => ("dpt", None),
+            Field::DestinationHost => ("dhost", None),
+            Field::DestinationProcessName => ("dproc", None),
+            Field::DestinationProcessId => ("dpid", None),
+            Field::Protocol => ("proto", None),
+            Field::FilePath => ("filePath", None),
+            Field::FileHash => ("fileHash", None),
+            Field::CommandLine => ("cs1", Some(("cs1Label", "CommandLine"))),
+            Field::LogonType => ("cn1", Some(("cn1Label", "LogonType"))),
+        }
+    }
+
+    /// LEEF key, predefined where LEEF has an attribute for the field.
+    fn leef_key(&self) -> &'static str {
+        match self {
+            Field::SourceUserName => "accountName",
+            Field::SourceUserId => "srcUserId",
+            Field::SourceDomain => "srcDomain",
+            Field::SourceAddress => "src",
+            Field::SourcePort => "srcPort",
+            Field::SourceHost => "srcHostName",
+            Field::SourceProcessName => "srcProcessName",
+            Field::SourceProcessId => "srcProcessId",
+            Field::DestinationUserName => "usrName",
+            Field::DestinationUserId => "usrId",
+            Field::DestinationDomain => "domain",
+            Field::DestinationAddress => "dst",
+            Field::DestinationPort => "dstPort",
+            Field::DestinationHost => "dstHostName",
+            Field::DestinationProcessName => "processName",
+            Field::DestinationProcessId => "processId",
+            Field::Protocol => "proto",
+            Field::FilePath => "filePath",
+            Field::FileHash => "fileHash",
+            Field::CommandLine => "commandLine",
+            Field::LogonType => "logonType",
+        }
+    }
+
+    /// Normalize a value of the event data. Returns `None` for values that are empty or invalid for the field, e.g. an
+    /// address of `-`.
+    fn normalize(&self, value: &str) -> Option<String> {
+        if value.is_empty() || value == "-" {
+            return None;
+        }
+
+        match self {
+            Field::SourceAddress | Field::DestinationAddress => {
+                parse_ip(value).map(|address| address.to_string())
+            }
+            Field::SourcePort | Field::DestinationPort => value
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .map(|port| port.to_string()),
+            Field::SourceProcessId | Field::DestinationProcessId => {
+                parse_pid(value).map(|pid| pid.to_string())
+            }
+            _ => Some(value.to_owned()),
+        }
+    }
+}
+
+type FieldMap = &'static [(&'static str, Field)];
+
+static SUBJECT_FIELDS: FieldMap = &[
+    ("SubjectUserName", Field::SourceUserName),
+    ("SubjectUserSid", Field::SourceUserId),
+    ("SubjectDomainName", Field::SourceDomain),
+];
+
+static TARGET_FIELDS: FieldMap = &[
+    ("TargetUserName", Field::DestinationUserName),
+    ("TargetUserSid", Field::DestinationUserId),
+    ("TargetSid", Field::DestinationUserId),
+    ("TargetDomainName", Field::DestinationDomain),
+];
+
+static LOGON_FIELDS: FieldMap = &[
+    ("IpAddress", Field::SourceAddress),
+    ("IpPort", Field::SourcePort),
+    ("WorkstationName", Field::SourceHost),
+    ("Workstation", Field::SourceHost),
+    ("LogonType", Field::LogonType),
+    ("ProcessName", Field::SourceProcessName),
+    ("ProcessId", Field::SourceProcessId),
+];
+
+static SECURITY_PROCESS_CREATION_FIELDS: FieldMap = &[
+    ("NewProcessName", Field::DestinationProcessName),
+    ("NewProcessId", Field::DestinationProcessId),
+    ("CommandLine", Field::CommandLine),
+    ("ParentProcessName", Field::SourceProcessName),
+    ("ProcessId", Field::SourceProcessId),
+];
+
+static SECURITY_PROCESS_EXIT_FIELDS: FieldMap = &[
+    ("ProcessName", Field::DestinationProcessName),
+    ("ProcessId", Field::DestinationProcessId),
+];
+
+static ACCOUNT_FIELDS: FieldMap = &[("CallerComputerName", Field::SourceHost)];
+
+static SYSMON_PROCESS_FIELDS: FieldMap = &[
+    ("Image", Field::DestinationProcessName),
+    ("ProcessId", Field::DestinationProcessId),
+    ("CommandLine", Field::CommandLine),
+    ("ParentImage", Field::SourceProcessName),
+    ("ParentProcessId", Field::SourceProcessId),
+    ("User", Field::DestinationUserName),
+    ("Hashes", Field::FileHash),
+];
+
+static SYSMON_NETWORK_FIELDS: FieldMap = &[
+    ("Image", Field::SourceProcessName),
+    ("ProcessId", Field::SourceProcessId),
+    ("User", Field::SourceUserName),
+    ("Protocol", Field::Protocol),
+    ("SourceIp", Field::SourceAddress),
+    ("SourceHostname", Field::SourceHost),
+    ("SourcePort", Field::SourcePort),
+    ("DestinationIp", Field::DestinationAddress),
+    ("DestinationHostname", Field::DestinationHost),
+    ("DestinationPort", Field::DestinationPort),
+];
+
+static SYSMON_FILE_FIELDS: FieldMap = &[
+    ("Image", Field::SourceProcessName),
+    ("ProcessId", Field::SourceProcessId),
+    ("User", Field::SourceUserName),
+    ("TargetFilename", Field::FilePath),
+];
+
+static SYSMON_DNS_FIELDS: FieldMap = &[
+    ("Image", Field::SourceProcessName),
+    ("ProcessId", Field::SourceProcessId),
+    ("User", Field::SourceUserName),
+    ("QueryName", Field::DestinationHost),
+];
+
+/// Name and event data fields of a well-known event.
+struct EventMapping {
+    provider: &'static str,
+    event_id: u16,
+    name: &'static str,
+    fields: &'static [FieldMap],
+}
+
+static EVENT_MAPPINGS: &[EventMapping] = &[
+    EventMapping {
+        provider: EVENTLOG_PROVIDER,
+        event_id: 1102,
+        name: "The audit log was cleared",
+        fields: &[SUBJECT_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4624,
+        name: "An account was successfully logged on",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4625,
+        name: "An account failed to log on",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4634,
+        name: "An account was logged off",
+        fields: &[TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4647,
+        name: "User initiated logoff",
+        fields: &[TARGET_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4648,
+        name: "A logon was attempted using explicit credentials",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4672,
+        name: "Special privileges assigned to new logon",
+        fields: &[SUBJECT_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4688,
+        name: "A new process has been created",
+        fields: &[
+            SUBJECT_FIELDS,
+            TARGET_FIELDS,
+            SECURITY_PROCESS_CREATION_FIELDS,
+        ],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4689,
+        name: "A process has exited",
+        fields: &[SUBJECT_FIELDS, SECURITY_PROCESS_EXIT_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4720,
+        name: "A user account was created",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4726,
+        name: "A user account was deleted",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4740,
+        name: "A user account was locked out",
+        fields: &[SUBJECT_FIELDS, TARGET_FIELDS, ACCOUNT_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4768,
+        name: "A Kerberos authentication ticket (TGT) was requested",
+        fields: &[TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4769,
+        name: "A Kerberos service ticket was requested",
+        fields: &[TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SECURITY_PROVIDER,
+        event_id: 4776,
+        name: "The computer attempted to validate the credentials for an account",
+        fields: &[TARGET_FIELDS, LOGON_FIELDS],
+    },
+    EventMapping {
+        provider: SYSMON_PROVIDER,
+        event_id: 1,
+        name: "Process Create",
+        fields: &[SYSMON_PROCESS_FIELDS],
+    },
+    EventMapping {
+        provider: SYSMON_PROVIDER,
+        event_id: 3,
+        name: "Network connection detected",
+        fields: &[SYSMON_NETWORK_FIELDS],
+    },
+    EventMapping {
+        provider: SYSMON_PROVIDER,
+        event_id: 5,
+        name: "Process terminated",
+        fields: &[SYSMON_PROCESS_FIELDS],
+    },
+    EventMapping {
+        provider: SYSMON_PROVIDER,
+        event_id: 11,
+        name: "File created",
+        fields: &[SYSMON_FILE_FIELDS],
+    },
+    EventMapping {
+        provider: SYSMON_PROVIDER,
+        event_id: 22,
+        name: "DNS query",
+        fields: &[SYSMON_DNS_FIELDS],
+    },
+];
+
+fn event_mapping(record: &EventRecord) -> Option<&'static EventMapping> {
+    EVENT_MAPPINGS.iter().find(|mapping| {
+        mapping.event_id == record.event_id && mapping.provider == record.provider_name
+    })
+}
+
+/// Mapped fields of a record, in the order of the mapping. Each field is used once, with the first value found.
+fn mapped_fields(record: &EventRecord, mapping: &EventMapping) -> Vec<(Field, String)> {
+    let mut fields: Vec<(Field, String)> = Vec::new();
+
+    for (name, field) in mapping.fields.iter().flat_map(|fields| fields.iter()) {
+        if fields.iter().any(|(mapped, _)| mapped == field) {
+            continue;
+        }
+        if let Some(value) = record
+            .event_data
+            .get(name)
+            .and_then(|value| field.normalize(&value.to_string()))
+        {
+            fields.push((*field, value));
+        }
+    }
+
+    fields
+}
+
+/// Name of an event for the CEF header: the name of a well-known event, the first line of the message, or the
+/// provider and event ID.
+fn event_name(record: &EventRecord, mapping: Option<&EventMapping>) -> String {
+    if let Some(mapping) = mapping {
+        return mapping.name.to_owned();
+    }
+
+    record
+        .message
+        .as_deref()
+        .and_then(|message| message.lines().map(str::trim).find(|line| !line.is_empty()))
+        .map(|line| line.trim_end_matches('.').chars().take(512).collect())
+        .unwrap_or_else(|| format!("{} event {}", record.provider_name, record.event_id))
+}
+
+/// CEF severity from 0 to 10 of an event level: critical (10), error (8), warning (5), informational (3) or
+/// verbose (1).
+///
+/// Level 0 (log always), used by the Security channel for all audit events, is informational.
+pub fn cef_severity(level: Option<u8>) -> u8 {
+    match level {
+        Some(1) => 10,
+        Some(2) => 8,
+        Some(3) => 5,
+        None | Some(0) | Some(4) => 3,
+        Some(_) => 1,
+    }
+}
+
+/// Settings of the CEF formatter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CefOptions {
+    /// Device vendor, product and version of the header.
+    pub vendor: String,
+    pub product: String,
+    pub version: String,
+    /// Parts of the event to include. The raw XML is never included.
+    pub fields: RecordFields,
+}
+
+impl CefOptions {
+    pub fn new(vendor: &str, product: &str, version: &str) -> Self {
+        Self {
+            vendor: vendor.to_owned(),
+            product: product.to_owned(),
+            version: version.to_owned(),
+            fields: RecordFields::default(),
+        }
+    }
+}
+
+/// Settings of the LEEF formatter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeefOptions {
+    /// Vendor, product and version of the header.
+    pub vendor: String,
+    pub product: String,
+    pub version: String,
+    /// Delimiter between the attributes, a tab by default.
+    pub delimiter: char,
+    /// Parts of the event to include. The raw XML is never included.
+    pub fields: RecordFields,
+}
+
+impl LeefOptions {
+    pub fn new(vendor: &str, product: &str, version: &str) -> Self {
+        Self {
+            vendor: vendor.to_owned(),
+            product: product.to_owned(),
+            version: version.to_owned(),
+            delimiter: '\t',
+            fields: RecordFields::default(),
+        }
+    }
+}
+
+/// Format a record as ArcSight CEF message.
+///
+/// The signature ID is the event ID. The extension contains the time (`rt`), computer (`dvchost`), process ID
+/// (`dvcpid`), record ID (`externalId`), channel (`cat`) and message (`msg`). The event data of the well-known Security
+/// and Sysmon events is mapped to the CEF keys, e.g. `suser`, `src` or `dproc`; other event data has no CEF key and is
+/// not included.
+pub fn format_cef(record: &EventRecord, options: &CefOptions) -> String {
+    let mapping = event_mapping(record);
+
+    let mut message = String::from("CEF:0");
+    for field in [
+        options.vendor.as_str(),
+        options.product.as_str(),
+        options.version.as_str(),
+        &record.event_id.to_string(),
+        &event_name(record, mapping),
+        &cef_severity(record.level).to_string(),
+    ] {
+        message.push('|');
+        push_header_field(&mut message, field);
+    }
+    message.push('|');
+
+    let mut extension = Vec::new();
+    if options.fields.system {
+        extension.push(("rt", record.time_created.timestamp_millis().to_string()));
+        if let Some(computer) = &record.computer {
+            extension.push(("dvchost", computer.clone()));
+        }
+        if let Some(process_id) = record.process_id {
+            extension.push(("dvcpid", process_id.to_string()));
+        }
+        extension.push(("externalId", record.event_record_id.to_string()));
+        if let Some(channel) = &record.channel {
+            extension.push(("cat", channel.clone()));
+        }
+    }
+    if options.fields.event_data {
+        for (field, value) in mapping
+            .map(|mapping| mapped_fields(record, mapping))
+            .unwrap_or_default()
+        {
+            let (key, label) = field.cef_key();
+            if let Some((label_key, label)) = label {
+                extension.push((label_key, label.to_owned()));
+            }
+            extension.push((key, value));
+        }
+    }
+    if options.fields.message {
+        if let Some(text) = record.message.as_deref().filter(|text| !text.is_empty()) {
+            extension.push(("msg", text.to_owned()));
+        }
+    }
+
+    for (index, (key, value)) in extension.iter().enumerate() {
+        if index > 0 {
+            message.push(' ');
+        }
+        message.push_str(key);
+        message.push('=');
+        for c in value.chars() {
+            match c {
+                '\\' => message.push_str("\\\\"),
+                '=' => message.push_str("\\="),
+                '\n' => message.push_str("\\n"),
+                '\r' => message.push_str("\\r"),
+                c => message.push(c),
+            }
+        }
+    }
+
+    message
+}
+
+/// Format a record as IBM QRadar LEEF 2.0 message.
+///
+/// The header contains the event ID and the delimiter. The attributes are the time (`devTime`), severity (`sev`),
+/// channel (`cat`), computer (`identHostName`) and message, followed by the event data: the fields of the well-known
+/// Security and Sysmon events with the LEEF keys, e.g. `usrName` or `src`, and all other event data with its own name.
+pub fn format_leef(record: &EventRecord, options: &LeefOptions) -> String {
+    let delimiter = options.delimiter;
+
+    let mut message = String::from("LEEF:2.0");
+    for field in [
+        options.vendor.as_str(),
+        options.product.as_str(),
+        options.version.as_str(),
+        &record.event_id.to_string(),
+    ] {
+        message.push('|');
+        push_header_field(&mut message, field);
+    }
+    message.push('|');
+    if delimiter.is_ascii_graphic() && delimiter != '|' {
+        message.push(delimiter);
+    } else {
+        message.push_str(&format!("x{:02X}", delimiter as u32));
+    }
+    message.push('|');
+
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    if options.fields.system {
+        attributes.push((
+            "devTime".to_owned(),
+            record
+                .time_created
+                .format("%b %d %Y %H:%M:%S%.3f UTC")
+                .to_string(),
+        ));
+        attributes.push((
+            "devTimeFormat".to_owned(),
+            "MMM dd yyyy HH:mm:ss.SSS z".to_owned(),
+        ));
+        attributes.push((
+            "sev".to_owned(),
+            cef_severity(record.level).max(1).to_string(),
+        ));
+        if let Some(channel) = &record.channel {
+            attributes.push(("cat".to_owned(), channel.clone()));
+        }
+        if let Some(computer) = &record.computer {
+            attributes.push(("identHostName".to_owned(), computer.clone()));
+        }
+        attributes.push(("provider".to_owned(), record.provider_name.clone()));
+        attributes.push(("recordId".to_owned(), record.event_record_id.to_string()));
+    }
+    if options.fields.event_data {
+        let mapping = event_mapping(record);
+        let mapped = mapping
+            .map(|mapping| mapped_fields(record, mapping))
+            .unwrap_or_default();
+        for (field, value) in &mapped {
+            attributes.push((field.leef_key().to_owned(), value.clone()));
+        }
+
+        for (name, value) in record.event_data.iter() {
+            let key: String = name.chars().filter(char::is_ascii_alphanumeric).collect();
+            let is_mapped = mapping.is_some_and(|mapping| {
+                mapping
+                    .fields
+                    .iter()
+                    .flat_map(|fields| fields.iter())
+                    .any(|(mapped_name, _)| *mapped_name == name)
+            });
+            if key.is_empty() || is_mapped || attributes.iter().any(|(used, _)| *used == key) {
+                continue;
+            }
+            attributes.push((key, value.to_string()));
+        }
+    }
+    if options.fields.message {
+        if let Some(text) = record.message.as_deref().filter(|text| !text.is_empty()) {
+            attributes.push(("msg".to_owned(), text.to_owned()));
+        }
+    }
+
+    for (index, (key, value)) in attributes.iter().enumerate() {
+        if index > 0 {
+            message.push(delimiter);
+        }
+        message.push_str(key);
+        message.push('=');
+        for c in value.chars() {
+            match c {
+                '\\' => message.push_str("\\\\"),
+                '\r' | '\n' => message.push(' '),
+                c if c == delimiter => {
+                    message.push('\\');
+                    message.push(c);
+                }
+                c => message.push(c),
+            }
+        }
+    }
+
+    message
+}
+
+/// Append a header field with `\` and `|` escaped and line breaks replaced by spaces.
+fn push_header_field(message: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => message.push_str("\\\\"),
+            '|' => message.push_str("\\|"),
+            '\r' | '\n' => message.push(' '),
+            c => message.push(c),
+        }
+    }
+}
+
+/// Message format of a `SiemSink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SiemFormat {
+    Cef(CefOptions),
+    Leef(LeefOptions),
+}
+
+impl SiemFormat {
+    pub fn format(&self, record: &EventRecord) -> String {
+        match self {
+            SiemFormat::Cef(options) => format_cef(record, options),
+            SiemFormat::Leef(options) => format_leef(record, options),
+        }
+    }
+}
+
+/// Sink writing one CEF or LEEF message per line, e.g. to a file collected by a SIEM connector.
+pub struct SiemSink<W: Write> {
+    writer: BufWriter<W>,
+    format: SiemFormat,
+}
+
+impl<W: Write> SiemSink<W> {
+    pub fn new(writer: W, format: SiemFormat) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            format,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flush the buffer and return the underlying writer.
+    pub fn into_inner(self) -> Result<W, String> {
+        self.writer
+            .into_inner()
+            .map_err(|error| format!("Failed to flush SIEM output: {}", error.error()))
+    }
+}
+
+impl<W: Write> EventSink for SiemSink<W> {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let mut line = self.format.format(record);
+        line.push('\n');
+
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|error| format!("Failed to write SIEM message: {}", error))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|error| format!("Failed to flush SIEM output: {}", error))
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use windows_sys::Win32::System::EventLog::*;
+
+use crate::backend::EventLogBackend;
+
+/// Type of an event log channel.
+///
+/// See https://learn.microsoft.com/en-us/windows/win32/api/winevt/ne-winevt-evt_channel_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelType {
+    Admin,
+    Operational,
+    Analytic,
+    Debug,
+}
+
+impl ChannelType {
+    pub fn from_raw(value: u32) -> Option<Self> {
+        #![allow(nonstandard_style)]
+        match value as EVT_CHANNEL_TYPE {
+            EvtChannelTypeAdmin => Some(ChannelType::Admin),
+            EvtChannelTypeOperational => Some(ChannelType::Operational),
+            EvtChannelTypeAnalytic => Some(ChannelType::Analytic),
+            EvtChannelTypeDebug => Some(ChannelType::Debug),
+            _ => None,
+        }
+    }
+
+    /// Analytic and debug channels are disabled by default and have to be enabled explicitly.
+    pub fn is_direct(&self) -> bool {
+        matches!(self, ChannelType::Analytic | ChannelType::Debug)
+    }
+}
+
+/// What happens when a channel's log file reaches its maximum size.
+///
+/// Combines the `Retention` and `AutoBackup` logging properties of a channel the same way Event Viewer does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRetention {
+    /// Overwrite the oldest events as needed.
+    Overwrite,
+    /// Keep all events and stop logging once the log is full.
+    Retain,
+    /// Archive the log file once it is full and start a new one.
+    AutoBackup,
+}
+
+impl LogRetention {
+    pub fn from_flags(retention: bool, auto_backup: bool) -> Self {
+        match (retention, auto_backup) {
+            (_, true) => LogRetention::AutoBackup,
+            (true, false) => LogRetention::Retain,
+            (false, false) => LogRetention::Overwrite,
+        }
+    }
+
+    /// Get the values of the `Retention` and `AutoBackup` properties, in that order.
+    pub fn as_flags(&self) -> (bool, bool) {
+        match self {
+            LogRetention::Overwrite => (false, false),
+            LogRetention::Retain => (true, false),
+            LogRetention::AutoBackup => (true, true),
+        }
+    }
+}
+
+/// Configuration of a channel, as returned by `EvtGetChannelConfigProperty`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelConfig {
+    pub enabled: bool,
+    pub channel_type: Option<ChannelType>,
+    pub classic: bool,
+    pub owning_publisher: Option<String>,
+    pub max_size: u64,
+    pub retention: LogRetention,
+    pub log_file_path: Option<String>,
+}
+
+/// Information about the log file of a channel, as returned by `EvtGetLogInfo`.
+///
+/// Properties the service does not report for a log (e.g. for a log that has never been written to) are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogInfo {
+    pub creation_time: Option<DateTime<Utc>>,
+    pub last_access_time: Option<DateTime<Utc>>,
+    pub last_write_time: Option<DateTime<Utc>>,
+    pub file_size: Option<u64>,
+    pub attributes: Option<u32>,
+    pub record_count: Option<u64>,
+    pub oldest_record_number: Option<u64>,
+    pub full: Option<bool>,
+}
+
+/// Configuration and log information of a single channel.
+#[derive(Debug, Clone)]
+pub struct ChannelSummary {
+    pub path: String,
+    pub config: ChannelConfig,
+    /// Log information can not be retrieved for every channel, e.g. enabled analytic and debug channels can not be
+    /// opened while they are being written to.
+    pub log_info: Result<LogInfo, String>,
+}
+
+/// Collect the configuration and log information of a channel.
+pub fn describe_channel<B: EventLogBackend + ?Sized>(
+    backend: &B,
+    path: &str,
+) -> Result<ChannelSummary, String> {
+    Ok(ChannelSummary {
+        path: path.to_owned(),
+        config: backend.channel_config(path)?,
+        log_info: backend.log_info(path),
+    })
+}
+
+/// Collect the configuration and log information of all channels known to the backend.
+///
+/// Fails only if the channels can not be enumerated. Errors of individual channels are returned in place of their
+/// summary.
+pub fn describe_channels<B: EventLogBackend + ?Sized>(
+    backend: &B,
+) -> Result<Vec<Result<ChannelSummary, String>>, String> {
+    Ok(backend
+        .channel_paths()?
+        .iter()
+        .map(|path| describe_channel(backend, path))
+        .collect())
+}
+
+/// A single writable channel setting, holding its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSetting {
+    Enabled(bool),
+    MaxSize(u64),
+    Retention(LogRetention),
+}
+
+impl ChannelSetting {
+    fn name(&self) -> &'static str {
+        match self {
+            ChannelSetting::Enabled(_) => "Enabled",
+            ChannelSetting::MaxSize(_) => "MaxSize",
+            ChannelSetting::Retention(_) => "Retention",
+        }
+    }
+}
+
+impl fmt::Display for ChannelSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelSetting::Enabled(value) => write!(f, "{}", value),
+            ChannelSetting::MaxSize(value) => write!(f, "{} bytes", value),
+            ChannelSetting::Retention(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// Change of a single channel setting, from the current to the requested value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelSettingChange {
+    pub from: ChannelSetting,
+    pub to: ChannelSetting,
+}
+
+impl fmt::Display for ChannelSettingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.to.name(), self.from, self.to)
+    }
+}
+
+/// Requested configuration of a channel. Settings that are `None` are left as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelConfigChange {
+    pub enabled: Option<bool>,
+    pub max_size: Option<u64>,
+    pub retention: Option<LogRetention>,
+}
+
+/// Settings that have to be written to bring a channel into the requested configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelConfigPlan {
+    pub channel: String,
+    /// Changes in the order they have to be applied.
+    pub changes: Vec<ChannelSettingChange>,
+}
+
+impl ChannelConfigPlan {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for ChannelConfigPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "{}: no changes", self.channel);
+        }
+
+        write!(f, "{}:", self.channel)?;
+        for change in &self.changes {
+            write!(f, "\n  {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl ChannelConfigChange {
+    /// Compare the requested with the current configuration of a channel.
+    ///
+    /// The logging settings of analytic and debug channels can only be changed while the channel is disabled, so a
+    /// channel that gets disabled is disabled first, and a channel that gets enabled is enabled last. An enabled
+    /// analytic or debug channel whose logging settings change is disabled first and enabled again afterwards, even
+    /// if the enabled state is not part of the change.
+    pub fn plan(&self, channel: &str, current: &ChannelConfig) -> ChannelConfigPlan {
+        let mut settings = Vec::new();
+
+        if let Some(max_size) = self.max_size.filter(|size| *size != current.max_size) {
+            settings.push(ChannelSettingChange {
+                from: ChannelSetting::MaxSize(current.max_size),
+                to: ChannelSetting::MaxSize(max_size),
+            });
+        }
+
+        if let Some(retention) = self.retention.filter(|r| *r != current.retention) {
+            settings.push(ChannelSettingChange {
+                from: ChannelSetting::Retention(current.retention),
+                to: ChannelSetting::Retention(retention),
+            });
+        }
+
+        let enabled = self.enabled.unwrap_or(current.enabled);
+        let direct = current
+            .channel_type
+            .is_some_and(|channel_type| channel_type.is_direct());
+        let disable = current.enabled && (!enabled || (direct && !settings.is_empty()));
+        let enable = enabled && (!current.enabled || disable);
+
+        let mut changes = Vec::new();
+        if disable {
+            changes.push(ChannelSettingChange {
+                from: ChannelSetting::Enabled(true),
+                to: ChannelSetting::Enabled(false),
+            });
+        }
+        changes.extend(settings);
+        if enable {
+            changes.push(ChannelSettingChange {
+                from: ChannelSetting::Enabled(false),
+                to: ChannelSetting::Enabled(true),
+            });
+        }
+
+        ChannelConfigPlan {
+            channel: channel.to_owned(),
+            changes,
+        }
+    }
+}
+
+/// Bring a channel into the requested configuration.
+///
+/// Returns the plan of the changes. If `dry_run` is set, the plan is only computed and nothing is written.
+///
+/// Changing the enabled state is saved on its own: the service only accepts the logging settings of analytic and
+/// debug channels once the disabled state was saved, so the configuration is saved after disabling, after the other
+/// settings, and again after enabling.
+pub fn apply_channel_config<B: EventLogBackend + ?Sized>(
+    backend: &B,
+    channel: &str,
+    change: &ChannelConfigChange,
+    dry_run: bool,
+) -> Result<ChannelConfigPlan, String> {
+    let current = backend.channel_config(channel)?;
+    let plan = change.plan(channel, &current);
+
+    if !dry_run {
+        let mut settings = Vec::new();
+        for change in &plan.changes {
+            if let ChannelSetting::Enabled(_) = change.to {
+                if !settings.is_empty() {
+                    backend.set_channel_config(channel, &settings)?;
+                    settings.clear();
+                }
+                backend.set_channel_config(channel, &[change.to])?;
+            } else {
+                settings.push(change.to);
+            }
+        }
+        if !settings.is_empty() {
+            backend.set_channel_config(channel, &settings)?;
+        }
+    }
+
+    Ok(plan)
+}
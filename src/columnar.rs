@@ -0,0 +1,668 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::builder::{MapBuilder, StringBuilder};
+use arrow_array::types::*;
+use arrow_array::{
+    Array, ArrayRef, ArrowPrimitiveType, BooleanArray, FixedSizeBinaryArray, PrimitiveArray,
+    RecordBatch, StringArray, StructArray, TimestampNanosecondArray,
+};
+use arrow_schema::{DataType, Field, Fields, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+
+use crate::ndjson::variant_to_json;
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+use crate::variant::EventVariantValue;
+
+/// How the event data is stored in a record batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventDataLayout {
+    /// A map from the names to the values as strings. Works for any mix of events.
+    #[default]
+    Map,
+    /// A struct with a typed field per name, inferred from the events of the batch. Names with values of different
+    /// types are strings.
+    Struct,
+}
+
+/// Settings of an `EventBatchBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColumnarOptions {
+    /// Parts of the event to include. The system properties are always included.
+    pub fields: RecordFields,
+    pub event_data: EventDataLayout,
+}
+
+/// Event data value converted for a column.
+#[derive(Debug, Clone, PartialEq)]
+enum Cell {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Text(String),
+}
+
+impl Cell {
+    /// Convert a value and determine its column type. Null values have no cell.
+    fn from_variant(value: &EventVariantValue) -> Option<(Cell, DataType)> {
+        Some(match value {
+            EventVariantValue::Null => return None,
+            EventVariantValue::Bool(value) => (Cell::Bool(*value), DataType::Boolean),
+            EventVariantValue::SByte(value) => (Cell::Int(*value as i64), DataType::Int8),
+            EventVariantValue::Int16(value) => (Cell::Int(*value as i64), DataType::Int16),
+            EventVariantValue::Int32(value) => (Cell::Int(*value as i64), DataType::Int32),
+            EventVariantValue::Int64(value) => (Cell::Int(*value), DataType::Int64),
+            EventVariantValue::Byte(value) => (Cell::UInt(*value as u64), DataType::UInt8),
+            EventVariantValue::UInt16(value) => (Cell::UInt(*value as u64), DataType::UInt16),
+            EventVariantValue::UInt32(value) | EventVariantValue::HexInt32(value) => {
+                (Cell::UInt(*value as u64), DataType::UInt32)
+            }
+            EventVariantValue::UInt64(value) | EventVariantValue::HexInt64(value) => {
+                (Cell::UInt(*value), DataType::UInt64)
+            }
+            EventVariantValue::SizeT(value) => (Cell::UInt(*value as u64), DataType::UInt64),
+            EventVariantValue::Single(value) => (Cell::Float(*value as f64), DataType::Float32),
+            EventVariantValue::Double(value) => (Cell::Float(*value), DataType::Float64),
+            value => {
+                let text = match variant_to_json(value) {
+                    Value::String(text) => text,
+                    Value::Null => return None,
+                    // Arrays as JSON.
+                    json => json.to_string(),
+                };
+                (Cell::Text(text), DataType::Utf8)
+            }
+        })
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Cell::Int(value) => Some(*value),
+            Cell::UInt(value) => i64::try_from(*value).ok(),
+            Cell::Text(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Cell::Int(value) => u64::try_from(*value).ok(),
+            Cell::UInt(value) => Some(*value),
+            Cell::Text(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Cell::Int(value) => Some(*value as f64),
+            Cell::UInt(value) => Some(*value as f64),
+            Cell::Float(value) => Some(*value),
+            Cell::Text(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Cell::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Cell::Bool(value) => value.to_string(),
+            Cell::Int(value) => value.to_string(),
+            Cell::UInt(value) => value.to_string(),
+            Cell::Float(value) => value.to_string(),
+            Cell::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// Event buffered by an `EventBatchBuilder`.
+struct Row {
+    provider_name: String,
+    provider_guid: Option<[u8; 16]>,
+    event_id: u16,
+    qualifiers: Option<u16>,
+    version: Option<u8>,
+    level: Option<u8>,
+    task: Option<u16>,
+    opcode: Option<u8>,
+    keywords: Option<u64>,
+    time_created: Option<i64>,
+    event_record_id: u64,
+    activity_id: Option<[u8; 16]>,
+    related_activity_id: Option<[u8; 16]>,
+    process_id: Option<u32>,
+    thread_id: Option<u32>,
+    channel: Option<String>,
+    computer: Option<String>,
+    user_id: Option<String>,
+    event_data: Vec<(String, Cell, DataType)>,
+    message: Option<String>,
+    xml: Option<String>,
+}
+
+/// Parse a GUID in registry format, e.g. `{54849625-5478-4994-a5ba-3e3b0328c30d}`, into its 16 bytes in the order
+/// of the text (as in RFC 4122).
+pub fn guid_bytes(guid: &str) -> Option<[u8; 16]> {
+    let hex: String = guid
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .chars()
+        .filter(|c| *c != '-')
+        .collect();
+    if hex.len() != 32 {
+        return None;
+    }
+
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Builder of Arrow record batches with one row per event.
+///
+/// The system properties are typed columns named like the fields of `EventSystemContext`: integers with their
+/// Windows sizes, `time_created` as nanosecond UTC timestamp, and GUIDs as 16-byte fixed-size binary with the
+/// `arrow.uuid` extension type. The event data is the column `event_data`, see `EventDataLayout`.
+pub struct EventBatchBuilder {
+    options: ColumnarOptions,
+    fixed_schema: bool,
+    event_data_fields: Option<Vec<(String, DataType)>>,
+    rows: Vec<Row>,
+}
+
+impl EventBatchBuilder {
+    pub fn new(options: ColumnarOptions) -> Self {
+        Self {
+            options,
+            fixed_schema: false,
+            event_data_fields: None,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Keep the event data struct inferred for the first batch for all later batches, so all batches have the same
+    /// schema, e.g. to write them into one file. Names missing from the first batch are then dropped, unless the
+    /// schema is reset when the buffered events do not `fit_schema`.
+    pub fn fixed_schema(mut self) -> Self {
+        self.fixed_schema = true;
+        self
+    }
+
+    /// Forget the event data struct kept by `fixed_schema`, so the next batch is inferred anew.
+    pub fn reset_schema(&mut self) {
+        self.event_data_fields = None;
+    }
+
+    /// Whether the event data of the buffered events fits into the struct kept by `fixed_schema`: every name is a
+    /// field of the struct, with the same type or as string. Always true for the map layout and before the first batch.
+    pub fn fit_schema(&self) -> bool {
+        let Some(fields) = &self.event_data_fields else {
+            return true;
+        };
+        if self.options.event_data != EventDataLayout::Struct || !self.options.fields.event_data {
+            return true;
+        }
+
+        infer_struct_fields(&self.rows)
+            .iter()
+            .all(|(name, data_type)| {
+                fields.iter().any(|(field, field_type)| {
+                    field == name && (field_type == data_type || *field_type == DataType::Utf8)
+                })
+            })
+    }
+
+    /// Number of buffered events.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn append(&mut self, record: &EventRecord) {
+        let fields = &self.options.fields;
+
+        let mut event_data: Vec<(String, Cell, DataType)> = Vec::new();
+        if fields.event_data {
+            for (name, value) in record.event_data.iter() {
+                if event_data.iter().any(|(used, _, _)| used == name) {
+                    continue;
+                }
+                if let Some((cell, data_type)) = Cell::from_variant(value) {
+                    event_data.push((name.to_owned(), cell, data_type));
+                }
+            }
+        }
+
+        self.rows.push(Row {
+            provider_name: record.provider_name.clone(),
+            provider_guid: record.provider_guid.as_deref().and_then(guid_bytes),
+            event_id: record.event_id,
+            qualifiers: record.qualifiers,
+            version: record.version,
+            level: record.level,
+            task: record.task,
+            opcode: record.opcode,
+            keywords: record.keywords,
+            time_created: record.time_created.timestamp_nanos_opt(),
+            event_record_id: record.event_record_id,
+            activity_id: record.activity_id.as_deref().and_then(guid_bytes),
+            related_activity_id: record.related_activity_id.as_deref().and_then(guid_bytes),
+            process_id: record.process_id,
+            thread_id: record.thread_id,
+            channel: record.channel.clone(),
+            computer: record.computer.clone(),
+            user_id: record.user_id.clone(),
+            event_data,
+            message: record.message.clone().filter(|_| fields.message),
+            xml: record.xml.clone().filter(|_| fields.xml),
+        });
+    }
+
+    /// Build a record batch of the buffered events and clear the buffer.
+    pub fn finish(&mut self) -> Result<RecordBatch, String> {
+        let rows = std::mem::take(&mut self.rows);
+        let mut fields: Vec<Field> = Vec::new();
+        let mut columns: Vec<ArrayRef> = Vec::new();
+        let mut add = |field: Field, column: ArrayRef| {
+            fields.push(field);
+            columns.push(column);
+        };
+
+        fn primitive<T: ArrowPrimitiveType>(
+            rows: &[Row],
+            value: impl Fn(&Row) -> Option<T::Native>,
+        ) -> ArrayRef {
+            Arc::new(rows.iter().map(value).collect::<PrimitiveArray<T>>())
+        }
+        fn strings(rows: &[Row], value: impl Fn(&Row) -> Option<&str>) -> ArrayRef {
+            Arc::new(rows.iter().map(value).collect::<StringArray>())
+        }
+        fn guids(rows: &[Row], value: impl Fn(&Row) -> Option<[u8; 16]>) -> ArrayRef {
+            Arc::new(
+                FixedSizeBinaryArray::try_from_sparse_iter_with_size(rows.iter().map(value), 16)
+                    .unwrap(),
+            )
+        }
+        let guid_field = |name: &str| {
+            Field::new(name, DataType::FixedSizeBinary(16), true).with_metadata(
+                [("ARROW:extension:name".to_owned(), "arrow.uuid".to_owned())].into(),
+            )
+        };
+
+        add(
+            Field::new("provider_name", DataType::Utf8, false),
+            strings(&rows, |row| Some(&row.provider_name)),
+        );
+        add(
+            guid_field("provider_guid"),
+            guids(&rows, |row| row.provider_guid),
+        );
+        add(
+            Field::new("event_id", DataType::UInt16, false),
+            primitive::<UInt16Type>(&rows, |row| Some(row.event_id)),
+        );
+        add(
+            Field::new("qualifiers", DataType::UInt16, true),
+            primitive::<UInt16Type>(&rows, |row| row.qualifiers),
+        );
+        add(
+            Field::new("version", DataType::UInt8, true),
+            primitive::<UInt8Type>(&rows, |row| row.version),
+        );
+        add(
+            Field::new("level", DataType::UInt8, true),
+            primitive::<UInt8Type>(&rows, |row| row.level),
+        );
+        add(
+            Field::new("task", DataType::UInt16, true),
+            primitive::<UInt16Type>(&rows, |row| row.task),
+        );
+        add(
+            Field::new("opcode", DataType::UInt8, true),
+            primitive::<UInt8Type>(&rows, |row| row.opcode),
+        );
+        add(
+            Field::new("keywords", DataType::UInt64, true),
+            primitive::<UInt64Type>(&rows, |row| row.keywords),
+        );
+        let time_created = rows
+            .iter()
+            .map(|row| row.time_created)
+            .collect::<TimestampNanosecondArray>()
+            .with_timezone("UTC");
+        add(
+            Field::new("time_created", time_created.data_type().clone(), true),
+            Arc::new(time_created),
+        );
+        add(
+            Field::new("event_record_id", DataType::UInt64, false),
+            primitive::<UInt64Type>(&rows, |row| Some(row.event_record_id)),
+        );
+        add(
+            guid_field("activity_id"),
+            guids(&rows, |row| row.activity_id),
+        );
+        add(
+            guid_field("related_activity_id"),
+            guids(&rows, |row| row.related_activity_id),
+        );
+        add(
+            Field::new("process_id", DataType::UInt32, true),
+            primitive::<UInt32Type>(&rows, |row| row.process_id),
+        );
+        add(
+            Field::new("thread_id", DataType::UInt32, true),
+            primitive::<UInt32Type>(&rows, |row| row.thread_id),
+        );
+        add(
+            Field::new("channel", DataType::Utf8, true),
+            strings(&rows, |row| row.channel.as_deref()),
+        );
+        add(
+            Field::new("computer", DataType::Utf8, true),
+            strings(&rows, |row| row.computer.as_deref()),
+        );
+        add(
+            Field::new("user_id", DataType::Utf8, true),
+            strings(&rows, |row| row.user_id.as_deref()),
+        );
+
+        if self.options.fields.event_data {
+            match self.options.event_data {
+                EventDataLayout::Map => {
+                    let mut builder =
+                        MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+                    for row in &rows {
+                        for (name, cell, _) in &row.event_data {
+                            builder.keys().append_value(name);
+                            builder.values().append_value(cell.to_text());
+                        }
+                        builder
+                            .append(true)
+                            .map_err(|error| format!("Failed to build event data: {}", error))?;
+                    }
+                    let map = builder.finish();
+                    add(
+                        Field::new("event_data", map.data_type().clone(), false),
+                        Arc::new(map),
+                    );
+                }
+                EventDataLayout::Struct => {
+                    let struct_fields = match &self.event_data_fields {
+                        Some(fields) => fields.clone(),
+                        None => infer_struct_fields(&rows),
+                    };
+                    if self.fixed_schema {
+                        self.event_data_fields = Some(struct_fields.clone());
+                    }
+
+                    // A struct without fields cannot be stored in Parquet.
+                    if !struct_fields.is_empty() {
+                        let mut children: Vec<(Arc<Field>, ArrayRef)> = Vec::new();
+                        for (name, data_type) in &struct_fields {
+                            let cells: Vec<Option<&Cell>> = rows
+                                .iter()
+                                .map(|row| {
+                                    row.event_data
+                                        .iter()
+                                        .find(|(used, _, _)| used == name)
+                                        .map(|(_, cell, _)| cell)
+                                })
+                                .collect();
+                            children.push((
+                                Arc::new(Field::new(name, data_type.clone(), true)),
+                                cell_array(data_type, &cells),
+                            ));
+                        }
+                        let event_data = StructArray::from(children);
+                        add(
+                            Field::new("event_data", event_data.data_type().clone(), false),
+                            Arc::new(event_data),
+                        );
+                    }
+                }
+            }
+        }
+
+        if self.options.fields.message {
+            add(
+                Field::new("message", DataType::Utf8, true),
+                strings(&rows, |row| row.message.as_deref()),
+            );
+        }
+        if self.options.fields.xml {
+            add(
+                Field::new("xml", DataType::Utf8, true),
+                strings(&rows, |row| row.xml.as_deref()),
+            );
+        }
+
+        RecordBatch::try_new(Arc::new(Schema::new(Fields::from(fields))), columns)
+            .map_err(|error| format!("Failed to build record batch: {}", error))
+    }
+}
+
+/// Struct fields of the event data of the rows, in the order the names first appear. Names with values of different
+/// types are strings.
+fn infer_struct_fields(rows: &[Row]) -> Vec<(String, DataType)> {
+    let mut fields: Vec<(String, DataType)> = Vec::new();
+
+    for (name, _, data_type) in rows.iter().flat_map(|row| row.event_data.iter()) {
+        match fields.iter_mut().find(|(used, _)| used == name) {
+            Some((_, used_type)) if used_type != data_type => *used_type = DataType::Utf8,
+            Some(_) => {}
+            None => fields.push((name.clone(), data_type.clone())),
+        }
+    }
+
+    fields
+}
+
+/// Array of a struct field. Cells that cannot be converted to the type are null.
+fn cell_array(data_type: &DataType, cells: &[Option<&Cell>]) -> ArrayRef {
+    fn convert<T: ArrowPrimitiveType>(
+        cells: &[Option<&Cell>],
+        convert: impl Fn(&Cell) -> Option<T::Native>,
+    ) -> ArrayRef {
+        Arc::new(
+            cells
+                .iter()
+                .map(|cell| cell.and_then(&convert))
+                .collect::<PrimitiveArray<T>>(),
+        )
+    }
+
+    match data_type {
+        DataType::Boolean => Arc::new(
+            cells
+                .iter()
+                .map(|cell| cell.and_then(Cell::as_bool))
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Int8 => convert::<Int8Type>(cells, |cell| cell.as_i64()?.try_into().ok()),
+        DataType::Int16 => convert::<Int16Type>(cells, |cell| cell.as_i64()?.try_into().ok()),
+        DataType::Int32 => convert::<Int32Type>(cells, |cell| cell.as_i64()?.try_into().ok()),
+        DataType::Int64 => convert::<Int64Type>(cells, Cell::as_i64),
+        DataType::UInt8 => convert::<UInt8Type>(cells, |cell| cell.as_u64()?.try_into().ok()),
+        DataType::UInt16 => convert::<UInt16Type>(cells, |cell| cell.as_u64()?.try_into().ok()),
+        DataType::UInt32 => convert::<UInt32Type>(cells, |cell| cell.as_u64()?.try_into().ok()),
+        DataType::UInt64 => convert::<UInt64Type>(cells, Cell::as_u64),
+        DataType::Float32 => {
+            convert::<Float32Type>(cells, |cell| cell.as_f64().map(|value| value as f32))
+        }
+        DataType::Float64 => convert::<Float64Type>(cells, Cell::as_f64),
+        _ => Arc::new(
+            cells
+                .iter()
+                .map(|cell| cell.map(Cell::to_text))
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+/// Settings of a `ParquetSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    pub columnar: ColumnarOptions,
+    /// Number of events per row group. Larger row groups compress better but need more memory while writing.
+    pub row_group_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            columnar: ColumnarOptions::default(),
+            row_group_size: 100_000,
+            compression: Compression::SNAPPY,
+        }
+    }
+}
+
+/// Sink writing events into Parquet files in a directory.
+///
+/// Events are buffered and written as a row group of `ParquetOptions::row_group_size` events. As a Parquet file can
+/// only be read after its footer was written, `flush` writes the buffered events as a (smaller) row group and
+/// finishes the file, and the next event starts a new one. Checkpoints should therefore be rare. Files are written as
+/// `<name>.parquet.tmp` and renamed when finished, so readers never see an incomplete file. With
+/// `EventDataLayout::Struct`, the event data struct is inferred from the first row group of each file; a row group
+/// with names or types that do not fit into it also starts a new file.
+pub struct ParquetSink {
+    directory: PathBuf,
+    prefix: String,
+    options: ParquetOptions,
+    builder: EventBatchBuilder,
+    writer: Option<(ArrowWriter<File>, PathBuf)>,
+    sequence: u64,
+    files: Vec<PathBuf>,
+}
+
+impl ParquetSink {
+    /// Create a sink writing `<prefix><sequence>.parquet` files into the directory, e.g. `events-000001.parquet`.
+    /// Existing files are kept, the sequence continues after them. Nothing is written before the first row group.
+    pub fn new(directory: &Path, prefix: &str, options: ParquetOptions) -> Self {
+        Self {
+            directory: directory.to_owned(),
+            prefix: prefix.to_owned(),
+            builder: EventBatchBuilder::new(options.columnar).fixed_schema(),
+            options,
+            writer: None,
+            sequence: 0,
+            files: Vec::new(),
+        }
+    }
+
+    /// Files finished by this sink.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Write the buffered events as a row group, starting a new file if none is open.
+    fn write_row_group(&mut self) -> Result<(), String> {
+        // Rather than dropping event data, continue in a file with a new struct.
+        if !self.builder.fit_schema() {
+            self.finish_file()?;
+            self.builder.reset_schema();
+        }
+        let batch = self.builder.finish()?;
+
+        if self.writer.is_none() {
+            let path = loop {
+                self.sequence += 1;
+                let path = self
+                    .directory
+                    .join(format!("{}{:06}.parquet", self.prefix, self.sequence));
+                if !path.exists() {
+                    break path;
+                }
+            };
+            let mut partial = path.clone().into_os_string();
+            partial.push(".tmp");
+            let file = File::create(&partial).map_err(|error| {
+                format!(
+                    "Failed to create {}: {}",
+                    Path::new(&partial).display(),
+                    error
+                )
+            })?;
+
+            let properties = WriterProperties::builder()
+                .set_max_row_group_size(self.options.row_group_size.max(1))
+                .set_compression(self.options.compression)
+                .build();
+            let writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))
+                .map_err(|error| format!("Failed to write Parquet file: {}", error))?;
+            self.writer = Some((writer, path));
+        }
+
+        let (writer, _) = self.writer.as_mut().unwrap();
+        writer
+            .write(&batch)
+            .and_then(|_| writer.flush())
+            .map_err(|error| format!("Failed to write Parquet file: {}", error))
+    }
+
+    /// Write the footer of the open file, sync it to disk and give it its final name.
+    fn finish_file(&mut self) -> Result<(), String> {
+        let Some((writer, path)) = self.writer.take() else {
+            return Ok(());
+        };
+
+        let file = writer
+            .into_inner()
+            .map_err(|error| format!("Failed to write Parquet file: {}", error))?;
+        let mut partial = path.clone().into_os_string();
+        partial.push(".tmp");
+        file.sync_all()
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .map_err(|error| format!("Failed to finish {}: {}", path.display(), error))?;
+
+        self.files.push(path);
+        Ok(())
+    }
+
+    /// Write the buffered events, finish the file and return all files finished by this sink.
+    pub fn close(mut self) -> Result<Vec<PathBuf>, String> {
+        self.flush()?;
+        Ok(self.files)
+    }
+}
+
+impl EventSink for ParquetSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        self.builder.append(record);
+
+        if self.builder.len() >= self.options.row_group_size {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        if !self.builder.is_empty() {
+            self.write_row_group()?;
+        }
+        self.finish_file()?;
+
+        // The next file is inferred anew.
+        self.builder.reset_schema();
+        Ok(())
+    }
+}
@@ -1,12 +1,11 @@
-use windows_strings::{PCSTR, PCWSTR};
 use windows_sys::{
     core::{PCSTR as PCSTR_SYS, PCWSTR as PCWSTR_SYS},
     Win32::Foundation::{FILETIME, SYSTEMTIME},
-    Win32::System::EventLog::EVT_HANDLE,
 };
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
-use std::time::{Duration, UNIX_EPOCH};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, SecondsFormat, Timelike, Utc};
+use std::fmt;
+use std::time::Duration;
 
 pub trait WindowsConversionFrom<T> {
     fn win_from(value: T) -> Self;
@@ -25,26 +24,174 @@ where
     }
 }
 
+/// Fallible counterpart of `WindowsConversionFrom` for values that are not always valid, e.g. a zeroed `SYSTEMTIME`.
+pub trait TryWindowsConversionFrom<T>: Sized {
+    fn try_win_from(value: T) -> Result<Self, ConversionError>;
+}
+
+pub trait TryWindowsConversionTo<T> {
+    fn try_win_into(self) -> Result<T, ConversionError>;
+}
+
+impl<T, U> TryWindowsConversionTo<U> for T
+where
+    U: TryWindowsConversionFrom<T>,
+{
+    fn try_win_into(self) -> Result<U, ConversionError> {
+        U::try_win_from(self)
+    }
+}
+
+/// Error of a `TryWindowsConversionFrom` conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// A UTF-16 string contains an unpaired surrogate.
+    InvalidUtf16,
+    /// An ANSI string is not valid UTF-8.
+    InvalidUtf8,
+    /// A timestamp in 100 nanosecond intervals since January 1, 1601 is outside the range of `DateTime<Utc>`.
+    TimestampOutOfRange(u64),
+    /// A `SYSTEMTIME` does not denote a valid date and time, formatted as `year-month-day hour:minute:second.millis`.
+    InvalidSystemTime(String),
+    /// A date and time, formatted as RFC 3339, is outside the range of the Windows type.
+    DateTimeOutOfRange(String),
+    /// A string contains a NUL character, so it cannot be passed as a null-terminated string.
+    InteriorNul(usize),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidUtf16 => write!(f, "Invalid UTF-16 string"),
+            ConversionError::InvalidUtf8 => write!(f, "Invalid UTF-8 string"),
+            ConversionError::TimestampOutOfRange(value) => {
+                write!(f, "Timestamp {} is out of range", value)
+            }
+            ConversionError::InvalidSystemTime(value) => write!(f, "Invalid SYSTEMTIME {}", value),
+            ConversionError::DateTimeOutOfRange(value) => {
+                write!(f, "Date and time {} is out of range", value)
+            }
+            ConversionError::InteriorNul(position) => {
+                write!(
+                    f,
+                    "String contains a NUL character at position {}",
+                    position
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Number of 100 nanosecond intervals between January 1, 1601 and January 1, 1970.
+pub const FILETIME_UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+/// Number of 100 nanosecond intervals per second.
+const TICKS_PER_SECOND: u64 = 10_000_000;
+
+/// Get the characters of a null-terminated string, without the terminator.
+///
+/// # Safety
+///
+/// `value` must point to a string terminated by a zero character.
+unsafe fn terminated<'a, T: Copy + Default + PartialEq>(value: *const T) -> &'a [T] {
+    let mut len = 0;
+    while unsafe { *value.add(len) } != T::default() {
+        len += 1;
+    }
+    unsafe { std::slice::from_raw_parts(value, len) }
+}
+
+/// Convert a null-terminated UTF-16 string, replacing unpaired surrogates with `U+FFFD`.
+///
+/// A null pointer gives an empty string.
+///
+/// # Safety
+///
+/// `value` must be null or point to a null-terminated UTF-16 string.
+pub unsafe fn wide_to_string_lossy(value: PCWSTR_SYS) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+
+    String::from_utf16_lossy(unsafe { terminated(value) })
+}
+
+/// Convert a null-terminated ANSI string, replacing invalid UTF-8 with `U+FFFD`.
+///
+/// A null pointer gives an empty string.
+///
+/// # Safety
+///
+/// `value` must be null or point to a null-terminated string.
+pub unsafe fn ansi_to_string_lossy(value: PCSTR_SYS) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+
+    String::from_utf8_lossy(unsafe { terminated(value) }).into_owned()
+}
+
+/// Invalid UTF-16 is replaced with `U+FFFD`, see `TryWindowsConversionFrom` for a strict conversion.
 impl WindowsConversionFrom<PCWSTR_SYS> for String {
+    // The conversions are only used on strings returned by the Windows API.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     fn win_from(value: PCWSTR_SYS) -> Self {
-        unsafe { PCWSTR::from_raw(value).to_string().unwrap() }
-        //unsafe { PCWSTR::from_raw(value).to_hstring() }.to_string_lossy()
+        unsafe { wide_to_string_lossy(value) }
     }
 }
 
+/// Invalid UTF-8 is replaced with `U+FFFD`, see `TryWindowsConversionFrom` for a strict conversion.
 impl WindowsConversionFrom<PCSTR_SYS> for String {
+    // The conversions are only used on strings returned by the Windows API.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     fn win_from(value: PCSTR_SYS) -> Self {
-        unsafe { PCSTR::from_raw(value).to_string().unwrap() }
+        unsafe { ansi_to_string_lossy(value) }
+    }
+}
+
+impl TryWindowsConversionFrom<PCWSTR_SYS> for String {
+    // The conversions are only used on strings returned by the Windows API.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn try_win_from(value: PCWSTR_SYS) -> Result<Self, ConversionError> {
+        if value.is_null() {
+            return Ok(String::new());
+        }
+
+        String::from_utf16(unsafe { terminated(value) }).map_err(|_| ConversionError::InvalidUtf16)
+    }
+}
+
+impl TryWindowsConversionFrom<PCSTR_SYS> for String {
+    // The conversions are only used on strings returned by the Windows API.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn try_win_from(value: PCSTR_SYS) -> Result<Self, ConversionError> {
+        if value.is_null() {
+            return Ok(String::new());
+        }
+
+        String::from_utf8(unsafe { terminated(value) }.to_vec())
+            .map_err(|_| ConversionError::InvalidUtf8)
     }
 }
 
 impl WindowsConversionFrom<u64> for DateTime<Utc> {
     /// Value is a windows timestamp containing number of elapsed 100 nsecs from Jan 1 1601
     fn win_from(value: u64) -> Self {
-        let offset_to_unix: u64 = 116444736000000000;
-        let unix_value = value - offset_to_unix;
-        let nanos = unix_value.checked_mul(100).unwrap();
-        DateTime::from_timestamp_nanos(nanos.try_into().unwrap())
+        Self::try_win_from(value).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl TryWindowsConversionFrom<u64> for DateTime<Utc> {
+    /// Value is a windows timestamp containing number of elapsed 100 nsecs from Jan 1 1601. Timestamps before 1970 are
+    /// supported.
+    fn try_win_from(value: u64) -> Result<Self, ConversionError> {
+        let seconds = (value / TICKS_PER_SECOND) as i64
+            - (FILETIME_UNIX_EPOCH_TICKS / TICKS_PER_SECOND) as i64;
+        let nanos = (value % TICKS_PER_SECOND) as u32 * 100;
+
+        DateTime::from_timestamp(seconds, nanos).ok_or(ConversionError::TimestampOutOfRange(value))
     }
 }
 
@@ -58,7 +205,17 @@ impl WindowsConversionFrom<u64> for FILETIME {
 }
 
 impl WindowsConversionFrom<SYSTEMTIME> for NaiveDateTime {
+    /// # Panics
+    ///
+    /// Panics if the value is not a valid date and time, e.g. a zeroed `SYSTEMTIME`. Use `try_win_from` for values
+    /// read from events.
     fn win_from(value: SYSTEMTIME) -> Self {
+        Self::try_win_from(value).expect("Invalid SYSTEMTIME value")
+    }
+}
+
+impl TryWindowsConversionFrom<SYSTEMTIME> for NaiveDateTime {
+    fn try_win_from(value: SYSTEMTIME) -> Result<Self, ConversionError> {
         NaiveDate::from_ymd_opt(value.wYear as i32, value.wMonth as u32, value.wDay as u32)
             .and_then(|date| {
                 date.and_hms_milli_opt(
@@ -68,19 +225,113 @@ impl WindowsConversionFrom<SYSTEMTIME> for NaiveDateTime {
                     value.wMilliseconds as u32,
                 )
             })
-            .expect("Invalid SYSTEMTIME value")
+            .ok_or_else(|| {
+                ConversionError::InvalidSystemTime(format!(
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+                    value.wYear,
+                    value.wMonth,
+                    value.wDay,
+                    value.wHour,
+                    value.wMinute,
+                    value.wSecond,
+                    value.wMilliseconds
+                ))
+            })
     }
 }
 
 impl WindowsConversionFrom<FILETIME> for DateTime<Utc> {
     fn win_from(file_time: FILETIME) -> Self {
+        Self::try_win_from(file_time).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl TryWindowsConversionFrom<FILETIME> for DateTime<Utc> {
+    fn try_win_from(file_time: FILETIME) -> Result<Self, ConversionError> {
         let filetime_as_u64 =
             ((file_time.dwHighDateTime as u64) << 32) | (file_time.dwLowDateTime as u64);
 
-        let epoch_difference = Duration::from_secs(11_644_473_600);
-        let system_time =
-            UNIX_EPOCH + Duration::from_nanos(filetime_as_u64 * 100) - epoch_difference;
+        Self::try_win_from(filetime_as_u64)
+    }
+}
+
+impl TryWindowsConversionFrom<DateTime<Utc>> for u64 {
+    /// Convert to a windows timestamp containing number of elapsed 100 nsecs from Jan 1 1601. Fails for times before
+    /// 1601 or after the year 60056.
+    fn try_win_from(value: DateTime<Utc>) -> Result<Self, ConversionError> {
+        let ticks = value.timestamp() as i128 * TICKS_PER_SECOND as i128
+            + (value.timestamp_subsec_nanos() / 100) as i128
+            + FILETIME_UNIX_EPOCH_TICKS as i128;
+
+        u64::try_from(ticks).map_err(|_| {
+            ConversionError::DateTimeOutOfRange(value.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        })
+    }
+}
+
+impl TryWindowsConversionFrom<DateTime<Utc>> for FILETIME {
+    fn try_win_from(value: DateTime<Utc>) -> Result<Self, ConversionError> {
+        u64::try_win_from(value).map(FILETIME::win_from)
+    }
+}
+
+impl TryWindowsConversionFrom<NaiveDateTime> for SYSTEMTIME {
+    /// The day of the week is set from the date. Sub-millisecond precision is truncated, and a leap second is
+    /// represented as the 999th millisecond of the previous second. Fails for years outside of 1601 to 30827.
+    fn try_win_from(value: NaiveDateTime) -> Result<Self, ConversionError> {
+        if !(1601..=30827).contains(&value.year()) {
+            return Err(ConversionError::DateTimeOutOfRange(
+                value.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ));
+        }
+
+        Ok(SYSTEMTIME {
+            wYear: value.year() as u16,
+            wMonth: value.month() as u16,
+            wDayOfWeek: value.weekday().num_days_from_sunday() as u16,
+            wDay: value.day() as u16,
+            wHour: value.hour() as u16,
+            wMinute: value.minute() as u16,
+            wSecond: value.second() as u16,
+            wMilliseconds: (value.nanosecond() / 1_000_000).min(999) as u16,
+        })
+    }
+}
+
+impl TryWindowsConversionFrom<&str> for Vec<u16> {
+    /// Encode as a null-terminated UTF-16 string, e.g. for a `PCWSTR` parameter.
+    fn try_win_from(value: &str) -> Result<Self, ConversionError> {
+        if let Some(position) = value.find('\0') {
+            return Err(ConversionError::InteriorNul(position));
+        }
 
-        DateTime::<Utc>::from(system_time)
+        Ok(value.encode_utf16().chain(Some(0)).collect())
     }
 }
+
+impl TryWindowsConversionFrom<&str> for Vec<u8> {
+    /// Encode as a null-terminated string for a `PCSTR` parameter. Like the conversion from `PCSTR`, the string is
+    /// encoded as UTF-8.
+    fn try_win_from(value: &str) -> Result<Self, ConversionError> {
+        if let Some(position) = value.find('\0') {
+            return Err(ConversionError::InteriorNul(position));
+        }
+
+        Ok(value.bytes().chain(Some(0)).collect())
+    }
+}
+
+/// Convert a duration to the milliseconds compared with the XPath `timediff` function of event queries.
+///
+/// Sub-millisecond precision is truncated and durations beyond `u64::MAX` milliseconds saturate.
+pub fn xpath_timediff_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// XPath query for events created within `duration` before now, e.g. `*[System[TimeCreated[timediff(@SystemTime) <= 3600000]]]`.
+pub fn xpath_created_within(duration: Duration) -> String {
+    format!(
+        "*[System[TimeCreated[timediff(@SystemTime) <= {}]]]",
+        xpath_timediff_millis(duration)
+    )
+}
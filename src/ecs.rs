@@ -0,0 +1,547 @@
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+
+use crate::record::EventRecord;
+use crate::sink::EventSink;
+
+/// Version of the Elastic Common Schema the documents follow.
+pub static ECS_VERSION: &str = "8.11.0";
+
+static KEYWORD_AUDIT_FAILURE: u64 = 0x0010_0000_0000_0000;
+static KEYWORD_AUDIT_SUCCESS: u64 = 0x0020_0000_0000_0000;
+
+/// Names of the standard keywords, as used by Winlogbeat in `winlog.keywords`.
+static KEYWORD_NAMES: &[(u64, &str)] = &[
+    (0x0001_0000_0000_0000, "Response Time"),
+    (0x0002_0000_0000_0000, "WDI Context"),
+    (0x0004_0000_0000_0000, "WDI Diag"),
+    (0x0008_0000_0000_0000, "SQM"),
+    (KEYWORD_AUDIT_FAILURE, "Audit Failure"),
+    (KEYWORD_AUDIT_SUCCESS, "Audit Success"),
+    (0x0040_0000_0000_0000, "Correlation Hint"),
+    (0x0080_0000_0000_0000, "Classic"),
+];
+
+/// Names of the logon types of the Security events, as used by Winlogbeat in `winlog.logon.type`.
+static LOGON_TYPES: &[(&str, &str)] = &[
+    ("0", "System"),
+    ("2", "Interactive"),
+    ("3", "Network"),
+    ("4", "Batch"),
+    ("5", "Service"),
+    ("7", "Unlock"),
+    ("8", "NetworkCleartext"),
+    ("9", "NewCredentials"),
+    ("10", "RemoteInteractive"),
+    ("11", "CachedInteractive"),
+    ("12", "CachedRemoteInteractive"),
+    ("13", "CachedUnlock"),
+];
+
+/// Whose account the `user.*` fields of a Security event describe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UserRole {
+    /// The account that performed the action (`Subject*` fields).
+    Subject,
+    /// The account the action was performed on (`Target*` fields), e.g. the account that logged on.
+    Target,
+}
+
+/// ECS classification of a Security event.
+struct SecurityEvent {
+    event_id: u16,
+    action: &'static str,
+    category: &'static [&'static str],
+    kind: &'static [&'static str],
+    user: UserRole,
+}
+
+/// The most common events of the Security channel, classified like the Winlogbeat security module.
+static SECURITY_EVENTS: &[SecurityEvent] = &[
+    SecurityEvent {
+        event_id: 1102,
+        action: "audit-log-cleared",
+        category: &["iam"],
+        kind: &["admin", "change"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4624,
+        action: "logged-in",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4625,
+        action: "logon-failed",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4634,
+        action: "logged-out",
+        category: &["authentication"],
+        kind: &["end"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4647,
+        action: "logged-out",
+        category: &["authentication"],
+        kind: &["end"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4648,
+        action: "logged-in-explicit",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4672,
+        action: "logged-in-special",
+        category: &["iam"],
+        kind: &["admin"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4688,
+        action: "created-process",
+        category: &["process"],
+        kind: &["start"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4689,
+        action: "exited-process",
+        category: &["process"],
+        kind: &["end"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4720,
+        action: "added-user-account",
+        category: &["iam"],
+        kind: &["user", "creation"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4722,
+        action: "enabled-user-account",
+        category: &["iam"],
+        kind: &["user", "change"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4725,
+        action: "disabled-user-account",
+        category: &["iam"],
+        kind: &["user", "change"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4726,
+        action: "deleted-user-account",
+        category: &["iam"],
+        kind: &["user", "deletion"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4732,
+        action: "added-member-to-group",
+        category: &["iam"],
+        kind: &["group", "change"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4740,
+        action: "locked-out-user-account",
+        category: &["iam"],
+        kind: &["user", "change"],
+        user: UserRole::Subject,
+    },
+    SecurityEvent {
+        event_id: 4768,
+        action: "kerberos-authentication-ticket-requested",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4769,
+        action: "kerberos-service-ticket-requested",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4771,
+        action: "kerberos-preauth-failed",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+    SecurityEvent {
+        event_id: 4776,
+        action: "credential-validated",
+        category: &["authentication"],
+        kind: &["start"],
+        user: UserRole::Target,
+    },
+];
+
+/// ECS `log.level` of an event level, as used by Winlogbeat.
+pub fn ecs_log_level(level: Option<u8>) -> &'static str {
+    match level {
+        Some(1) => "critical",
+        Some(2) => "error",
+        Some(3) => "warning",
+        Some(5) => "verbose",
+        _ => "information",
+    }
+}
+
+/// Map a record to an ECS document with the field layout of Winlogbeat.
+///
+/// The event data is kept as strings in `winlog.event_data`. Events of the Security channel listed in
+/// `SECURITY_EVENTS` additionally get `event.action`, `event.category`, `event.type` and the user, source, logon and
+/// process fields extracted from their event data.
+pub fn ecs_document(record: &EventRecord) -> Map<String, Value> {
+    let event_id = record.event_id.to_string();
+    let mut document = json!({
+        "@timestamp": record.time_created.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "ecs": { "version": ECS_VERSION },
+        "event": {
+            "code": event_id,
+            "kind": "event",
+            "provider": record.provider_name,
+        },
+        "log": { "level": ecs_log_level(record.level) },
+        "winlog": {
+            "event_id": event_id,
+            "provider_name": record.provider_name,
+            "record_id": record.event_record_id,
+        },
+    });
+
+    let mut set = |path: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            set_path(&mut document, path, value);
+        }
+    };
+
+    set("message", record.message.clone().map(Value::from));
+    set("host.name", record.computer.clone().map(Value::from));
+    set("process.pid", record.process_id.map(Value::from));
+    set("user.id", record.user_id.clone().map(Value::from));
+    set("winlog.channel", record.channel.clone().map(Value::from));
+    set(
+        "winlog.computer_name",
+        record.computer.clone().map(Value::from),
+    );
+    set(
+        "winlog.provider_guid",
+        record.provider_guid.clone().map(Value::from),
+    );
+    set("winlog.version", record.version.map(Value::from));
+    set("winlog.task", record.task.map(Value::from));
+    set("winlog.opcode", record.opcode.map(Value::from));
+    set("winlog.process.pid", record.process_id.map(Value::from));
+    set(
+        "winlog.process.thread.id",
+        record.thread_id.map(Value::from),
+    );
+    set(
+        "winlog.activity_id",
+        record.activity_id.clone().map(Value::from),
+    );
+    set(
+        "winlog.related_activity_id",
+        record.related_activity_id.clone().map(Value::from),
+    );
+    set(
+        "winlog.user.identifier",
+        record.user_id.clone().map(Value::from),
+    );
+
+    if let Some(keywords) = record.keywords {
+        let names: Vec<Value> = KEYWORD_NAMES
+            .iter()
+            .filter(|(mask, _)| keywords & mask != 0)
+            .map(|(_, name)| Value::from(*name))
+            .collect();
+        if !names.is_empty() {
+            set("winlog.keywords", Some(Value::Array(names)));
+        }
+
+        if keywords & KEYWORD_AUDIT_SUCCESS != 0 {
+            set("event.outcome", Some("success".into()));
+        } else if keywords & KEYWORD_AUDIT_FAILURE != 0 {
+            set("event.outcome", Some("failure".into()));
+        }
+    }
+
+    if !record.event_data.is_empty() {
+        let mut event_data = Map::new();
+        for (name, value) in record.event_data.iter() {
+            if !event_data.contains_key(name) {
+                event_data.insert(name.to_owned(), value.to_string().into());
+            }
+        }
+        set("winlog.event_data", Some(Value::Object(event_data)));
+    }
+
+    if record.channel.as_deref() == Some("Security") {
+        enrich_security_event(record, &mut document);
+    }
+
+    match document {
+        Value::Object(document) => document,
+        _ => unreachable!(),
+    }
+}
+
+fn enrich_security_event(record: &EventRecord, document: &mut Value) {
+    let Some(event) = SECURITY_EVENTS
+        .iter()
+        .find(|event| event.event_id == record.event_id)
+    else {
+        return;
+    };
+
+    let data = |name: &str| -> Option<String> {
+        record
+            .event_data
+            .get(name)
+            .map(|value| value.to_string())
+            .filter(|value| !value.is_empty() && value != "-")
+    };
+
+    set_path(document, "event.action", event.action.into());
+    set_path(document, "event.category", json!(event.category));
+    set_path(document, "event.type", json!(event.kind));
+
+    let (user, other, other_path) = match event.user {
+        UserRole::Subject => ("Subject", "Target", "user.target"),
+        UserRole::Target => ("Target", "Subject", "user.effective"),
+    };
+    for (prefix, path) in [(user, "user"), (other, other_path)] {
+        if let Some(name) = data(&format!("{}UserName", prefix)) {
+            set_path(document, &format!("{}.name", path), name.into());
+        }
+        if let Some(domain) = data(&format!("{}DomainName", prefix)) {
+            set_path(document, &format!("{}.domain", path), domain.into());
+        }
+        if let Some(sid) = data(&format!("{}UserSid", prefix)) {
+            set_path(document, &format!("{}.id", path), sid.into());
+        }
+    }
+
+    if let Some(address) = data("IpAddress").and_then(|address| parse_ip(&address)) {
+        set_path(document, "source.ip", address.to_string().into());
+    }
+    if let Some(port) = data("IpPort").and_then(|port| port.parse::<u16>().ok()) {
+        if port != 0 {
+            set_path(document, "source.port", port.into());
+        }
+    }
+    if let Some(workstation) = data("WorkstationName") {
+        set_path(document, "source.domain", workstation.into());
+    }
+
+    if let Some(logon_type) = data("LogonType") {
+        let name = LOGON_TYPES
+            .iter()
+            .find(|(value, _)| *value == logon_type)
+            .map_or(logon_type.as_str(), |(_, name)| name);
+        set_path(document, "winlog.logon.type", name.into());
+    }
+    if let Some(logon_id) = data("TargetLogonId").or_else(|| data("SubjectLogonId")) {
+        set_path(document, "winlog.logon.id", logon_id.into());
+    }
+
+    // 4688 describes the new process and its parent, other events the process that caused them.
+    let (process, parent) = match data("NewProcessName") {
+        Some(_) => ("NewProcess", Some("Process")),
+        None => ("Process", None),
+    };
+    if let Some(executable) = data(&format!("{}Name", process)) {
+        set_path(document, "process.name", file_name(&executable).into());
+        set_path(document, "process.executable", executable.into());
+    }
+    if let Some(pid) = data(&format!("{}Id", process)).and_then(|pid| parse_pid(&pid)) {
+        set_path(document, "process.pid", pid.into());
+    }
+    if let Some(command_line) = data("CommandLine") {
+        set_path(document, "process.command_line", command_line.into());
+    }
+    if let Some(parent) = parent {
+        if let Some(pid) = data(&format!("{}Id", parent)).and_then(|pid| parse_pid(&pid)) {
+            set_path(document, "process.parent.pid", pid.into());
+        }
+        if let Some(executable) = data("ParentProcessName") {
+            set_path(
+                document,
+                "process.parent.name",
+                file_name(&executable).into(),
+            );
+            set_path(document, "process.parent.executable", executable.into());
+        }
+    }
+}
+
+/// Set a field of a nested object, creating the objects on the path. Existing values are replaced.
+fn set_path(document: &mut Value, path: &str, value: Value) {
+    let mut target = document;
+    let mut names = path.split('.').peekable();
+
+    while let Some(name) = names.next() {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let object = target.as_object_mut().unwrap();
+
+        if names.peek().is_none() {
+            object.insert(name.to_owned(), value);
+            return;
+        }
+        target = object
+            .entry(name)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+/// Parse an IP address of the event data, e.g. `::ffff:192.168.1.10` (as `192.168.1.10`) or `::1`.
+pub fn parse_ip(address: &str) -> Option<IpAddr> {
+    let address: IpAddr = address.parse().ok()?;
+
+    Some(match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4),
+        v4 => v4,
+    })
+}
+
+/// Parse a process ID of the event data, which is hexadecimal (`0x1a4`) in most Security events.
+pub fn parse_pid(pid: &str) -> Option<u32> {
+    match pid.strip_prefix("0x").or_else(|| pid.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => pid.parse().ok(),
+    }
+}
+
+/// File name of a Windows path, e.g. `cmd.exe` of `C:\Windows\System32\cmd.exe`.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+/// Bulk API action of the documents written by `EcsBulkWriter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BulkAction {
+    /// Add the document, required for data streams. Fails for documents with an existing ID.
+    #[default]
+    Create,
+    /// Add or replace the document.
+    Index,
+}
+
+/// Settings of an `EcsBulkWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkOptions {
+    /// Index or data stream name. May contain `chrono` format specifiers, e.g. `winlogbeat-%Y.%m.%d`, which are
+    /// replaced with the time the event was created.
+    pub index: String,
+    pub action: BulkAction,
+    /// Set the document ID from the computer, channel and record ID, so retried requests do not duplicate events.
+    pub deterministic_ids: bool,
+}
+
+impl BulkOptions {
+    pub fn new(index: &str) -> Self {
+        Self {
+            index: index.to_owned(),
+            action: BulkAction::Create,
+            deterministic_ids: true,
+        }
+    }
+}
+
+/// Sink writing the request body of the Elasticsearch `_bulk` API: an action line followed by the ECS document of each
+/// event.
+pub struct EcsBulkWriter<W: Write> {
+    writer: BufWriter<W>,
+    options: BulkOptions,
+}
+
+impl<W: Write> EcsBulkWriter<W> {
+    pub fn new(writer: W, options: BulkOptions) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            options,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flush the buffer and return the underlying writer.
+    pub fn into_inner(self) -> Result<W, String> {
+        self.writer
+            .into_inner()
+            .map_err(|error| format!("Failed to flush bulk request: {}", error.error()))
+    }
+}
+
+impl<W: Write> EventSink for EcsBulkWriter<W> {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        // `to_string` would panic on an invalid format specifier.
+        let mut index = String::new();
+        write!(index, "{}", record.time_created.format(&self.options.index))
+            .map_err(|_| format!("Invalid index name pattern: {}", self.options.index))?;
+        let mut metadata = Map::new();
+        metadata.insert("_index".to_owned(), index.into());
+        if self.options.deterministic_ids {
+            metadata.insert("_id".to_owned(), document_id(record).into());
+        }
+
+        let action = match self.options.action {
+            BulkAction::Create => "create",
+            BulkAction::Index => "index",
+        };
+        let mut action_line = Map::new();
+        action_line.insert(action.to_owned(), Value::Object(metadata));
+
+        let write = |writer: &mut BufWriter<W>, value: &Value| -> Result<(), String> {
+            serde_json::to_writer(&mut *writer, value)
+                .map_err(|error| error.to_string())
+                .and_then(|_| writer.write_all(b"\n").map_err(|error| error.to_string()))
+                .map_err(|error| format!("Failed to write bulk request: {}", error))
+        };
+
+        write(&mut self.writer, &Value::Object(action_line))?;
+        write(&mut self.writer, &Value::Object(ecs_document(record)))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|error| format!("Failed to flush bulk request: {}", error))
+    }
+}
+
+/// Document ID identifying an event across retries: computer, channel and record ID.
+pub fn document_id(record: &EventRecord) -> String {
+    format!(
+        "{}:{}:{}",
+        record.computer.as_deref().unwrap_or_default(),
+        record.channel.as_deref().unwrap_or_default(),
+        record.event_record_id
+    )
+}
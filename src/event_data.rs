@@ -0,0 +1,154 @@
+use roxmltree::{Document, Node};
+
+use crate::manifest::ManifestTemplate;
+use crate::variant::EventVariantValue;
+
+/// User data of an event as an ordered map of field names to values.
+///
+/// Fields keep the order of the event's template, so positional access matches `render_user_context`.
+#[derive(Debug, Default)]
+pub struct EventData {
+    fields: Vec<(String, EventVariantValue)>,
+}
+
+impl EventData {
+    /// Name the values of an event with the field names of its template.
+    ///
+    /// Values without a (non-empty) name get a positional name, `param1` for the first value and so on. This is how
+    /// the values of classic events, whose `Data` elements have no names, are accessed.
+    pub fn new(values: Vec<EventVariantValue>, names: &[String]) -> Self {
+        let fields = values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let name = match names.get(index) {
+                    Some(name) if !name.is_empty() => name.clone(),
+                    _ => positional_name(index),
+                };
+                (name, value)
+            })
+            .collect();
+
+        Self { fields }
+    }
+
+    /// Get the value of the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&EventVariantValue> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(&str, &EventVariantValue)> {
+        self.fields
+            .get(index)
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &EventVariantValue)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Get the values in template order, as returned by `render_user_context`.
+    pub fn into_values(self) -> Vec<EventVariantValue> {
+        self.fields.into_iter().map(|(_, value)| value).collect()
+    }
+}
+
+impl IntoIterator for EventData {
+    type Item = (String, EventVariantValue);
+    type IntoIter = std::vec::IntoIter<(String, EventVariantValue)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter()
+    }
+}
+
+/// Positional name of a field without a name in its template, e.g. `param1` for the first value.
+pub fn positional_name(index: usize) -> String {
+    format!("param{}", index + 1)
+}
+
+/// Get the field names of an event from its XML.
+///
+/// These are the `Name` attributes of the `EventData/Data` elements, or the element names below `UserData` for events
+/// with a custom user data schema. Unnamed `Data` elements (classic events) give empty names.
+pub fn field_names_from_event_xml(xml: &str) -> Result<Vec<String>, String> {
+    let document =
+        Document::parse(xml).map_err(|error| format!("Failed to parse event XML: {}", error))?;
+    let root = document.root_element();
+
+    if let Some(event_data) = child(root, "EventData") {
+        return Ok(event_data
+            .children()
+            .filter(Node::is_element)
+            .map(|data| match data.tag_name().name() {
+                "Data" => data.attribute("Name").unwrap_or_default().to_owned(),
+                other => other.to_owned(),
+            })
+            .collect());
+    }
+
+    if let Some(user_data) = child(root, "UserData") {
+        return Ok(user_data
+            .children()
+            .find(Node::is_element)
+            .map(|schema| {
+                schema
+                    .children()
+                    .filter(Node::is_element)
+                    .map(|field| field.tag_name().name().to_owned())
+                    .collect()
+            })
+            .unwrap_or_default());
+    }
+
+    Ok(Vec::new())
+}
+
+/// Get the field names of an event template, as returned by the `EventMetadataEventTemplate` property of the
+/// publisher metadata (e.g. `PublisherEventMetadata::template`).
+pub fn field_names_from_template_xml(template: &str) -> Result<Vec<String>, String> {
+    let document = Document::parse(template)
+        .map_err(|error| format!("Failed to parse event template: {}", error))?;
+
+    Ok(document
+        .root_element()
+        .children()
+        .filter(|node| node.is_element() && matches!(node.tag_name().name(), "data" | "struct"))
+        .map(|field| field.attribute("name").unwrap_or_default().to_owned())
+        .collect())
+}
+
+/// Get the field names of a template of an instrumentation manifest.
+pub fn field_names_from_manifest_template(template: &ManifestTemplate) -> Vec<String> {
+    template
+        .fields
+        .iter()
+        .map(|field| field.name.clone())
+        .collect()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
@@ -0,0 +1,375 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use serde_json::{Map, Value};
+
+use crate::http::{HttpClient, HttpOptions};
+use crate::ndjson::variant_to_json;
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+use crate::syslog::syslog_severity;
+use crate::transport::{NetworkOptions, QueuedSender, Transport};
+
+/// Magic bytes at the start of a chunk of a GELF message sent over UDP.
+static CHUNK_MAGIC: [u8; 2] = [0x1E, 0x0F];
+/// Size of the chunk header: magic bytes, message ID, sequence number and sequence count.
+static CHUNK_HEADER_SIZE: usize = 12;
+/// Maximum number of chunks of a message accepted by Graylog.
+static MAX_CHUNKS: usize = 128;
+
+/// Compression of GELF messages sent over UDP or HTTP. Messages sent over TCP are never compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GelfCompression {
+    None,
+    #[default]
+    Gzip,
+    Zlib,
+}
+
+/// Destination of a `GelfSink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GelfEndpoint {
+    /// GELF UDP, TCP or TLS input.
+    Network(NetworkOptions),
+    /// GELF HTTP input, e.g. `http://graylog:12201/gelf`.
+    Http(HttpOptions),
+}
+
+/// Settings of a `GelfSink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GelfOptions {
+    pub endpoint: GelfEndpoint,
+    pub compression: GelfCompression,
+    /// Maximum size of a UDP datagram. Longer messages are split into chunks of this size.
+    pub chunk_size: usize,
+    /// Number of messages kept while the HTTP endpoint is unreachable. For the other endpoints, this is
+    /// `NetworkOptions::max_queued`.
+    pub max_queued: usize,
+    /// Parts of the event to send. The raw XML is never sent.
+    pub fields: RecordFields,
+}
+
+impl GelfOptions {
+    /// Create settings for a GELF UDP, TCP or TLS input.
+    pub fn new(address: &str, transport: Transport) -> Self {
+        Self::with_endpoint(GelfEndpoint::Network(NetworkOptions::new(
+            address, transport,
+        )))
+    }
+
+    /// Create settings for a GELF HTTP input.
+    pub fn http(url: &str) -> Self {
+        Self::with_endpoint(GelfEndpoint::Http(HttpOptions::new(url)))
+    }
+
+    fn with_endpoint(endpoint: GelfEndpoint) -> Self {
+        Self {
+            endpoint,
+            compression: GelfCompression::Gzip,
+            chunk_size: 1420,
+            max_queued: 10_000,
+            fields: RecordFields::default(),
+        }
+    }
+}
+
+enum GelfOutput {
+    Network(QueuedSender),
+    Http {
+        client: HttpClient,
+        queue: VecDeque<Vec<u8>>,
+        dropped: u64,
+    },
+}
+
+/// Sink forwarding events as GELF 1.1 messages to Graylog.
+///
+/// Over UDP, messages are compressed and split into chunks if they are longer than `GelfOptions::chunk_size`. Over TCP
+/// and TLS, messages are terminated by a null byte. Over HTTP, each message is sent with its own request. Messages are
+/// queued by `write` and sent by `flush`.
+pub struct GelfSink {
+    options: GelfOptions,
+    output: GelfOutput,
+    random: RandomState,
+    /// Number of messages sent over UDP, hashed into the unique ID of their chunks.
+    messages: u64,
+}
+
+impl GelfSink {
+    /// Create a sink. The connection is opened by the first `flush`.
+    pub fn new(options: GelfOptions) -> Result<Self, String> {
+        let output = match &options.endpoint {
+            GelfEndpoint::Network(network) => {
+                GelfOutput::Network(QueuedSender::new(network.clone()))
+            }
+            GelfEndpoint::Http(http) => GelfOutput::Http {
+                client: HttpClient::new(http.clone())?,
+                queue: VecDeque::new(),
+                dropped: 0,
+            },
+        };
+
+        Ok(Self {
+            options,
+            output,
+            random: RandomState::new(),
+            messages: 0,
+        })
+    }
+
+    /// Number of messages waiting to be sent.
+    pub fn queued(&self) -> usize {
+        match &self.output {
+            GelfOutput::Network(sender) => sender.queued(),
+            GelfOutput::Http { queue, .. } => queue.len(),
+        }
+    }
+
+    /// Number of messages dropped because the queue was full or the server rejected them.
+    pub fn dropped(&self) -> u64 {
+        match &self.output {
+            GelfOutput::Network(sender) => sender.dropped(),
+            GelfOutput::Http { dropped, .. } => *dropped,
+        }
+    }
+}
+
+impl EventSink for GelfSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let message = Value::Object(gelf_message(record, &self.options.fields))
+            .to_string()
+            .into_bytes();
+
+        match &mut self.output {
+            GelfOutput::Network(sender) if sender.options().transport == Transport::Udp => {
+                let message = compress(&message, self.options.compression)?;
+                self.messages += 1;
+                let mut hasher = self.random.build_hasher();
+                hasher.write_u64(self.messages);
+                let id = hasher.finish().to_be_bytes();
+
+                sender.enqueue_parts(chunk_message(message, id, self.options.chunk_size)?);
+            }
+            GelfOutput::Network(sender) => {
+                let mut framed = message;
+                framed.push(0);
+                sender.enqueue(framed);
+            }
+            GelfOutput::Http { queue, dropped, .. } => {
+                let message = compress(&message, self.options.compression)?;
+                if self.options.max_queued == 0 {
+                    *dropped += 1;
+                    return Ok(());
+                }
+                while queue.len() >= self.options.max_queued {
+                    queue.pop_front();
+                    *dropped += 1;
+                }
+                queue.push_back(message);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        match &mut self.output {
+            GelfOutput::Network(sender) => sender.send_queued(),
+            GelfOutput::Http {
+                client,
+                queue,
+                dropped,
+            } => {
+                let mut headers = vec![("Content-Type", "application/json")];
+                match self.options.compression {
+                    GelfCompression::None => {}
+                    GelfCompression::Gzip => headers.push(("Content-Encoding", "gzip")),
+                    GelfCompression::Zlib => headers.push(("Content-Encoding", "deflate")),
+                }
+                let path = client.path().to_owned();
+
+                while let Some(message) = queue.front() {
+                    let response = client.request("POST", &path, &headers, message)?;
+                    if !response.is_success() {
+                        if response.is_retryable() {
+                            return Err(format!(
+                                "GELF message failed with status {}",
+                                response.status
+                            ));
+                        }
+                        queue.pop_front();
+                        *dropped += 1;
+                        return Err(format!(
+                            "GELF message rejected with status {}: {}",
+                            response.status,
+                            response.body_text()
+                        ));
+                    }
+                    queue.pop_front();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Convert a record to a GELF 1.1 message.
+///
+/// `host` is the computer, `short_message` the first line of the message (or the provider and event ID if the event
+/// has no message), `full_message` the message if it has more than one line, and `level` the syslog severity of the
+/// event level. The system properties and the event data are additional fields, see `gelf_field_name`.
+pub fn gelf_message(record: &EventRecord, fields: &RecordFields) -> Map<String, Value> {
+    let mut message = Map::new();
+    let text = record
+        .message
+        .as_deref()
+        .filter(|_| fields.message)
+        .map(str::trim)
+        .filter(|text| !text.is_empty());
+    let short_message = text
+        .and_then(|text| text.lines().next())
+        .map(|line| line.trim().to_owned())
+        .unwrap_or_else(|| format!("{} event {}", record.provider_name, record.event_id));
+
+    message.insert("version".to_owned(), "1.1".into());
+    message.insert(
+        "host".to_owned(),
+        record.computer.as_deref().unwrap_or("unknown").into(),
+    );
+    if let Some(text) = text.filter(|text| *text != short_message) {
+        message.insert("full_message".to_owned(), text.into());
+    }
+    message.insert("short_message".to_owned(), short_message.into());
+    message.insert(
+        "timestamp".to_owned(),
+        (record.time_created.timestamp_millis() as f64 / 1000.0).into(),
+    );
+    message.insert("level".to_owned(), syslog_severity(record.level).into());
+
+    let mut add = |name: &str, value: Option<Value>| {
+        let name = gelf_field_name(name);
+        if let Some(value) = value {
+            message.entry(name).or_insert(value);
+        }
+    };
+
+    add("event_id", Some(record.event_id.into()));
+    add("provider_name", Some(record.provider_name.clone().into()));
+    add("channel", record.channel.clone().map(Value::from));
+    add("event_record_id", Some(record.event_record_id.into()));
+    if fields.system {
+        add(
+            "provider_guid",
+            record.provider_guid.clone().map(Value::from),
+        );
+        add("qualifiers", record.qualifiers.map(Value::from));
+        add("version", record.version.map(Value::from));
+        add("event_level", record.level.map(Value::from));
+        add("task", record.task.map(Value::from));
+        add("opcode", record.opcode.map(Value::from));
+        add(
+            "keywords",
+            record
+                .keywords
+                .map(|keywords| format!("0x{:X}", keywords).into()),
+        );
+        add("activity_id", record.activity_id.clone().map(Value::from));
+        add(
+            "related_activity_id",
+            record.related_activity_id.clone().map(Value::from),
+        );
+        add("process_id", record.process_id.map(Value::from));
+        add("thread_id", record.thread_id.map(Value::from));
+        add("user_id", record.user_id.clone().map(Value::from));
+    }
+    if fields.event_data {
+        for (name, value) in record.event_data.iter() {
+            // Additional fields can only be strings or numbers.
+            let value = match variant_to_json(value) {
+                Value::Null => None,
+                Value::Number(number) => Some(Value::Number(number)),
+                Value::String(text) => Some(Value::String(text)),
+                _ => Some(value.to_string().into()),
+            };
+            add(name, value);
+        }
+    }
+
+    message
+}
+
+/// Name of an additional field: `_` followed by the name, with characters other than letters, digits, `_`, `-` and `.`
+/// replaced by `_`. As `_id` is reserved, the field `id` becomes `_id_`.
+pub fn gelf_field_name(name: &str) -> String {
+    let mut field = String::with_capacity(name.len() + 1);
+    field.push('_');
+    field.extend(name.chars().map(|c| {
+        if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+            c
+        } else {
+            '_'
+        }
+    }));
+
+    if field == "_id" {
+        field.push('_');
+    }
+    field
+}
+
+fn compress(message: &[u8], compression: GelfCompression) -> Result<Vec<u8>, String> {
+    let result = match compression {
+        GelfCompression::None => return Ok(message.to_vec()),
+        GelfCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(message).and_then(|_| encoder.finish())
+        }
+        GelfCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(message).and_then(|_| encoder.finish())
+        }
+    };
+
+    result.map_err(|error| format!("Failed to compress GELF message: {}", error))
+}
+
+/// Split a message into GELF chunks of at most `chunk_size` bytes. A message that fits into one datagram is not
+/// chunked.
+pub fn chunk_message(
+    message: Vec<u8>,
+    id: [u8; 8],
+    chunk_size: usize,
+) -> Result<Vec<Vec<u8>>, String> {
+    if message.len() <= chunk_size {
+        return Ok(vec![message]);
+    }
+
+    let payload_size = chunk_size.saturating_sub(CHUNK_HEADER_SIZE).max(1);
+    let count = message.len().div_ceil(payload_size);
+    if count > MAX_CHUNKS {
+        return Err(format!(
+            "GELF message of {} bytes exceeds {} chunks of {} bytes",
+            message.len(),
+            MAX_CHUNKS,
+            chunk_size
+        ));
+    }
+
+    Ok(message
+        .chunks(payload_size)
+        .enumerate()
+        .map(|(sequence, payload)| {
+            let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + payload.len());
+            chunk.extend_from_slice(&CHUNK_MAGIC);
+            chunk.extend_from_slice(&id);
+            chunk.push(sequence as u8);
+            chunk.push(count as u8);
+            chunk.extend_from_slice(payload);
+            chunk
+        })
+        .collect())
+}
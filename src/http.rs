@@ -0,0 +1,366 @@
+use std::io::{BufRead, BufReader, Read};
+use std::time::{Duration, Instant};
+
+use crate::transport::{Connection, NetworkOptions, TlsOptions, Transport};
+
+/// Settings of an `HttpClient`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// URL of the endpoint, e.g. `http://localhost:4318/v1/logs`. `https` URLs use TLS.
+    pub url: String,
+    pub tls: TlsOptions,
+    /// Timeout for connecting and for each read and write.
+    pub timeout: Duration,
+    /// Headers sent with every request, e.g. `Authorization`.
+    pub headers: Vec<(String, String)>,
+    /// Maximum size of a response body. Larger responses fail instead of being read into memory.
+    pub max_response_size: usize,
+}
+
+impl HttpOptions {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            tls: TlsOptions::default(),
+            timeout: Duration::from_secs(10),
+            headers: Vec::new(),
+            max_response_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Response to an HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Value of the first header with the name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Whether the request may succeed when sent again later: 429 (too many requests), 502, 503 and 504.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 429 | 502 | 503 | 504)
+    }
+
+    /// Delay requested by a `Retry-After` header in seconds. HTTP dates are not supported.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.header("Retry-After")
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs)
+    }
+
+    /// Body as text, with invalid UTF-8 replaced, for error messages.
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Minimal HTTP/1.1 client for the network sinks.
+///
+/// The connection is kept open between requests. A request on a kept connection that fails is sent once more on a new
+/// connection, as the server may have closed it in the meantime.
+pub struct HttpClient {
+    network: NetworkOptions,
+    path: String,
+    headers: Vec<(String, String)>,
+    max_response_size: usize,
+    connection: Option<Connection>,
+}
+
+impl HttpClient {
+    /// Create a client for the URL of the options. The connection is opened by the first request.
+    pub fn new(options: HttpOptions) -> Result<Self, String> {
+        let (transport, address, path) = parse_url(&options.url)?;
+
+        let mut network = NetworkOptions::new(&address, transport);
+        network.tls = options.tls;
+        network.timeout = options.timeout;
+
+        Ok(Self {
+            network,
+            path,
+            headers: options.headers,
+            max_response_size: options.max_response_size,
+            connection: None,
+        })
+    }
+
+    /// Path of the URL, including the query.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// `POST` a body to the URL of the client.
+    pub fn post(&mut self, content_type: &str, body: &[u8]) -> Result<HttpResponse, String> {
+        let path = self.path.clone();
+        self.request("POST", &path, &[("Content-Type", content_type)], body)
+    }
+
+    /// Send a request to a path of the server and read the response.
+    pub fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<HttpResponse, String> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.network.address,
+            body.len()
+        );
+        let extra = headers.iter().map(|(name, value)| (*name, *value));
+        let configured = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        for (name, value) in configured.chain(extra) {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+
+        let reused = self.connection.is_some();
+        let mut result = self.send(head.as_bytes(), body);
+        if result.is_err() && reused {
+            result = self.send(head.as_bytes(), body);
+        }
+
+        match &result {
+            Ok(response)
+                if !response
+                    .header("Connection")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("close")) => {}
+            _ => self.connection = None,
+        }
+        result
+    }
+
+    fn send(&mut self, head: &[u8], body: &[u8]) -> Result<HttpResponse, String> {
+        if self.connection.is_none() {
+            self.connection = Some(Connection::connect(&self.network)?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+
+        let result = connection
+            .send(head)
+            .and_then(|_| connection.send(body))
+            .and_then(|_| read_response(connection, self.max_response_size));
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+}
+
+/// Split an `http` or `https` URL into transport, `host:port` address and path.
+pub fn parse_url(url: &str) -> Result<(Transport, String, String), String> {
+    let (transport, default_port, rest) = if let Some(rest) = url.strip_prefix("http://") {
+        (Transport::Tcp, 80, rest)
+    } else if let Some(rest) = url.strip_prefix("https://") {
+        (Transport::Tls, 443, rest)
+    } else {
+        return Err(format!("Unsupported URL '{}': expected http or https", url));
+    };
+
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(index) if rest[index..].starts_with('/') => (&rest[..index], rest[index..].to_owned()),
+        Some(index) => (&rest[..index], format!("/{}", &rest[index..])),
+        None => (rest, "/".to_owned()),
+    };
+    if authority.is_empty() || authority.contains('@') {
+        return Err(format!("Unsupported URL '{}': invalid host", url));
+    }
+
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'));
+    let address = if has_port {
+        authority.to_owned()
+    } else {
+        format!("{}:{}", authority, default_port)
+    };
+
+    Ok((transport, address, path))
+}
+
+fn read_response<R: Read>(reader: R, max_size: usize) -> Result<HttpResponse, String> {
+    let error = |error: std::io::Error| format!("Failed to read HTTP response: {}", error);
+    let too_large = || {
+        format!(
+            "Failed to read HTTP response: body is larger than {} bytes",
+            max_size
+        )
+    };
+    let mut reader = BufReader::new(reader);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(error)?;
+    let status = line
+        .strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.get(2..5))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| format!("Invalid HTTP status line '{}'", line.trim_end()))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(error)? == 0 {
+            return Err("Failed to read HTTP response: connection closed".to_owned());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
+    {
+        loop {
+            line.clear();
+            reader.read_line(&mut line).map_err(error)?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size.trim(), 16)
+                .map_err(|_| format!("Invalid HTTP chunk size '{}'", line.trim_end()))?;
+
+            let start = response.body.len();
+            let end = start
+                .checked_add(size)
+                .filter(|end| *end <= max_size)
+                .ok_or_else(too_large)?;
+            response.body.resize(end, 0);
+            reader
+                .read_exact(&mut response.body[start..])
+                .map_err(error)?;
+            line.clear();
+            reader.read_line(&mut line).map_err(error)?;
+
+            if size == 0 {
+                break;
+            }
+        }
+    } else if let Some(length) = response.header("Content-Length") {
+        let length: usize = length
+            .parse()
+            .map_err(|_| format!("Invalid HTTP content length '{}'", length))?;
+        if length > max_size {
+            return Err(too_large());
+        }
+        response.body.resize(length, 0);
+        reader.read_exact(&mut response.body).map_err(error)?;
+    } else if !(status == 204 || status == 304 || (100..200).contains(&status)) {
+        reader
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut response.body)
+            .map_err(error)?;
+        if response.body.len() > max_size {
+            return Err(too_large());
+        }
+        response
+            .headers
+            .push(("Connection".to_owned(), "close".to_owned()));
+    }
+
+    Ok(response)
+}
+
+/// Exponential back-off between attempts of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts after the first one.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry`, counted from zero. A delay requested by the server takes precedence, up to
+    /// `max_delay`.
+    pub fn delay(&self, retry: u32, requested: Option<Duration>) -> Duration {
+        let delay = requested.unwrap_or_else(|| {
+            self.initial_delay
+                .saturating_mul(2u32.saturating_pow(retry.min(31)))
+        });
+        delay.min(self.max_delay)
+    }
+}
+
+/// Back-off after failed requests, shared by all requests of a sink.
+///
+/// Sinks make a single attempt in `EventSink::write` and skip it while `is_waiting`, so writing an event never sleeps.
+/// `flush` waits for the delay and retries.
+#[derive(Debug, Clone, Default)]
+pub struct Backoff {
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    /// Whether the last request failed with a network error or a retryable status.
+    pub fn is_failing(&self) -> bool {
+        self.failures > 0
+    }
+
+    /// Whether the delay after the last failure has not passed yet.
+    pub fn is_waiting(&self) -> bool {
+        self.next_attempt
+            .is_some_and(|next_attempt| Instant::now() < next_attempt)
+    }
+
+    /// Sleep until the delay after the last failure has passed.
+    pub fn wait(&self) {
+        if let Some(next_attempt) = self.next_attempt {
+            std::thread::sleep(next_attempt.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// Record a failed request. The delay grows with every failure in a row, see `RetryPolicy::delay`.
+    pub fn failed(&mut self, policy: &RetryPolicy, requested: Option<Duration>) {
+        self.next_attempt = Some(Instant::now() + policy.delay(self.failures, requested));
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    /// Record a request that got a response that must not be retried, e.g. a success.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
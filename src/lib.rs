@@ -0,0 +1,40 @@
+pub mod backend;
+pub mod cache;
+pub mod catalog;
+pub mod cef;
+pub mod channel;
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod conversions;
+pub mod ecs;
+pub mod event_data;
+pub mod gelf;
+pub mod http;
+pub mod maintenance;
+pub mod manifest;
+pub mod message_template;
+#[cfg(windows)]
+pub mod model;
+pub mod ndjson;
+pub mod otlp;
+pub mod pe;
+#[cfg(windows)]
+pub mod projection;
+#[cfg(windows)]
+pub mod publisher;
+pub mod record;
+#[cfg(windows)]
+pub mod render_cache;
+#[cfg(windows)]
+pub mod session;
+pub mod sink;
+pub mod splunk;
+pub mod syslog;
+pub mod table;
+// The conversion tests spell out the raw Windows values, e.g. `0xAAAAAAAA as u32` and month `04`.
+#[allow(clippy::unnecessary_cast, clippy::zero_prefixed_literal)]
+mod tests;
+pub mod transport;
+pub mod variant;
+#[cfg(windows)]
+pub mod variant_ref;
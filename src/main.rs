@@ -1,74 +1,103 @@
-mod conversions;
-mod model;
-mod tests;
+#[cfg(windows)]
+use std::cell::RefCell;
 
-use model::*;
+#[cfg(windows)]
+use winevttest::model::*;
+#[cfg(windows)]
+use winevttest::ndjson::NdjsonSink;
+#[cfg(windows)]
+use winevttest::record::EventRecord;
+#[cfg(windows)]
+use winevttest::sink::{CheckpointedSink, RecordFields};
 
+/// File the bookmark is saved to after every flushed batch of events and whenever no more events are available.
+#[cfg(windows)]
+static BOOKMARK_PATH: &str = "bookmark.xml";
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("Reading the event log is only supported on Windows");
+    std::process::exit(1);
+}
+
+#[cfg(windows)]
 fn main() {
-    let agomillis = 3600000;
     let channel = "Application";
-    let query = format!(
-        "*[System[TimeCreated[timediff(@SystemTime) <= {}]]]",
-        agomillis
-    );
+    let query = winevttest::conversions::xpath_created_within(std::time::Duration::from_secs(3600));
 
-    let subscription = WindowsEventLogPollingSubscription::new(channel, Some(query.as_str()), None)
-        .unwrap_or_else(|err| {
-            eprintln!("Failed to create subscription: {}", err);
+    // Resume after the last checkpoint of a previous run.
+    let saved = match std::fs::read_to_string(BOOKMARK_PATH) {
+        Ok(xml) => Some(xml),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => {
+            eprintln!("Failed to read bookmark: {}", error);
             std::process::exit(1);
-        });
-
-    let bookmark: WindowsEventLogBookmark = WindowsEventLogBookmark::new().unwrap_or_else(|err| {
+        }
+    };
+    let bookmark = match &saved {
+        Some(xml) => WindowsEventLogBookmark::from_xml(xml),
+        None => WindowsEventLogBookmark::new(),
+    }
+    .unwrap_or_else(|err| {
         eprintln!("Failed to create bookmark: {}", err);
         std::process::exit(1);
     });
 
-    subscription.read_events_blocking(
-        |event| {
-            let system_context = event.render_system_context();
-            match system_context {
-                Ok(context) => {
-                    println!("Context Provider Name: {:?}", context.provider_name);
-                    println!("Context Event Id: {:?}", context.event_id);
-                    println!("Context Process Id: {:?}", context.process_id);
-                    match context.provider_guid {
-                        Some(guid) => {
-                            println!("Context Provider GUID: {:?}", format_guid(&guid));
-                        }
-                        None => println!("Context Provider GUID: None"),
-                    }
-                }
-                Err(error) => println!("Error getting system context: {}", error),
-            };
-
-            let xml = event.render_xml();
+    let subscription = WindowsEventLogPollingSubscription::new(
+        channel,
+        Some(query.as_str()),
+        saved.as_ref().map(|_| &bookmark),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("Failed to create subscription: {}", err);
+        std::process::exit(1);
+    });
 
-            match xml {
-                Ok(xml) => {
-                    println!("{:?}", xml);
-                }
-                Err(error) => println!("Error getting XML: {}", error),
-            };
+    let fields = RecordFields::default();
+    let sink = RefCell::new(CheckpointedSink::new(
+        NdjsonSink::new(std::io::stdout(), fields),
+        10,
+        || {
+            std::fs::write(BOOKMARK_PATH, bookmark.to_xml()?)
+                .map_err(|error| format!("Failed to save bookmark: {}", error))
+        },
+    ));
 
-            match event.render_user_context() {
-                Ok(variables) => {
-                    println!("Event has {} properties.", variables.len());
+    // Stops at the first event that cannot be rendered or written. The bookmark still points at the event before
+    // it, so the next run starts again at the failed event instead of skipping it.
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        if let Err(error) = sink.borrow_mut().checkpoint() {
+            eprintln!("Failed to save checkpoint: {}", error);
+        }
+        std::process::exit(1);
+    };
 
-                    variables.iter().for_each(|f| println!("{:?}", f));
-                }
-                Err(err) => println!("Error: {}", err),
-            }
+    subscription.read_events_blocking_with_idle(
+        |event| {
+            let record = EventRecord::render(event, &fields)
+                .unwrap_or_else(|error| fail(format!("Error rendering event: {}", error)));
 
-            match event.render_message() {
-                Ok(val) => println!("Description: {}", val),
-                Err(err) => println!("Error rendering message: {}", err),
+            let mut sink = sink.borrow_mut();
+            if let Err(error) = sink.write(&record) {
+                drop(sink);
+                fail(format!("Failed to write event: {}", error));
             }
 
             bookmark.update(event).unwrap_or_else(|err| {
                 eprintln!("Failed to update bookmark: {}", err);
             });
+
+            if let Err(error) = sink.checkpoint_if_due() {
+                eprintln!("Failed to save checkpoint: {}", error);
+            }
+        },
+        // Deliver the rest of a batch while waiting for new events.
+        || {
+            if let Err(error) = sink.borrow_mut().checkpoint() {
+                eprintln!("Failed to save checkpoint: {}", error);
+            }
         },
         10,
-        0,
     );
 }
@@ -0,0 +1,61 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::EventLogBackend;
+
+/// Options for exporting the events of a channel with `EvtExportLog`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportLogOptions {
+    /// XPath or structured XML query selecting the events to export. All events are exported if `None`.
+    pub query: Option<String>,
+    /// Export the events matched by the valid parts of the query, instead of failing on query errors.
+    pub tolerate_query_errors: bool,
+    /// Overwrite the target file if it already exists.
+    pub overwrite: bool,
+}
+
+/// Result of a successful export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedLog {
+    pub channel: String,
+    pub target_path: PathBuf,
+    pub query: Option<String>,
+}
+
+/// Result of adding localized metadata to an exported log file with `EvtArchiveExportedLog`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedLog {
+    pub path: PathBuf,
+    /// Locale of the archived metadata, `0` for the locale of the calling thread.
+    pub locale: u32,
+}
+
+/// Result of clearing a channel with `EvtClearLog`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClearedLog {
+    pub channel: String,
+    /// File the events were saved to before they were cleared.
+    pub backup_path: Option<PathBuf>,
+}
+
+/// Result of `snapshot_and_clear`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogSnapshot {
+    pub cleared: ClearedLog,
+    pub archived: ArchivedLog,
+}
+
+/// Save the events of a channel to `backup_path`, clear the channel and archive the backup for offline viewing.
+///
+/// The backup is written by `EvtClearLog` itself, so no events are lost between saving and clearing. The archived
+/// metadata allows the backup to be viewed on computers without the channel's publishers installed.
+pub fn snapshot_and_clear<B: EventLogBackend + ?Sized>(
+    backend: &B,
+    channel: &str,
+    backup_path: &Path,
+    locale: u32,
+) -> Result<LogSnapshot, String> {
+    let cleared = backend.clear_log(channel, Some(backup_path))?;
+    let archived = backend.archive_exported_log(backup_path, locale)?;
+
+    Ok(LogSnapshot { cleared, archived })
+}
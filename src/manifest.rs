@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use roxmltree::{Document, Node};
+
+use crate::message_template::expand_message;
+use crate::variant::EventVariantValue;
+
+/// Named value of a publisher, like a level, task, opcode or keyword.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestNamedValue {
+    /// Name the events refer to, e.g. `win:Informational`.
+    pub name: String,
+    /// Value of the entry. Keywords use their mask.
+    pub value: u64,
+    /// Reference to the localized display name, e.g. `$(string.Task.Logon)`.
+    pub message: Option<String>,
+}
+
+/// Channel a publisher writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestChannel {
+    /// Channel path, e.g. `Microsoft-Windows-PowerShell/Operational`.
+    pub name: String,
+    /// Identifier the events use to refer to the channel. Defaults to the channel name.
+    pub chid: String,
+    pub value: Option<u64>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEvent {
+    pub value: u16,
+    pub version: u8,
+    pub symbol: Option<String>,
+    pub level: Option<String>,
+    pub task: Option<String>,
+    pub opcode: Option<String>,
+    pub keywords: Vec<String>,
+    pub channel: Option<String>,
+    pub template: Option<String>,
+    /// Reference to the localized message, e.g. `$(string.event.4624.message)`.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestTemplateField {
+    pub name: String,
+    pub in_type: String,
+    pub out_type: Option<String>,
+    /// Name of the value map used to render the field.
+    pub map: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestTemplate {
+    pub tid: String,
+    pub fields: Vec<ManifestTemplateField>,
+}
+
+/// Map from field values to localized strings, either of single values (`valueMap`) or of bits (`bitMap`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestValueMap {
+    pub name: String,
+    pub bitmap: bool,
+    /// Values and references to their localized strings.
+    pub entries: Vec<(u64, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestProvider {
+    pub name: String,
+    pub guid: String,
+    pub symbol: Option<String>,
+    pub message: Option<String>,
+    pub message_file_name: Option<String>,
+    pub parameter_file_name: Option<String>,
+    pub events: Vec<ManifestEvent>,
+    pub templates: HashMap<String, ManifestTemplate>,
+    pub maps: HashMap<String, ManifestValueMap>,
+    pub channels: Vec<ManifestChannel>,
+    pub levels: Vec<ManifestNamedValue>,
+    pub tasks: Vec<ManifestNamedValue>,
+    pub opcodes: Vec<ManifestNamedValue>,
+    pub keywords: Vec<ManifestNamedValue>,
+}
+
+impl ManifestProvider {
+    /// Get an event definition. If the exact version is not defined, the highest version of the event is used.
+    pub fn event(&self, event_id: u16, version: u8) -> Option<&ManifestEvent> {
+        self.events
+            .iter()
+            .find(|event| event.value == event_id && event.version == version)
+            .or_else(|| {
+                self.events
+                    .iter()
+                    .filter(|event| event.value == event_id)
+                    .max_by_key(|event| event.version)
+            })
+    }
+
+    /// Get the template of an event.
+    pub fn template(&self, event: &ManifestEvent) -> Option<&ManifestTemplate> {
+        event
+            .template
+            .as_ref()
+            .and_then(|tid| self.templates.get(tid))
+    }
+}
+
+/// Parsed instrumentation manifest (`<instrumentationManifest>`), as used to register event publishers.
+///
+/// Only the parts needed to format event messages offline are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstrumentationManifest {
+    pub providers: Vec<ManifestProvider>,
+    /// Localized strings by culture (e.g. `en-US`) and string ID.
+    pub string_tables: HashMap<String, HashMap<String, String>>,
+}
+
+impl InstrumentationManifest {
+    pub fn parse(xml: &str) -> Result<Self, String> {
+        let document =
+            Document::parse(xml).map_err(|error| format!("Failed to parse manifest: {}", error))?;
+        let root = document.root_element();
+
+        if root.tag_name().name() != "instrumentationManifest" {
+            return Err(format!(
+                "Unexpected manifest root element '{}'",
+                root.tag_name().name()
+            ));
+        }
+
+        let providers = root
+            .descendants()
+            .filter(|node| is_element(node, "provider"))
+            .map(parse_provider)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut string_tables: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for resources in root
+            .descendants()
+            .filter(|node| is_element(node, "resources"))
+        {
+            let culture = resources.attribute("culture").unwrap_or_default();
+            let table = string_tables.entry(culture.to_owned()).or_default();
+
+            for string in resources
+                .descendants()
+                .filter(|node| is_element(node, "string"))
+            {
+                if let (Some(id), Some(value)) = (string.attribute("id"), string.attribute("value"))
+                {
+                    table.insert(id.to_owned(), value.to_owned());
+                }
+            }
+        }
+
+        Ok(Self {
+            providers,
+            string_tables,
+        })
+    }
+
+    /// Find a provider by name (case insensitive) or by GUID.
+    pub fn provider(&self, name_or_guid: &str) -> Option<&ManifestProvider> {
+        let guid = name_or_guid.trim_matches(['{', '}']);
+
+        self.providers.iter().find(|provider| {
+            provider.name.eq_ignore_ascii_case(name_or_guid)
+                || provider
+                    .guid
+                    .trim_matches(['{', '}'])
+                    .eq_ignore_ascii_case(guid)
+        })
+    }
+
+    /// Cultures with a string table, `en-US` first if present.
+    pub fn cultures(&self) -> Vec<&str> {
+        let mut cultures: Vec<&str> = self.string_tables.keys().map(String::as_str).collect();
+        cultures.sort_by_key(|culture| (*culture != "en-US", culture.to_string()));
+        cultures
+    }
+
+    /// Resolve a string reference like `$(string.Id)` in the given culture.
+    ///
+    /// Falls back to `en-US` and then to any other culture defining the string. Values that are not references are
+    /// returned as they are.
+    pub fn resolve_string<'a>(&'a self, reference: &'a str, culture: &str) -> Option<&'a str> {
+        std::iter::once(culture)
+            .chain(self.cultures())
+            .find_map(|culture| self.localized_string(reference, culture))
+    }
+
+    /// Resolve a string reference like `$(string.Id)` in exactly the given culture, without any fallback.
+    pub fn localized_string<'a>(&'a self, reference: &'a str, culture: &str) -> Option<&'a str> {
+        match reference
+            .strip_prefix("$(string.")
+            .and_then(|id| id.strip_suffix(')'))
+        {
+            Some(id) => self.string_tables.get(culture)?.get(id).map(String::as_str),
+            None => Some(reference),
+        }
+    }
+
+    /// Format the message of an event from its user data, like `EvtFormatMessage` does with the installed manifest.
+    ///
+    /// # Parameters
+    /// - `provider`: Name or GUID of the provider.
+    /// - `values`: The event's user data, in template order (as returned by `render_user_context`).
+    /// - `culture`: Preferred culture of the strings, e.g. `en-US`.
+    /// - `parameter`: Lookup of parameter strings for `%%NNNN` insertions.
+    pub fn format_event_message<F>(
+        &self,
+        provider: &str,
+        event_id: u16,
+        version: u8,
+        values: &[EventVariantValue],
+        culture: &str,
+        parameter: F,
+    ) -> Result<String, String>
+    where
+        F: Fn(u32) -> Option<String>,
+    {
+        let manifest_provider = self
+            .provider(provider)
+            .ok_or_else(|| format!("Provider '{}' is not defined in the manifest", provider))?;
+        let event = manifest_provider.event(event_id, version).ok_or_else(|| {
+            format!(
+                "Event {} is not defined for provider '{}'",
+                event_id, provider
+            )
+        })?;
+        let message = event.message.as_deref().ok_or_else(|| {
+            format!(
+                "Event {} of provider '{}' has no message",
+                event_id, provider
+            )
+        })?;
+        let template = self.resolve_string(message, culture).ok_or_else(|| {
+            format!(
+                "Message '{}' of provider '{}' is not localized",
+                message, provider
+            )
+        })?;
+
+        let fields = manifest_provider
+            .template(event)
+            .map_or(&[][..], |template| template.fields.as_slice());
+
+        let insertions: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                self.format_field(manifest_provider, fields.get(index), value, culture)
+            })
+            .collect();
+
+        Ok(expand_message(template, &insertions, parameter))
+    }
+
+    /// Render a single field value, applying its value map and output type.
+    fn format_field(
+        &self,
+        provider: &ManifestProvider,
+        field: Option<&ManifestTemplateField>,
+        value: &EventVariantValue,
+        culture: &str,
+    ) -> String {
+        let field = match field {
+            Some(field) => field,
+            None => return value.to_string(),
+        };
+
+        let map = field
+            .map
+            .as_ref()
+            .and_then(|map| provider.maps.get(map))
+            .map(|map| {
+                let entries = map.entries.iter().filter_map(|(entry, reference)| {
+                    self.resolve_string(reference, culture)
+                        .map(|string| (*entry, string))
+                });
+                (map.bitmap, entries)
+            });
+
+        format_field_value(value, field.out_type.as_deref(), map)
+    }
+}
+
+/// Render a template field value like `EvtFormatMessage` does, applying its value map and output type.
+///
+/// `map` tells whether the field's map is a bitmap, and yields its values with their strings in the wanted culture.
+pub(crate) fn format_field_value<'a, I>(
+    value: &EventVariantValue,
+    out_type: Option<&str>,
+    map: Option<(bool, I)>,
+) -> String
+where
+    I: IntoIterator<Item = (u64, &'a str)>,
+{
+    if let (Some((bitmap, entries)), Some(number)) = (map, value.as_u64()) {
+        let mapped: Vec<&str> = entries
+            .into_iter()
+            .filter(|(entry, _)| match bitmap {
+                true => *entry != 0 && number & entry == *entry,
+                false => *entry == number,
+            })
+            .map(|(_, string)| string.trim_end_matches(['\r', '\n']))
+            .collect();
+
+        if !mapped.is_empty() {
+            return mapped.join(", ");
+        }
+    }
+
+    match (out_type, value.as_u64()) {
+        (Some("win:HexInt32") | Some("win:HexInt64"), Some(number)) => format!("0x{:X}", number),
+        _ => value.to_string(),
+    }
+}
+
+fn is_element(node: &Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| is_element(child, name))
+}
+
+/// Parse a numeric attribute, which may be decimal or hexadecimal (`0x` prefix).
+fn parse_number(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn attribute(node: &Node, name: &str) -> Option<String> {
+    node.attribute(name).map(str::to_owned)
+}
+
+fn parse_named_values(
+    provider: Node,
+    element: &'static str,
+    value_attribute: &str,
+) -> Vec<ManifestNamedValue> {
+    provider
+        .descendants()
+        .filter(|node| is_element(node, element))
+        .filter_map(|node| {
+            Some(ManifestNamedValue {
+                name: node.attribute("name")?.to_owned(),
+                value: node
+                    .attribute(value_attribute)
+                    .and_then(parse_number)
+                    .unwrap_or(0),
+                message: attribute(&node, "message"),
+            })
+        })
+        .collect()
+}
+
+fn parse_provider(provider: Node) -> Result<ManifestProvider, String> {
+    let name = provider
+        .attribute("name")
+        .ok_or("Provider without name in manifest")?
+        .to_owned();
+
+    let mut events = Vec::new();
+    for event in provider
+        .descendants()
+        .filter(|node| is_element(node, "event"))
+    {
+        let value = event
+            .attribute("value")
+            .and_then(parse_number)
+            .ok_or_else(|| format!("Event without value in provider '{}'", name))?;
+        let value = u16::try_from(value)
+            .map_err(|_| format!("Event value {} out of range in provider '{}'", value, name))?;
+
+        events.push(ManifestEvent {
+            value,
+            version: event
+                .attribute("version")
+                .and_then(parse_number)
+                .unwrap_or(0) as u8,
+            symbol: attribute(&event, "symbol"),
+            level: attribute(&event, "level"),
+            task: attribute(&event, "task"),
+            opcode: attribute(&event, "opcode"),
+            keywords: event
+                .attribute("keywords")
+                .map(|keywords| keywords.split_whitespace().map(str::to_owned).collect())
+                .unwrap_or_default(),
+            channel: attribute(&event, "channel"),
+            template: attribute(&event, "template"),
+            message: attribute(&event, "message"),
+        });
+    }
+
+    let templates = provider
+        .descendants()
+        .filter(|node| is_element(node, "template"))
+        .filter_map(|template| {
+            let tid = template.attribute("tid")?.to_owned();
+            let fields = template
+                .children()
+                .filter(|node| is_element(node, "data") || is_element(node, "struct"))
+                .map(|field| ManifestTemplateField {
+                    name: field.attribute("name").unwrap_or_default().to_owned(),
+                    in_type: field
+                        .attribute("inType")
+                        .unwrap_or(field.tag_name().name())
+                        .to_owned(),
+                    out_type: attribute(&field, "outType"),
+                    map: attribute(&field, "map"),
+                })
+                .collect();
+
+            Some((tid.clone(), ManifestTemplate { tid, fields }))
+        })
+        .collect();
+
+    let maps = provider
+        .descendants()
+        .filter(|node| is_element(node, "valueMap") || is_element(node, "bitMap"))
+        .filter_map(|map| {
+            let name = map.attribute("name")?.to_owned();
+            let entries = children(map, "map")
+                .filter_map(|entry| {
+                    Some((
+                        parse_number(entry.attribute("value")?)?,
+                        entry.attribute("message")?.to_owned(),
+                    ))
+                })
+                .collect();
+
+            Some((
+                name.clone(),
+                ManifestValueMap {
+                    name,
+                    bitmap: map.tag_name().name() == "bitMap",
+                    entries,
+                },
+            ))
+        })
+        .collect();
+
+    let channels = provider
+        .descendants()
+        .filter(|node| is_element(node, "channel") || is_element(node, "importChannel"))
+        .filter_map(|channel| {
+            let name = channel.attribute("name")?.to_owned();
+            Some(ManifestChannel {
+                chid: channel.attribute("chid").unwrap_or(&name).to_owned(),
+                name,
+                value: channel.attribute("value").and_then(parse_number),
+                message: attribute(&channel, "message"),
+            })
+        })
+        .collect();
+
+    Ok(ManifestProvider {
+        guid: provider.attribute("guid").unwrap_or_default().to_owned(),
+        symbol: attribute(&provider, "symbol"),
+        message: attribute(&provider, "message"),
+        message_file_name: attribute(&provider, "messageFileName"),
+        parameter_file_name: attribute(&provider, "parameterFileName"),
+        events,
+        templates,
+        maps,
+        channels,
+        levels: parse_named_values(provider, "level", "value"),
+        tasks: parse_named_values(provider, "task", "value"),
+        opcodes: parse_named_values(provider, "opcode", "value"),
+        keywords: parse_named_values(provider, "keyword", "mask"),
+        name,
+    })
+}
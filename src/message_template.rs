@@ -0,0 +1,230 @@
+/// Expansion of message templates, following the rules `FormatMessage` and `EvtFormatMessage` apply to message
+/// table and manifest strings.
+///
+/// Supported escapes:
+/// - `%1` to `%99`: insertion of a value, optionally followed by a printf format like `%1!08X!`.
+/// - `%%NNNN`: insertion of the parameter string `NNNN`, e.g. from the publisher's parameter message file. Unknown
+///   parameters are kept as they are. Insertion values are searched for parameter references as well.
+/// - `%%`: a percent sign, `%n`: a line break, `%t`: a tab, `%r`: a carriage return, `%b`: a space.
+/// - `%0`: end of the message, without the trailing line break.
+/// - `%` followed by any other character: that character, e.g. `%.` or `%!`.
+///
+/// Insertions without a value are kept as they are, like `EvtFormatMessage` does.
+pub fn expand_message<F>(template: &str, insertions: &[String], parameter: F) -> String
+where
+    F: Fn(u32) -> Option<String>,
+{
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            None => result.push('%'),
+            Some('%') => {
+                let digits = take_digits(&mut chars, usize::MAX);
+                if digits.is_empty() {
+                    result.push('%');
+                } else {
+                    result.push_str(&resolve_parameter(&digits, &parameter));
+                }
+            }
+            Some('0') => break,
+            Some(first @ '1'..='9') => {
+                let mut digits = first.to_string();
+                digits.push_str(&take_digits(&mut chars, 1));
+                let index: usize = digits.parse().unwrap_or(0);
+
+                let spec = if chars.peek() == Some(&'!') {
+                    take_printf_spec(&mut chars)
+                } else {
+                    None
+                };
+
+                match insertions.get(index - 1) {
+                    Some(value) => {
+                        let value = expand_parameters(value, &parameter);
+                        match spec {
+                            Some(spec) => result.push_str(&apply_printf_spec(&value, &spec)),
+                            None => result.push_str(&value),
+                        }
+                    }
+                    None => {
+                        result.push('%');
+                        result.push_str(&digits);
+                        if let Some(spec) = spec {
+                            result.push('!');
+                            result.push_str(&spec);
+                            result.push('!');
+                        }
+                    }
+                }
+            }
+            Some('n') => result.push_str("\r\n"),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('b') => result.push(' '),
+            Some(other) => result.push(other),
+        }
+    }
+
+    result
+}
+
+/// Replace all `%%NNNN` parameter references in `text`. Unknown parameters are kept as they are.
+pub fn expand_parameters<F>(text: &str, parameter: F) -> String
+where
+    F: Fn(u32) -> Option<String>,
+{
+    if !text.contains("%%") {
+        return text.to_owned();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(position) = rest.find("%%") {
+        result.push_str(&rest[..position]);
+        rest = &rest[position + 2..];
+
+        let digits_len = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits_len == 0 {
+            result.push_str("%%");
+            continue;
+        }
+
+        result.push_str(&resolve_parameter(&rest[..digits_len], &parameter));
+        rest = &rest[digits_len..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn resolve_parameter<F>(digits: &str, parameter: &F) -> String
+where
+    F: Fn(u32) -> Option<String>,
+{
+    digits
+        .parse()
+        .ok()
+        .and_then(parameter)
+        // Parameter strings come from message tables and usually end with a line break.
+        .map(|value| value.trim_end_matches(['\r', '\n']).to_owned())
+        .unwrap_or_else(|| format!("%%{}", digits))
+}
+
+/// Largest field width of a printf format spec, so a template like `%1!999999999999s!` cannot exhaust memory.
+const MAX_PRINTF_WIDTH: usize = 4096;
+
+fn take_digits<I>(chars: &mut std::iter::Peekable<I>, max: usize) -> String
+where
+    I: Iterator<Item = char>,
+{
+    let mut digits = String::new();
+    while digits.len() < max {
+        match chars.peek() {
+            Some(c) if c.is_ascii_digit() => {
+                digits.push(*c);
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    digits
+}
+
+/// Take a printf format spec enclosed in `!`, e.g. `!08X!`, without the enclosing `!`.
+///
+/// Without a closing `!` nothing is taken, so the rest of the template is kept as it is.
+fn take_printf_spec<I>(chars: &mut std::iter::Peekable<I>) -> Option<String>
+where
+    I: Iterator<Item = char> + Clone,
+{
+    let mut lookahead = chars.clone();
+    lookahead.next();
+
+    let mut spec = String::new();
+    while let Some(c) = lookahead.next() {
+        if c == '!' {
+            *chars = lookahead;
+            return Some(spec);
+        }
+        spec.push(c);
+    }
+    None
+}
+
+/// Apply a printf format spec like `s`, `08X` or `-10s` to an insertion value.
+///
+/// Values are already rendered as text, so numeric conversions parse them back first. Values that are not numbers
+/// are inserted unformatted. Widths above `MAX_PRINTF_WIDTH` are clamped.
+fn apply_printf_spec(value: &str, spec: &str) -> String {
+    let conversion = match spec.chars().last() {
+        Some(c) => c,
+        None => return value.to_owned(),
+    };
+    let mut flags_and_width = &spec[..spec.len() - conversion.len_utf8()];
+    for size_prefix in ["I64", "I32", "ll", "hh", "l", "h", "I"] {
+        if let Some(stripped) = flags_and_width.strip_suffix(size_prefix) {
+            flags_and_width = stripped;
+            break;
+        }
+    }
+
+    let left_align = flags_and_width.starts_with('-');
+    let alternate = flags_and_width.contains('#');
+    let digits = flags_and_width.trim_start_matches(['-', '#', '+', ' ']);
+    let zero_pad = digits.starts_with('0');
+    let width: usize = digits
+        .split('.')
+        .next()
+        .and_then(|width| width.parse().ok())
+        .unwrap_or(0)
+        .min(MAX_PRINTF_WIDTH);
+
+    let formatted = match conversion {
+        'd' | 'i' | 'u' => parse_integer(value).map(|number| number.to_string()),
+        'x' => parse_integer(value).map(|number| format!("{:x}", number)),
+        'X' => parse_integer(value).map(|number| format!("{:X}", number)),
+        'o' => parse_integer(value).map(|number| format!("{:o}", number)),
+        _ => None,
+    };
+
+    let (prefix, body, numeric) = match formatted {
+        Some(body) if alternate && matches!(conversion, 'x' | 'X') => {
+            (if conversion == 'x' { "0x" } else { "0X" }, body, true)
+        }
+        Some(body) => ("", body, true),
+        None => ("", value.to_owned(), false),
+    };
+
+    let length = prefix.len() + body.chars().count();
+    if length >= width {
+        return format!("{}{}", prefix, body);
+    }
+
+    let padding = width - length;
+    if left_align {
+        format!("{}{}{}", prefix, body, " ".repeat(padding))
+    } else if zero_pad && numeric {
+        format!("{}{}{}", prefix, "0".repeat(padding), body)
+    } else {
+        format!("{}{}{}", " ".repeat(padding), prefix, body)
+    }
+}
+
+fn parse_integer(value: &str) -> Option<i128> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
@@ -1,14 +1,12 @@
 use std::ffi::c_void;
-use std::fmt;
 use std::ptr::{null, null_mut};
-use std::slice::from_raw_parts;
 
-use chrono::{DateTime, NaiveDateTime, Utc};
 use windows_result::{Error as WindowsError, HRESULT};
 use windows_strings::HSTRING;
-use windows_sys::core::{GUID, PCWSTR};
+use windows_sys::core::{BOOL, GUID, PCWSTR};
 use windows_sys::Win32::Foundation::{
-    CloseHandle, ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS, FALSE, TRUE, WAIT_OBJECT_0,
+    CloseHandle, ERROR_EVT_UNRESOLVED_PARAMETER_INSERT, ERROR_EVT_UNRESOLVED_VALUE_INSERT,
+    ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS, FALSE, TRUE, WAIT_OBJECT_0,
 };
 use windows_sys::Win32::Security::SID;
 use windows_sys::Win32::System::EventLog::*;
@@ -16,7 +14,14 @@ use windows_sys::Win32::System::Threading::{
     CreateEventW, ResetEvent, WaitForSingleObject, INFINITE,
 };
 
+use crate::catalog::{locale_name, message_catalog};
 use crate::conversions::*;
+use crate::event_data::{field_names_from_event_xml, EventData};
+use crate::publisher::with_publisher_metadata;
+use crate::render_cache::{recycle_render_buffer, take_render_buffer, with_render_context};
+use crate::session::{session_handle, session_target, RemoteSession};
+pub use crate::variant::{format_guid, EventVariantValue};
+use crate::variant_ref::EventVariantRef;
 
 static ZERO_BUFFER_SIZE: u32 = 0;
 static NULL_EVT_HANDLE: EVT_HANDLE = 0 as EVT_HANDLE;
@@ -81,8 +86,36 @@ impl WindowsEventHandle for OwnedWindowsEventHandle {
 pub trait WindowsEventRender {
     fn render_system_context(&self) -> Result<EventSystemContext, String>;
     fn render_user_context(&self) -> Result<Vec<EventVariantValue>, String>;
+    fn render_event_data(&self) -> Result<EventData, String>;
+    fn render_event_data_with_names(&self, names: &[String]) -> Result<EventData, String>;
     fn render_xml(&self) -> Result<String, String>;
     fn render_message(&self) -> Result<String, String>;
+    fn render_message_in_session(
+        &self,
+        session: Option<&RemoteSession>,
+        locale: u32,
+    ) -> Result<String, String>;
+    fn render_strings(
+        &self,
+        session: Option<&RemoteSession>,
+        locale: u32,
+    ) -> Result<RenderedStrings, String>;
+}
+
+/// All strings `EvtFormatMessage` can produce for an event.
+///
+/// Strings the publisher does not define, e.g. the task of an event without a task, are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderedStrings {
+    pub message: Option<String>,
+    pub level: Option<String>,
+    pub task: Option<String>,
+    pub opcode: Option<String>,
+    pub keywords: Vec<String>,
+    pub channel: Option<String>,
+    pub provider: Option<String>,
+    /// The event XML, including a `RenderingInfo` section with the other strings.
+    pub xml: Option<String>,
 }
 
 impl<T> WindowsEventRender for T
@@ -97,7 +130,7 @@ where
             EvtRenderEventValues,
         )?;
         let buffer = unsafe { EventVariantBuffer::from_raw_buffer(raw_buffer, property_count) };
-        Ok(unsafe { EventSystemContext::from_variant_buffer(&buffer) })
+        EventSystemContext::from_variant_buffer(&buffer)
     }
 
     fn render_user_context(&self) -> Result<Vec<EventVariantValue>, String> {
@@ -111,12 +144,41 @@ where
         Ok(buffer.into_iter().collect())
     }
 
-    fn render_xml<'x>(&'x self) -> Result<String, String> {
+    /// Render the user data of the event with the field names of its XML.
+    ///
+    /// Fields without a name, e.g. of classic events, get positional names (`param1`, ...).
+    fn render_event_data(&self) -> Result<EventData, String> {
+        let names = field_names_from_event_xml(&self.render_xml()?)?;
+        self.render_event_data_with_names(&names)
+    }
+
+    /// Render the user data of the event with known field names, e.g. from the publisher's event template.
+    ///
+    /// This avoids rendering the event XML, which makes it the faster choice when the names are cached.
+    fn render_event_data_with_names(&self, names: &[String]) -> Result<EventData, String> {
+        Ok(EventData::new(self.render_user_context()?, names))
+    }
+
+    fn render_xml(&self) -> Result<String, String> {
         let (raw_buffer, _) = event_render_generic(self.get_handle(), &[], 0, EvtRenderEventXml)?;
-        Ok((raw_buffer.as_ptr() as *const u16).win_into())
+        let xml = (raw_buffer.as_ptr() as *const u16).win_into();
+        recycle_render_buffer(raw_buffer);
+        Ok(xml)
     }
 
     fn render_message(&self) -> Result<String, String> {
+        self.render_message_in_session(None, 0) // LANG_NEUTRAL and SORT_DEFAULT
+    }
+
+    /// Render the message of the event, in the language of `locale` if the publisher provides it.
+    ///
+    /// Events that carry their own `RenderingInfo` (e.g. forwarded events) are not formatted again. If the publisher
+    /// metadata cannot be used, the message is formatted with the global message catalog (see `set_message_catalog`).
+    fn render_message_in_session(
+        &self,
+        session: Option<&RemoteSession>,
+        locale: u32,
+    ) -> Result<String, String> {
         let pathspec_system_provider = HSTRING::from("Event/System/Provider/@Name");
         let pathspec_rendering_inf = HSTRING::from("Event/RenderingInfo/Message");
 
@@ -135,54 +197,131 @@ where
         let buffer = unsafe { EventVariantBuffer::from_raw_buffer(raw_buffer, property_count) };
 
         match buffer.get_property_value(1) {
-            Some(EventVariantValue::String(str)) => return Ok(str),
+            Some(EventVariantValue::String(str)) => Ok(str),
             Some(EventVariantValue::Null) => {
-                let provider_name = HSTRING::from(match buffer.get_property_value(0) {
+                let provider_name = match buffer.get_property_value(0) {
                     Some(EventVariantValue::String(str)) => str,
                     _ => return Err("Unexpected result on provider name query".to_owned()),
-                });
-
-                let metadata_handle = unsafe {
-                    EvtOpenPublisherMetadata(
-                        0 as EVT_HANDLE,
-                        provider_name.as_ptr(),
-                        null(),
-                        0, // LANG_NEUTRAL and SORT_DEFAULT
+                };
+
+                with_publisher_metadata(session, &provider_name, locale, |metadata| {
+                    format_message(
+                        *metadata.get_handle(),
+                        *self.get_handle(),
                         0,
+                        EvtFormatMessageEvent,
                     )
-                };
+                    .map_err(|error| format!("Error during message formatting: {:?}", error.code()))
+                })
+                .or_else(|error| {
+                    // The publisher may not be installed here, e.g. for forwarded or exported events.
+                    format_message_from_catalog(self, &provider_name, locale).ok_or(error)
+                })
+            }
+            _ => Err("Unexpected result on message query".to_owned()),
+        }
+    }
 
-                let result = event_format_message(self, metadata_handle, 512);
+    /// Render all formatted strings of the event, in the language of `locale` if the publisher provides it.
+    ///
+    /// Events that carry their own `RenderingInfo` (e.g. forwarded events) are not formatted again. Their
+    /// `RenderingInfo` only provides the first keyword.
+    fn render_strings(
+        &self,
+        session: Option<&RemoteSession>,
+        locale: u32,
+    ) -> Result<RenderedStrings, String> {
+        let pathspecs = [
+            "Event/System/Provider/@Name",
+            "Event/RenderingInfo/Message",
+            "Event/RenderingInfo/Level",
+            "Event/RenderingInfo/Task",
+            "Event/RenderingInfo/Opcode",
+            "Event/RenderingInfo/Keywords/Keyword",
+            "Event/RenderingInfo/Channel",
+            "Event/RenderingInfo/Provider",
+        ]
+        .map(HSTRING::from);
+        let pathspec_ptrs = pathspecs.each_ref().map(|pathspec| pathspec.as_ptr());
 
-                let (error, buffer_size) = match result {
-                    Ok(str) => return Ok(str),
-                    Err((err, bs)) => (err, bs),
-                };
+        let (raw_buffer, property_count) = event_render_generic(
+            self.get_handle(),
+            pathspec_ptrs.as_slice(),
+            EvtRenderContextValues,
+            EvtRenderEventValues,
+        )?;
 
-                if error.code() != HRESULT::from_win32(ERROR_INSUFFICIENT_BUFFER) {
-                    return Err(format!(
-                        "Error during initial message formatting: {:?}",
-                        error.code()
-                    ));
-                }
+        let buffer = unsafe { EventVariantBuffer::from_raw_buffer(raw_buffer, property_count) };
+        let string_value = |index: u32| match buffer.get_property_value(index) {
+            Some(EventVariantValue::String(str)) => Some(str),
+            _ => None,
+        };
 
-                let result = event_format_message(self, metadata_handle, buffer_size as usize);
+        if let Some(message) = string_value(1) {
+            return Ok(RenderedStrings {
+                message: Some(message),
+                level: string_value(2),
+                task: string_value(3),
+                opcode: string_value(4),
+                keywords: string_value(5).into_iter().collect(),
+                channel: string_value(6),
+                provider: string_value(7),
+                xml: self.render_xml().ok(),
+            });
+        }
 
-                match result {
-                    Ok(str) => return Ok(str),
-                    Err((error, _)) => {
-                        return Err(format!(
-                            "Error during message formatting: {:?}",
-                            error.code()
-                        ));
-                    }
-                }
-            }
-            _ => return Err("Unexpected result on message query".to_owned()),
+        let provider_name = match string_value(0) {
+            Some(str) => str,
+            None => return Err("Unexpected result on provider name query".to_owned()),
         };
+
+        with_publisher_metadata(session, &provider_name, locale, |metadata| {
+            // Not every event defines every string, so formatting errors only leave the string empty.
+            let format = |flags: u32| {
+                format_message(*metadata.get_handle(), *self.get_handle(), 0, flags).ok()
+            };
+
+            Ok(RenderedStrings {
+                message: format(EvtFormatMessageEvent),
+                level: format(EvtFormatMessageLevel),
+                task: format(EvtFormatMessageTask),
+                opcode: format(EvtFormatMessageOpcode),
+                keywords: format_message_raw(
+                    *metadata.get_handle(),
+                    *self.get_handle(),
+                    0,
+                    EvtFormatMessageKeyword,
+                )
+                .map(|buffer| split_multi_string(&buffer))
+                .unwrap_or_default(),
+                channel: format(EvtFormatMessageChannel),
+                provider: format(EvtFormatMessageProvider),
+                xml: format(EvtFormatMessageXml),
+            })
+        })
     }
 }
 
+/// Format the message of an event with the global message catalog, if one is set and knows the event.
+fn format_message_from_catalog<T: WindowsEventRender + ?Sized>(
+    event: &T,
+    provider_name: &str,
+    locale: u32,
+) -> Option<String> {
+    let catalog = message_catalog()?;
+    let system = event.render_system_context().ok()?;
+    let values = event.render_user_context().ok()?;
+
+    catalog.format_event_message(
+        provider_name,
+        system.event_id,
+        system.qualifiers.unwrap_or_default(),
+        system.version.unwrap_or_default(),
+        locale_name(locale).unwrap_or("en-US"),
+        &values,
+    )
+}
+
 /// Rust wrapper of an event render context
 pub struct EventRenderContext {
     render_context: EVT_HANDLE,
@@ -203,15 +342,20 @@ impl EventRenderContext {
     /// Create a new render context with the provided parameters.
     ///
     /// # Parameters
-    /// - `valuepathscount`: The number of elements in the `valuepaths` array.
-    /// - `valuepaths`: A pointer to an array of strings that specify the names of the values to be rendered.
+    /// - `valuepaths`: Null-terminated strings that specify the names of the values to be rendered.
     /// - `flags`: Flags that specify which context is created.
-    pub fn create(
-        valuepathscount: u32,
-        valuepaths: *const windows_sys::core::PCWSTR,
-        flags: u32,
-    ) -> Result<Self, WindowsError> {
-        let context = unsafe { EvtCreateRenderContext(valuepathscount, valuepaths, flags) };
+    pub fn create(valuepaths: &[PCWSTR], flags: u32) -> Result<Self, WindowsError> {
+        let context = unsafe {
+            EvtCreateRenderContext(
+                valuepaths.len() as u32,
+                if valuepaths.is_empty() {
+                    null()
+                } else {
+                    valuepaths.as_ptr()
+                },
+                flags,
+            )
+        };
 
         if context == NULL_EVT_HANDLE {
             Err(WindowsError::from_win32())
@@ -239,105 +383,173 @@ impl EventRenderContext {
 
 /// Rust representation of a rendered system context.
 ///
+/// Properties the event does not have, e.g. the user SID of most events, the qualifiers of manifest-based events or
+/// the provider GUID of classic events, are `None`.
+///
 /// See https://learn.microsoft.com/en-us/windows/win32/api/winevt/ne-winevt-evt_system_property_id for system context values
 pub struct EventSystemContext {
     pub provider_name: String,
     pub provider_guid: Option<GUID>,
     pub event_id: u16,
-    pub qualifiers: u16,
-    pub level: u8,
-    pub task: u16,
-    pub opcode: u8,
-    pub keywords: i64,
+    pub qualifiers: Option<u16>,
+    pub level: Option<u8>,
+    pub task: Option<u16>,
+    pub opcode: Option<u8>,
+    pub keywords: Option<i64>,
     pub time_created: u64,
     pub event_record_id: u64,
     pub activity_id: Option<GUID>,
     pub related_activity_id: Option<GUID>,
-    pub process_id: u32,
-    pub thread_id: u32,
-    pub channel: String,
-    pub computer: String,
-    pub user_id: Option<SID>,
-    pub version: u8,
+    pub process_id: Option<u32>,
+    pub thread_id: Option<u32>,
+    pub channel: Option<String>,
+    pub computer: Option<String>,
+    /// Security identifier of the user, in its string form, e.g. `S-1-5-18`.
+    pub user_id: Option<String>,
+    pub version: Option<u8>,
 }
 
+/// Names of the system properties, indexed by `EVT_SYSTEM_PROPERTY_ID`.
+static SYSTEM_PROPERTY_NAMES: [&str; EvtSystemPropertyIdEND as usize] = [
+    "ProviderName",
+    "ProviderGuid",
+    "EventID",
+    "Qualifiers",
+    "Level",
+    "Task",
+    "Opcode",
+    "Keywords",
+    "TimeCreated",
+    "EventRecordId",
+    "ActivityID",
+    "RelatedActivityID",
+    "ProcessID",
+    "ThreadID",
+    "Channel",
+    "Computer",
+    "UserID",
+    "Version",
+];
+
 impl EventSystemContext {
-    /// Extract the system context data from a variant buffer.
+    /// Extract the system context data from a variant buffer rendered with `EvtRenderContextSystem`.
     ///
-    /// # Safety
-    ///
-    /// This function is unsafe because it assumes that the `variant` parameter contains valid system context data.
-    /// No checks are performed to ensure the validity of the data, and dereferencing raw pointers is inherently unsafe.
-    pub unsafe fn from_variant_buffer(variant: &EventVariantBuffer) -> Self {
-        unsafe {
-            Self {
-                provider_name: variant
-                    .index(EvtSystemProviderName as isize)
-                    .Anonymous
-                    .StringVal
-                    .win_into(),
-                provider_guid: variant
-                    .index(EvtSystemProviderGuid as isize)
-                    .Anonymous
-                    .GuidVal
-                    .as_ref()
-                    .cloned(),
-                event_id: variant.index(EvtSystemEventID as isize).Anonymous.UInt16Val,
-                qualifiers: variant
-                    .index(EvtSystemQualifiers as isize)
-                    .Anonymous
-                    .UInt16Val,
-                level: variant.index(EvtSystemLevel as isize).Anonymous.ByteVal,
-                task: variant.index(EvtSystemTask as isize).Anonymous.UInt16Val,
-                opcode: variant.index(EvtSystemOpcode as isize).Anonymous.ByteVal,
-                keywords: variant.index(EvtSystemKeywords as isize).Anonymous.Int64Val,
-                time_created: variant
-                    .index(EvtSystemTimeCreated as isize)
-                    .Anonymous
-                    .UInt64Val,
-                event_record_id: variant
-                    .index(EvtSystemEventRecordId as isize)
-                    .Anonymous
-                    .UInt64Val,
-                activity_id: variant
-                    .index(EvtSystemActivityID as isize)
-                    .Anonymous
-                    .GuidVal
-                    .as_ref()
-                    .cloned(),
-                related_activity_id: variant
-                    .index(EvtSystemRelatedActivityID as isize)
-                    .Anonymous
-                    .GuidVal
-                    .as_ref()
-                    .cloned(),
-                process_id: variant
-                    .index(EvtSystemProcessID as isize)
-                    .Anonymous
-                    .UInt32Val,
-                thread_id: variant
-                    .index(EvtSystemThreadID as isize)
-                    .Anonymous
-                    .UInt32Val,
-                channel: variant
-                    .index(EvtSystemChannel as isize)
-                    .Anonymous
-                    .StringVal
-                    .win_into(),
-                computer: variant
-                    .index(EvtSystemComputer as isize)
-                    .Anonymous
-                    .StringVal
-                    .win_into(),
-                user_id: (variant.index(EvtSystemUserID as isize).Anonymous.SidVal as *const SID)
-                    .as_ref()
-                    .cloned(),
-                version: variant.index(EvtSystemVersion as isize).Anonymous.ByteVal,
-            }
-        }
+    /// The type of every property is checked before its value is read. Null properties and properties beyond the end
+    /// of the buffer are `None`. Fails if the provider name, event ID, creation time or record ID is missing, or if a
+    /// property has an unexpected type.
+    pub fn from_variant_buffer(buffer: &EventVariantBuffer) -> Result<Self, String> {
+        let guid = |id| {
+            system_property(buffer, id, "Guid", |value| match value {
+                EventVariantRef::Guid(guid) => Some(*guid),
+                _ => None,
+            })
+        };
+        let string = |id| {
+            system_property(buffer, id, "String", |value| match value {
+                EventVariantRef::String(value) => Some(value.to_string_lossy()),
+                _ => None,
+            })
+        };
+        let byte = |id| {
+            system_property(buffer, id, "Byte", |value| match value {
+                EventVariantRef::Byte(value) => Some(value),
+                _ => None,
+            })
+        };
+        let uint16 = |id| {
+            system_property(buffer, id, "UInt16", |value| match value {
+                EventVariantRef::UInt16(value) => Some(value),
+                _ => None,
+            })
+        };
+        let uint32 = |id| {
+            system_property(buffer, id, "UInt32", |value| match value {
+                EventVariantRef::UInt32(value) => Some(value),
+                _ => None,
+            })
+        };
+        let uint64 = |id| {
+            system_property(buffer, id, "UInt64", |value| match value {
+                EventVariantRef::UInt64(value) => Some(value),
+                _ => None,
+            })
+        };
+
+        Ok(Self {
+            provider_name: required(EvtSystemProviderName, string(EvtSystemProviderName)?)?,
+            provider_guid: guid(EvtSystemProviderGuid)?,
+            event_id: required(EvtSystemEventID, uint16(EvtSystemEventID)?)?,
+            qualifiers: uint16(EvtSystemQualifiers)?,
+            level: byte(EvtSystemLevel)?,
+            task: uint16(EvtSystemTask)?,
+            opcode: byte(EvtSystemOpcode)?,
+            keywords: system_property(
+                buffer,
+                EvtSystemKeywords,
+                "HexInt64",
+                |value| match value {
+                    EventVariantRef::HexInt64(value) | EventVariantRef::UInt64(value) => {
+                        Some(value as i64)
+                    }
+                    EventVariantRef::Int64(value) => Some(value),
+                    _ => None,
+                },
+            )?,
+            time_created: required(
+                EvtSystemTimeCreated,
+                system_property(
+                    buffer,
+                    EvtSystemTimeCreated,
+                    "FileTime",
+                    |value| match value {
+                        EventVariantRef::FileTime(value) => Some(value),
+                        _ => None,
+                    },
+                )?,
+            )?,
+            event_record_id: required(EvtSystemEventRecordId, uint64(EvtSystemEventRecordId)?)?,
+            activity_id: guid(EvtSystemActivityID)?,
+            related_activity_id: guid(EvtSystemRelatedActivityID)?,
+            process_id: uint32(EvtSystemProcessID)?,
+            thread_id: uint32(EvtSystemThreadID)?,
+            channel: string(EvtSystemChannel)?,
+            computer: string(EvtSystemComputer)?,
+            user_id: system_property(buffer, EvtSystemUserID, "Sid", |value| match value {
+                EventVariantRef::Sid(sid) => Some(format_sid(sid)),
+                _ => None,
+            })?,
+            version: byte(EvtSystemVersion)?,
+        })
+    }
+}
+
+/// Read a system property with `extract`, which returns `None` if the variant is not of the `expected` type.
+fn system_property<'buf, T>(
+    buffer: &'buf EventVariantBuffer,
+    id: EVT_SYSTEM_PROPERTY_ID,
+    expected: &str,
+    extract: impl FnOnce(EventVariantRef<'buf>) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match buffer.get_property_ref(id as u32) {
+        None | Some(EventVariantRef::Null) => Ok(None),
+        Some(value) => extract(value).map(Some).ok_or_else(|| {
+            format!(
+                "Unexpected type of system property {}: expected {}, got {:?}",
+                SYSTEM_PROPERTY_NAMES[id as usize], expected, value
+            )
+        }),
     }
 }
 
+fn required<T>(id: EVT_SYSTEM_PROPERTY_ID, value: Option<T>) -> Result<T, String> {
+    value.ok_or_else(|| {
+        format!(
+            "Missing system property {}",
+            SYSTEM_PROPERTY_NAMES[id as usize]
+        )
+    })
+}
+
 /// Convenience wrapper around a buffer containing `EVENT_VARIANT` objects.
 #[derive(Debug)]
 pub struct EventVariantBuffer {
@@ -348,6 +560,8 @@ pub struct EventVariantBuffer {
 impl EventVariantBuffer {
     /// Create a new wrapper from a raw byte buffer and the number of properties it contains.
     ///
+    /// # Safety
+    ///
     /// Requires the buffer to contain `EVENT_VARIANT` objects as returned by `EvtRender`.
     /// No checks are performed.
     pub unsafe fn from_raw_buffer(buffer: Vec<u8>, property_count: u32) -> Self {
@@ -371,6 +585,20 @@ impl EventVariantBuffer {
         Some((*raw_variant).into())
     }
 
+    /// Get a borrowed view of a property, without copying strings or arrays.
+    pub fn get_property_ref(&self, index: u32) -> Option<EventVariantRef<'_>> {
+        if index >= self.property_count {
+            return None;
+        }
+
+        Some(unsafe { EventVariantRef::from_variant(self.index(index as isize)) })
+    }
+
+    /// Iterate over borrowed views of all properties.
+    pub fn refs(&self) -> impl Iterator<Item = EventVariantRef<'_>> {
+        (0..self.property_count).filter_map(|index| self.get_property_ref(index))
+    }
+
     pub fn as_ptr(&self) -> *const EVT_VARIANT {
         self.buffer.as_ptr() as *const EVT_VARIANT
     }
@@ -379,11 +607,23 @@ impl EventVariantBuffer {
         self.buffer.len()
     }
 
+    /// Get the raw variant of a property.
+    ///
+    /// # Safety
+    ///
+    /// `offset` has to be smaller than `property_count`.
     pub unsafe fn index(&self, offset: isize) -> &EVT_VARIANT {
         unsafe { self.as_ptr().offset(offset).as_ref().unwrap_unchecked() }
     }
 }
 
+/// Return the buffer to the scratch buffers of the current thread, so the next render can reuse it.
+impl Drop for EventVariantBuffer {
+    fn drop(&mut self) {
+        recycle_render_buffer(std::mem::take(&mut self.buffer));
+    }
+}
+
 // Implement the iterator trait for the variant buffer to allow iterating over all variants.
 impl<'a> IntoIterator for &'a EventVariantBuffer {
     type Item = EventVariantValue;
@@ -413,298 +653,11 @@ impl<'a> Iterator for EventVariantBufferIterator<'a> {
     }
 }
 
-/// Rust representation of a EVT_VARIANT, owning all of its data.
-///
-/// This enum and its implementation assumes that any
-/// data field of a variant referenced by pointer is not a nullpointer.
-///
-/// This is not guaranteed if the fields of rendering an event
-/// are fixed, e.g. when rendering a system event.
-///
-/// Bigger data fields are intentionally boxed. No guarantees are provided
-/// regarding the validity of the EvtHandle value.
-///
-pub enum EventVariantValue {
-    Null,
-    Bool(bool),
-    SByte(i8),
-    Int16(i16),
-    Int32(i32),
-    Int64(i64),
-    Byte(u8),
-    UInt16(u16),
-    UInt32(u32),
-    UInt64(u64),
-    Single(f32),
-    Double(f64),
-    FileTime(DateTime<Utc>),
-    SysTime(NaiveDateTime),
-    Guid(Box<GUID>),
-    HexInt32(u32),
-    HexInt64(u64),
-    String(String),
-    AnsiString(String),
-    Binary(Vec<u8>),
-    Sid(Box<SID>),
-    SizeT(usize),
-    BoolArr(Vec<bool>),
-    SByteArr(Vec<i8>),
-    Int16Arr(Vec<i16>),
-    Int32Arr(Vec<i32>),
-    Int64Arr(Vec<i64>),
-    ByteArr(Vec<u8>),
-    UInt16Arr(Vec<u16>),
-    UInt32Arr(Vec<u32>),
-    UInt64Arr(Vec<u64>),
-    SingleArr(Vec<f32>),
-    DoubleArr(Vec<f64>),
-    FileTimeArr(Vec<DateTime<Utc>>),
-    SysTimeArr(Vec<NaiveDateTime>),
-    GuidArr(Vec<GUID>),
-    HexInt32Arr(Vec<u32>),
-    HexInt64Arr(Vec<u64>),
-    StringArr(Vec<String>),
-    AnsiStringArr(Vec<String>),
-    SidArr(Vec<SID>),
-    SizeTArr(Vec<usize>),
-    EvtHandle(EVT_HANDLE),
-    Xml(String),
-    XmlArr(Vec<String>),
-    UnknownType(i32),
-    UnknownTypeArr(i32),
-}
-
-impl fmt::Debug for EventVariantValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            EventVariantValue::Null => write!(f, "Null"),
-            EventVariantValue::Bool(value) => f.debug_tuple("Bool").field(value).finish(),
-            EventVariantValue::SByte(value) => f.debug_tuple("SByte").field(value).finish(),
-            EventVariantValue::Int16(value) => f.debug_tuple("Int16").field(value).finish(),
-            EventVariantValue::Int32(value) => f.debug_tuple("Int32").field(value).finish(),
-            EventVariantValue::Int64(value) => f.debug_tuple("Int64").field(value).finish(),
-            EventVariantValue::Byte(value) => f.debug_tuple("Byte").field(value).finish(),
-            EventVariantValue::UInt16(value) => f.debug_tuple("UInt16").field(value).finish(),
-            EventVariantValue::UInt32(value) => f.debug_tuple("UInt32").field(value).finish(),
-            EventVariantValue::UInt64(value) => f.debug_tuple("UInt64").field(value).finish(),
-            EventVariantValue::Single(value) => f.debug_tuple("Single").field(value).finish(),
-            EventVariantValue::Double(value) => f.debug_tuple("Double").field(value).finish(),
-            EventVariantValue::FileTime(value) => f.debug_tuple("FileTime").field(value).finish(),
-            EventVariantValue::SysTime(value) => f.debug_tuple("SysTime").field(value).finish(),
-            EventVariantValue::Guid(value) => write!(f, "Guid({})", format_guid(value)),
-            EventVariantValue::HexInt32(value) => write!(f, "HexInt32(0x{:08X})", value),
-            EventVariantValue::HexInt64(value) => write!(f, "HexInt64(0x{:016X})", value),
-            EventVariantValue::String(value) => f.debug_tuple("String").field(value).finish(),
-            EventVariantValue::AnsiString(value) => {
-                f.debug_tuple("AnsiString").field(value).finish()
-            }
-            EventVariantValue::Binary(value) => write!(
-                f,
-                "Binary({:?})",
-                value
-                    .iter()
-                    .map(|b| format!("{:02X}", b))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            ),
-            EventVariantValue::Sid(value) => write!(f, "Sid({})", format_sid(value)),
-            EventVariantValue::SizeT(value) => f.debug_tuple("SizeT").field(value).finish(),
-            EventVariantValue::BoolArr(value) => f.debug_tuple("BoolArr").field(value).finish(),
-            EventVariantValue::SByteArr(value) => f.debug_tuple("SByteArr").field(value).finish(),
-            EventVariantValue::Int16Arr(value) => f.debug_tuple("Int16Arr").field(value).finish(),
-            EventVariantValue::Int32Arr(value) => f.debug_tuple("Int32Arr").field(value).finish(),
-            EventVariantValue::Int64Arr(value) => f.debug_tuple("Int64Arr").field(value).finish(),
-            EventVariantValue::ByteArr(value) => f.debug_tuple("ByteArr").field(value).finish(),
-            EventVariantValue::UInt16Arr(value) => f.debug_tuple("UInt16Arr").field(value).finish(),
-            EventVariantValue::UInt32Arr(value) => f.debug_tuple("UInt32Arr").field(value).finish(),
-            EventVariantValue::UInt64Arr(value) => f.debug_tuple("UInt64Arr").field(value).finish(),
-            EventVariantValue::SingleArr(value) => f.debug_tuple("SingleArr").field(value).finish(),
-            EventVariantValue::DoubleArr(value) => f.debug_tuple("DoubleArr").field(value).finish(),
-            EventVariantValue::FileTimeArr(value) => {
-                f.debug_tuple("FileTimeArr").field(value).finish()
-            }
-            EventVariantValue::SysTimeArr(value) => {
-                f.debug_tuple("SysTimeArr").field(value).finish()
-            }
-            EventVariantValue::GuidArr(value) => {
-                let formatted: Vec<String> = value.iter().map(|g| format_guid(g)).collect();
-                write!(f, "GuidArr({:?})", formatted)
-            }
-            EventVariantValue::HexInt32Arr(value) => {
-                write!(
-                    f,
-                    "HexInt32Arr({:?})",
-                    value
-                        .iter()
-                        .map(|v| format!("0x{:08X}", v))
-                        .collect::<Vec<_>>()
-                )
-            }
-            EventVariantValue::HexInt64Arr(value) => {
-                write!(
-                    f,
-                    "HexInt64Arr({:?})",
-                    value
-                        .iter()
-                        .map(|v| format!("0x{:016X}", v))
-                        .collect::<Vec<_>>()
-                )
-            }
-            EventVariantValue::StringArr(value) => f.debug_tuple("StringArr").field(value).finish(),
-            EventVariantValue::AnsiStringArr(value) => {
-                f.debug_tuple("AnsiStringArr").field(value).finish()
-            }
-            EventVariantValue::SidArr(value) => {
-                let formatted: Vec<String> = value.iter().map(|s| format_sid(s)).collect();
-                write!(f, "SidArr({:?})", formatted)
-            }
-            EventVariantValue::SizeTArr(value) => f.debug_tuple("SizeTArr").field(value).finish(),
-            EventVariantValue::EvtHandle(value) => f.debug_tuple("EvtHandle").field(value).finish(),
-            EventVariantValue::Xml(value) => f.debug_tuple("Xml").field(value).finish(),
-            EventVariantValue::XmlArr(value) => f.debug_tuple("XmlArr").field(value).finish(),
-            EventVariantValue::UnknownType(value) => {
-                f.debug_tuple("UnknownType").field(value).finish()
-            }
-            EventVariantValue::UnknownTypeArr(value) => {
-                f.debug_tuple("UnknownTypeArr").field(value).finish()
-            }
-        }
-    }
-}
-
+/// Copy a variant rendered by `EvtRender` or one of the `EvtGet*Property` functions, see
+/// `EventVariantRef::to_owned_value`.
 impl From<EVT_VARIANT> for EventVariantValue {
     fn from(value: EVT_VARIANT) -> Self {
-        let is_array = (value.Type & EVT_VARIANT_TYPE_ARRAY) != 0;
-        let value_type = (value.Type & EVT_VARIANT_TYPE_MASK) as i32;
-        let count = value.Count as usize;
-
-        if is_array {
-            unsafe {
-                #![allow(nonstandard_style)]
-                match value_type {
-                    EvtVarTypeString => Self::StringArr(
-                        from_raw_parts(value.Anonymous.StringArr as *const PCWSTR, count)
-                            .iter()
-                            .map(|s| (*s).win_into())
-                            .collect(),
-                    ),
-                    EvtVarTypeAnsiString => Self::AnsiStringArr(
-                        from_raw_parts(value.Anonymous.AnsiStringArr as *const PCWSTR, count)
-                            .iter()
-                            .map(|s| (*s).win_into())
-                            .collect(),
-                    ),
-                    EvtVarTypeSByte => {
-                        Self::SByteArr(from_raw_parts(value.Anonymous.SByteArr, count).to_vec())
-                    }
-                    EvtVarTypeByte => {
-                        Self::ByteArr(from_raw_parts(value.Anonymous.ByteArr, count).to_vec())
-                    }
-                    EvtVarTypeInt16 => {
-                        Self::Int16Arr(from_raw_parts(value.Anonymous.Int16Arr, count).to_vec())
-                    }
-                    EvtVarTypeUInt16 => {
-                        Self::UInt16Arr(from_raw_parts(value.Anonymous.UInt16Arr, count).to_vec())
-                    }
-                    EvtVarTypeInt32 => {
-                        Self::Int32Arr(from_raw_parts(value.Anonymous.Int32Arr, count).to_vec())
-                    }
-                    EvtVarTypeUInt32 => {
-                        Self::UInt32Arr(from_raw_parts(value.Anonymous.UInt32Arr, count).to_vec())
-                    }
-                    EvtVarTypeInt64 => {
-                        Self::Int64Arr(from_raw_parts(value.Anonymous.Int64Arr, count).to_vec())
-                    }
-                    EvtVarTypeUInt64 => {
-                        Self::UInt64Arr(from_raw_parts(value.Anonymous.UInt64Arr, count).to_vec())
-                    }
-                    EvtVarTypeSingle => {
-                        Self::SingleArr(from_raw_parts(value.Anonymous.SingleArr, count).to_vec())
-                    }
-                    EvtVarTypeDouble => {
-                        Self::DoubleArr(from_raw_parts(value.Anonymous.DoubleArr, count).to_vec())
-                    }
-                    EvtVarTypeBoolean => Self::BoolArr(
-                        from_raw_parts(value.Anonymous.BooleanArr, count)
-                            .iter()
-                            .map(|b| *b != 0)
-                            .collect(),
-                    ),
-                    EvtVarTypeGuid => {
-                        Self::GuidArr(from_raw_parts(value.Anonymous.GuidArr, count).to_vec())
-                    }
-                    EvtVarTypeSizeT => {
-                        Self::SizeTArr(from_raw_parts(value.Anonymous.SizeTArr, count).to_vec())
-                    }
-                    EvtVarTypeFileTime => Self::FileTimeArr(
-                        from_raw_parts(value.Anonymous.FileTimeArr, count)
-                            .iter()
-                            .map(|f| (*f).win_into())
-                            .collect(),
-                    ),
-                    EvtVarTypeSysTime => Self::SysTimeArr(
-                        from_raw_parts(value.Anonymous.SysTimeArr, count)
-                            .iter()
-                            .map(|s| (*s).win_into())
-                            .collect(),
-                    ),
-                    EvtVarTypeSid => Self::SidArr(
-                        from_raw_parts(value.Anonymous.SidArr as *const *const SID, count)
-                            .iter()
-                            .map(|s| **s)
-                            .collect(),
-                    ),
-                    EvtVarTypeHexInt32 => {
-                        Self::HexInt32Arr(from_raw_parts(value.Anonymous.UInt32Arr, count).to_vec())
-                    }
-                    EvtVarTypeHexInt64 => {
-                        Self::HexInt64Arr(from_raw_parts(value.Anonymous.UInt64Arr, count).to_vec())
-                    }
-                    EvtVarTypeEvtXml => Self::XmlArr(
-                        from_raw_parts(value.Anonymous.XmlValArr, count)
-                            .iter()
-                            .map(|s| (*s as PCWSTR).win_into())
-                            .collect(),
-                    ),
-                    _ => Self::UnknownTypeArr(value_type),
-                }
-            }
-        } else {
-            unsafe {
-                #![allow(nonstandard_style)]
-                match value_type {
-                    EvtVarTypeNull => Self::Null,
-                    EvtVarTypeString => Self::String(value.Anonymous.StringVal.win_into()),
-                    EvtVarTypeAnsiString => {
-                        Self::AnsiString(value.Anonymous.AnsiStringVal.win_into())
-                    }
-                    EvtVarTypeSByte => Self::SByte(value.Anonymous.SByteVal),
-                    EvtVarTypeByte => Self::Byte(value.Anonymous.ByteVal),
-                    EvtVarTypeInt16 => Self::Int16(value.Anonymous.Int16Val),
-                    EvtVarTypeUInt16 => Self::UInt16(value.Anonymous.UInt16Val),
-                    EvtVarTypeInt32 => Self::Int32(value.Anonymous.Int32Val),
-                    EvtVarTypeUInt32 => Self::UInt32(value.Anonymous.UInt32Val),
-                    EvtVarTypeInt64 => Self::Int64(value.Anonymous.Int64Val),
-                    EvtVarTypeUInt64 => Self::UInt64(value.Anonymous.UInt64Val),
-                    EvtVarTypeSingle => Self::Single(value.Anonymous.SingleVal),
-                    EvtVarTypeDouble => Self::Double(value.Anonymous.DoubleVal),
-                    EvtVarTypeBoolean => Self::Bool(value.Anonymous.BooleanVal != 0),
-                    EvtVarTypeBinary => {
-                        Self::Binary(from_raw_parts(value.Anonymous.BinaryVal, count).to_vec())
-                    }
-                    EvtVarTypeGuid => Self::Guid(Box::new(*value.Anonymous.GuidVal)),
-                    EvtVarTypeSizeT => Self::SizeT(value.Anonymous.SizeTVal),
-                    EvtVarTypeFileTime => Self::FileTime(value.Anonymous.FileTimeVal.win_into()),
-                    EvtVarTypeSysTime => Self::SysTime((*value.Anonymous.SysTimeVal).win_into()),
-                    EvtVarTypeSid => Self::Sid(Box::new(*(value.Anonymous.SidVal as *const SID))),
-                    EvtVarTypeHexInt32 => Self::HexInt32(value.Anonymous.UInt32Val),
-                    EvtVarTypeHexInt64 => Self::HexInt64(value.Anonymous.UInt64Val),
-                    EvtVarTypeEvtHandle => Self::EvtHandle(value.Anonymous.EvtHandleVal),
-                    EvtVarTypeEvtXml => Self::Xml(value.Anonymous.XmlVal.win_into()),
-                    _ => Self::UnknownType(value_type),
-                }
-            }
-        }
+        unsafe { EventVariantRef::from_variant(&value) }.to_owned_value()
     }
 }
 
@@ -712,7 +665,7 @@ pub struct WindowsThreadingEvent {
     handle: std::ptr::NonNull<c_void>,
 }
 
-impl<'a> WindowsThreadingEvent {
+impl WindowsThreadingEvent {
     pub fn new() -> Result<Self, String> {
         let handle: *mut c_void = unsafe {
             CreateEventW(
@@ -784,6 +737,7 @@ impl WindowsEventLogBookmark {
     pub fn to_xml(&self) -> Result<String, String> {
         let (buffer, _) = event_render_generic(&self.handle, &[], 0, EvtRenderBookmark)?;
         let xml = (buffer.as_ptr() as *const u16).win_into();
+        recycle_render_buffer(buffer);
         Ok(xml)
     }
 
@@ -811,25 +765,40 @@ impl Drop for WindowsEventLogBookmark {
 pub struct WindowsEventLogPollingSubscription {
     handle: EVT_HANDLE,
     event: WindowsThreadingEvent,
+    // Keeps the remote session open for as long as the subscription exists.
+    _session: Option<RemoteSession>,
 }
 
 impl WindowsEventLogPollingSubscription {
     pub fn new(
         channel: &str,
         query: Option<&str>,
-        bookmark: Option<WindowsEventLogBookmark>,
+        bookmark: Option<&WindowsEventLogBookmark>,
+    ) -> Result<Self, String> {
+        Self::new_with_session(None, channel, query, bookmark)
+    }
+
+    /// Subscribe to a channel on the computer of `session`, or on the local computer if `session` is `None`.
+    ///
+    /// With a bookmark, the subscription starts after the bookmarked event, otherwise at the oldest event. The
+    /// bookmark is only read here, so it can be updated afterwards.
+    pub fn new_with_session(
+        session: Option<&RemoteSession>,
+        channel: &str,
+        query: Option<&str>,
+        bookmark: Option<&WindowsEventLogBookmark>,
     ) -> Result<Self, String> {
         let event = WindowsThreadingEvent::new()?;
         let channel = HSTRING::from(channel);
-        let query = query.map(|q| HSTRING::from(q));
+        let query = query.map(HSTRING::from);
 
         let handle: EVT_HANDLE = unsafe {
             EvtSubscribe(
-                NULL_EVT_HANDLE,
+                session_handle(session),
                 event.get_handle(),
                 channel.as_ptr(),
                 query.as_ref().map_or(null(), |q| q.as_ptr()),
-                NULL_EVT_HANDLE, // TODO: Implement bookmark support
+                bookmark.map_or(NULL_EVT_HANDLE, |bookmark| bookmark.handle),
                 null(),
                 None,
                 if bookmark.is_some() {
@@ -843,23 +812,50 @@ impl WindowsEventLogPollingSubscription {
         if handle == 0 {
             let last_error = WindowsError::from_win32();
             return Err(format!(
-                "Received unexpected error while subscribing to events: {:?}",
+                "Received unexpected error while subscribing to events on {}: {:?}",
+                session_target(session),
                 last_error.message()
             ));
         }
 
-        Ok(Self { handle, event })
+        Ok(Self {
+            handle,
+            event,
+            _session: session.cloned(),
+        })
+    }
+
+    /// Pass new events to `f` as they arrive, in batches of up to `max_events` events. Never returns.
+    ///
+    /// `_timeout` is not used; the subscription waits until new events are signaled.
+    pub fn read_events_blocking<F>(&self, f: F, max_events: usize, _timeout: u32)
+    where
+        F: Fn(&OwnedWindowsEventHandle),
+    {
+        self.read_events_blocking_with_idle(f, || {}, max_events)
     }
 
-    pub fn read_events_blocking<F>(&self, f: F, max_events: usize, timeout: u32)
+    /// Like `read_events_blocking`, but calls `idle` whenever all available events were passed to `f`, before waiting
+    /// for new events.
+    ///
+    /// Buffering sinks should be flushed in `idle`, so events are not held back until the next events arrive.
+    ///
+    /// Returns right away if `max_events` is zero.
+    pub fn read_events_blocking_with_idle<F, I>(&self, f: F, idle: I, max_events: usize)
     where
         F: Fn(&OwnedWindowsEventHandle),
+        I: Fn(),
     {
+        if max_events == 0 {
+            eprintln!("Batch size must be greater than zero");
+            return;
+        }
+
         let mut buffer: Vec<EVT_HANDLE> = Vec::with_capacity(max_events);
         let mut events_returned: u32 = 0;
 
         loop {
-            println!("Waiting for events...");
+            eprintln!("Waiting for events...");
             let wait_result = unsafe {
                 WaitForSingleObject(
                     self.event.get_handle(),
@@ -868,7 +864,7 @@ impl WindowsEventLogPollingSubscription {
             };
 
             if wait_result == WAIT_OBJECT_0 {
-                println!("Event signaled, processing events...");
+                eprintln!("Event signaled, processing events...");
 
                 // The event was signaled, meaning new events are available
                 while unsafe {
@@ -888,9 +884,9 @@ impl WindowsEventLogPollingSubscription {
                         break;
                     }
 
-                    println!("-----------------------------------");
-                    println!("Received {} events", events_returned);
-                    println!("-----------------------------------");
+                    eprintln!("-----------------------------------");
+                    eprintln!("Received {} events", events_returned);
+                    eprintln!("-----------------------------------");
 
                     for event_handle in buffer.iter() {
                         let event = OwnedWindowsEventHandle::new(*event_handle);
@@ -900,7 +896,7 @@ impl WindowsEventLogPollingSubscription {
 
                 let last_error = WindowsError::from_win32();
                 if last_error.code() != HRESULT::from_win32(ERROR_NO_MORE_ITEMS) {
-                    println!(
+                    eprintln!(
                         "EvtNext failed: {:?} ({:?})",
                         last_error.message(),
                         last_error.code()
@@ -908,10 +904,12 @@ impl WindowsEventLogPollingSubscription {
                     break;
                 }
 
+                idle();
+
                 // Reset the event to wait for new events again
                 if unsafe { ResetEvent(self.event.get_handle()) } == FALSE {
                     let last_error = WindowsError::from_win32();
-                    println!(
+                    eprintln!(
                         "ResetEvent failed: {:?} ({:?})",
                         last_error.message(),
                         last_error.code()
@@ -919,7 +917,7 @@ impl WindowsEventLogPollingSubscription {
                 }
             } else {
                 let last_error = WindowsError::from_win32();
-                println!(
+                eprintln!(
                     "WaitForSingleObject failed: {:?} ({:?})",
                     last_error.message(),
                     last_error.code()
@@ -939,137 +937,320 @@ impl Drop for WindowsEventLogPollingSubscription {
     }
 }
 
+/// Wrapper around a result set returned by `EvtQuery`.
+pub struct WindowsEventLogQuery {
+    handle: EVT_HANDLE,
+    // Keeps the remote session open for as long as the query exists.
+    _session: Option<RemoteSession>,
+}
+
+impl WindowsEventLogQuery {
+    /// Query the events of a channel.
+    ///
+    /// # Parameters
+    /// - `session`: Session of the remote computer to query, or `None` for the local computer.
+    /// - `channel`: Name of the channel to query.
+    /// - `query`: XPath query selecting the events, or `None` to select all events.
+    /// - `reverse`: Return the newest events first.
+    pub fn new(
+        session: Option<&RemoteSession>,
+        channel: &str,
+        query: Option<&str>,
+        reverse: bool,
+    ) -> Result<Self, String> {
+        let channel = HSTRING::from(channel);
+        let query = query.map(HSTRING::from);
+
+        let handle: EVT_HANDLE = unsafe {
+            EvtQuery(
+                session_handle(session),
+                channel.as_ptr(),
+                query.as_ref().map_or(null(), |q| q.as_ptr()),
+                EvtQueryChannelPath
+                    | if reverse {
+                        EvtQueryReverseDirection
+                    } else {
+                        EvtQueryForwardDirection
+                    },
+            )
+        };
+
+        if handle == NULL_EVT_HANDLE {
+            let last_error = WindowsError::from_win32();
+            return Err(format!(
+                "Failed to query events on {}: {:?}",
+                session_target(session),
+                last_error.message()
+            ));
+        }
+
+        Ok(Self {
+            handle,
+            _session: session.cloned(),
+        })
+    }
+
+    /// Read all remaining events of the result set, in batches of `batch_size` events.
+    ///
+    /// Returns the number of events passed to `f`. Fails if `batch_size` is zero.
+    pub fn read_events<F>(&self, mut f: F, batch_size: usize, timeout: u32) -> Result<usize, String>
+    where
+        F: FnMut(&OwnedWindowsEventHandle),
+    {
+        if batch_size == 0 {
+            return Err("Batch size must be greater than zero".to_owned());
+        }
+
+        let mut buffer: Vec<EVT_HANDLE> = Vec::with_capacity(batch_size);
+        let mut events_returned: u32 = 0;
+        let mut total: usize = 0;
+
+        while unsafe {
+            EvtNext(
+                self.handle,
+                buffer.capacity() as u32,
+                buffer.as_mut_ptr(),
+                timeout,
+                0,
+                &mut events_returned,
+            )
+        } == TRUE
+        {
+            unsafe { buffer.set_len(events_returned as usize) };
+
+            for event_handle in buffer.iter() {
+                let event = OwnedWindowsEventHandle::new(*event_handle);
+                f(&event);
+            }
+
+            total += events_returned as usize;
+        }
+
+        let last_error = WindowsError::from_win32();
+        if last_error.code() != HRESULT::from_win32(ERROR_NO_MORE_ITEMS) {
+            return Err(format!(
+                "EvtNext failed: {:?} ({:?})",
+                last_error.message(),
+                last_error.code()
+            ));
+        }
+
+        Ok(total)
+    }
+}
+
+impl Drop for WindowsEventLogQuery {
+    fn drop(&mut self) {
+        if self.handle != NULL_EVT_HANDLE {
+            unsafe {
+                EvtClose(self.handle);
+            }
+        }
+    }
+}
+
 fn event_render_generic(
     event: &EVT_HANDLE,
     valuepaths: &[PCWSTR],
     context_flags: u32,
     render_flags: u32,
 ) -> Result<(Vec<u8>, u32), String> {
-    let mut buffer_used: u32 = 0;
-    let mut property_count: u32 = 0;
-
-    let render_context = if render_flags == EvtRenderEventXml || render_flags == EvtRenderBookmark {
+    if render_flags == EvtRenderEventXml || render_flags == EvtRenderBookmark {
         // For rendering XML or bookmarks, context has to be NULL
-        EventRenderContext::create_null()
-    } else {
-        match EventRenderContext::create(
-            valuepaths.len() as u32,
-            if valuepaths.len() > 1 {
-                valuepaths.as_ptr()
-            } else {
-                null()
-            },
-            context_flags,
-        ) {
-            Ok(context) => context,
-            Err(error) => {
-                return Err(format!(
+        return event_render_into_buffer(&EventRenderContext::create_null(), event, render_flags);
+    }
+
+    with_render_context(
+        context_flags,
+        valuepaths,
+        || {
+            EventRenderContext::create(valuepaths, context_flags).map_err(|error| {
+                format!(
                     "Error trying to create render context: {:?}",
                     error.message()
-                ));
-            }
-        }
-    };
+                )
+            })
+        },
+        |render_context| event_render_into_buffer(render_context, event, render_flags),
+    )?
+}
+
+/// Render an event into a scratch buffer of the current thread.
+///
+/// The buffer is only probed for its size and grown if it is too small, so rendering similar events one after another
+/// needs a single `EvtRender` call and no allocation.
+pub fn event_render_into_buffer(
+    render_context: &EventRenderContext,
+    event: &EVT_HANDLE,
+    render_flags: u32,
+) -> Result<(Vec<u8>, u32), String> {
+    let mut buffer = take_render_buffer();
+    let mut buffer_used: u32 = 0;
+    let mut property_count: u32 = 0;
 
-    // Render the event values with zero length buffer to determine size.
-    unsafe {
+    let render = |buffer: &mut Vec<u8>, buffer_used: &mut u32, property_count: &mut u32| unsafe {
         EvtRender(
             *render_context.as_ptr(),
             *event,
             render_flags,
-            ZERO_BUFFER_SIZE,
-            null_mut(),
-            &mut buffer_used,
-            &mut property_count,
+            buffer.len() as u32,
+            if buffer.is_empty() {
+                null_mut()
+            } else {
+                buffer.as_mut_ptr() as *mut c_void
+            },
+            buffer_used,
+            property_count,
         )
     };
 
-    let last_error = WindowsError::from_win32();
+    if render(&mut buffer, &mut buffer_used, &mut property_count) == FALSE {
+        let last_error = WindowsError::from_win32();
 
-    // ... and to receive the error ERROR_INSUFFICIENT_BUFFER, if anything needs to be rendered.
-    if last_error.code().is_err() {
         if last_error.code() != HRESULT::from_win32(ERROR_INSUFFICIENT_BUFFER) {
+            recycle_render_buffer(buffer);
             return Err(format!(
                 "Error trying to determine buffer size: {:?}",
                 last_error.code()
             ));
         }
+
+        buffer.resize(buffer_used as usize, 0);
+
+        if render(&mut buffer, &mut buffer_used, &mut property_count) == FALSE {
+            let last_error = WindowsError::from_win32();
+            recycle_render_buffer(buffer);
+            return Err(format!(
+                "Error when trying to render event: {:?}",
+                last_error.message()
+            ));
+        }
     }
 
-    // TODO: Does returning an empty Vec make sense when no error occured?
-    let mut buffer: Vec<u8> = vec![0; buffer_used as usize];
+    Ok((buffer, property_count))
+}
 
-    unsafe {
-        EvtRender(
-            *render_context.as_ptr(),
-            *event,
-            render_flags,
-            buffer.len() as u32,
-            buffer.as_mut_ptr() as *mut c_void,
-            &mut buffer_used,
-            &mut property_count,
-        )
-    };
+/// Retrieve a single property value from one of the `EvtGet*Property` style functions.
+///
+/// `getter` receives the buffer size, the buffer and a pointer to the variable receiving the used buffer size, and
+/// has to pass them on to the Windows API call. It is called once with an empty buffer to determine the required
+/// size, and a second time to retrieve the value.
+pub fn get_variant_property<F>(getter: F) -> Result<EventVariantValue, WindowsError>
+where
+    F: Fn(u32, *mut EVT_VARIANT, *mut u32) -> BOOL,
+{
+    let mut buffer_used: u32 = 0;
+
+    if getter(ZERO_BUFFER_SIZE, null_mut(), &mut buffer_used) == FALSE {
+        let last_error = WindowsError::from_win32();
+        if last_error.code() != HRESULT::from_win32(ERROR_INSUFFICIENT_BUFFER) {
+            return Err(last_error);
+        }
+    }
 
-    let last_error = WindowsError::from_win32();
+    let mut buffer: Vec<u8> = vec![0; buffer_used as usize];
 
-    if last_error.code().is_err() {
-        return Err(format!(
-            "Error when trying to render event: {:?}",
-            last_error.message()
-        ));
+    if getter(
+        buffer.len() as u32,
+        buffer.as_mut_ptr() as *mut EVT_VARIANT,
+        &mut buffer_used,
+    ) == FALSE
+    {
+        return Err(WindowsError::from_win32());
     }
 
-    return Ok((buffer, property_count));
+    if buffer.len() < std::mem::size_of::<EVT_VARIANT>() {
+        return Ok(EventVariantValue::Null);
+    }
+
+    let buffer = unsafe { EventVariantBuffer::from_raw_buffer(buffer, 1) };
+    Ok(buffer
+        .get_property_value(0)
+        .unwrap_or(EventVariantValue::Null))
 }
 
-fn event_format_message<T: WindowsEventHandle>(
-    event: &T,
-    metadata: isize,
-    max_buf_len: usize,
-) -> Result<String, (WindowsError, u32)> {
-    let mut message_buf: Vec<u16> = vec![0; max_buf_len];
+/// Format a message with `EvtFormatMessage` and return the raw UTF-16 buffer, including the terminating null.
+///
+/// Most messages fit into the initial buffer, so the size only needs to be determined for long messages.
+///
+/// Insertions that cannot be resolved, e.g. `%1` when formatting a message ID without an event, are left in place:
+/// `EvtFormatMessage` reports them as an error but still fills the buffer.
+pub fn format_message_raw(
+    metadata: EVT_HANDLE,
+    event: EVT_HANDLE,
+    message_id: u32,
+    flags: u32,
+) -> Result<Vec<u16>, WindowsError> {
+    let mut message_buf: Vec<u16> = vec![0; 512];
     let mut buffer_used: u32 = 0;
 
-    let result = unsafe {
-        EvtFormatMessage(
-            metadata,
-            *event.get_handle(),
-            0,
-            0,
-            null(),
-            EvtFormatMessageEvent,
-            message_buf.len() as u32,
-            message_buf.as_mut_ptr(),
-            &mut buffer_used,
-        )
-    };
+    loop {
+        let result = unsafe {
+            EvtFormatMessage(
+                metadata,
+                event,
+                message_id,
+                0,
+                null(),
+                flags,
+                message_buf.len() as u32,
+                message_buf.as_mut_ptr(),
+                &mut buffer_used,
+            )
+        };
 
-    if result != 0 {
-        Ok(message_buf.as_ptr().win_into())
-    } else {
-        Err((WindowsError::from_win32(), buffer_used))
+        if result != FALSE {
+            message_buf.truncate(buffer_used as usize);
+            return Ok(message_buf);
+        }
+
+        let last_error = WindowsError::from_win32();
+        let unresolved = [
+            ERROR_EVT_UNRESOLVED_VALUE_INSERT,
+            ERROR_EVT_UNRESOLVED_PARAMETER_INSERT,
+        ]
+        .iter()
+        .any(|code| last_error.code() == HRESULT::from_win32(*code));
+        if unresolved && buffer_used as usize <= message_buf.len() {
+            message_buf.truncate(buffer_used as usize);
+            return Ok(message_buf);
+        }
+        if last_error.code() != HRESULT::from_win32(ERROR_INSUFFICIENT_BUFFER)
+            || buffer_used as usize <= message_buf.len()
+        {
+            return Err(last_error);
+        }
+
+        message_buf.resize(buffer_used as usize, 0);
     }
 }
 
-pub fn format_guid(guid: &GUID) -> String {
-    format!(
-        "{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}}}",
-        guid.data1,
-        guid.data2,
-        guid.data3,
-        guid.data4[0],
-        guid.data4[1],
-        guid.data4[2],
-        guid.data4[3],
-        guid.data4[4],
-        guid.data4[5],
-        guid.data4[6],
-        guid.data4[7]
-    )
+/// Split a buffer of null-terminated strings, as returned for `EvtFormatMessageKeyword`, into its strings.
+pub fn split_multi_string(buffer: &[u16]) -> Vec<String> {
+    buffer
+        .split(|c| *c == 0)
+        .filter(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+/// Format a message with `EvtFormatMessage`.
+pub fn format_message(
+    metadata: EVT_HANDLE,
+    event: EVT_HANDLE,
+    message_id: u32,
+    flags: u32,
+) -> Result<String, WindowsError> {
+    let mut buffer = format_message_raw(metadata, event, message_id, flags)?;
+    buffer.push(0);
+    Ok(buffer.as_ptr().win_into())
 }
 
-fn format_sid(value: &SID) -> String {
+/// Format a SID in string form, e.g. `S-1-5-21-1004336348-1177238915-682003330-512`.
+///
+/// The subauthorities are read past the end of `SID`, which only declares the first one, so `value` must point into
+/// the buffer holding the whole SID, e.g. a render buffer. A copied `SID` is truncated.
+pub fn format_sid(value: &SID) -> String {
     use std::slice;
     let revision = unsafe { *(&value.Revision as *const u8) };
     let sub_authority_count = unsafe { *(&value.SubAuthorityCount as *const u8) };
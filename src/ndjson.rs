@@ -0,0 +1,217 @@
+use std::io::{BufWriter, Write};
+
+use chrono::SecondsFormat;
+use serde_json::{Map, Number, Value};
+
+use crate::event_data::EventData;
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+use crate::variant::{format_guid, EventVariantValue};
+
+/// Sink writing one JSON object per line (newline-delimited JSON).
+///
+/// Writes are buffered and only reach the underlying writer on `flush` or when the buffer is full.
+pub struct NdjsonSink<W: Write> {
+    writer: BufWriter<W>,
+    fields: RecordFields,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W, fields: RecordFields) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            fields,
+        }
+    }
+
+    /// Create a sink with a write buffer of `capacity` bytes instead of the default 8 KiB.
+    pub fn with_capacity(capacity: usize, writer: W, fields: RecordFields) -> Self {
+        Self {
+            writer: BufWriter::with_capacity(capacity, writer),
+            fields,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flush the buffer and return the underlying writer.
+    pub fn into_inner(self) -> Result<W, String> {
+        self.writer
+            .into_inner()
+            .map_err(|error| format!("Failed to flush NDJSON output: {}", error.error()))
+    }
+}
+
+impl<W: Write> EventSink for NdjsonSink<W> {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let object = Value::Object(record_to_json(record, &self.fields));
+
+        serde_json::to_writer(&mut self.writer, &object)
+            .map_err(|error| format!("Failed to write NDJSON record: {}", error))?;
+        self.writer
+            .write_all(b"\n")
+            .map_err(|error| format!("Failed to write NDJSON record: {}", error))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|error| format!("Failed to flush NDJSON output: {}", error))
+    }
+}
+
+/// Convert a record to a JSON object with the selected fields.
+///
+/// System properties use the names of `EventSystemContext`, and properties the event does not have are omitted. The
+/// event data is a nested object `event_data`, see `event_data_to_json`.
+pub fn record_to_json(record: &EventRecord, fields: &RecordFields) -> Map<String, Value> {
+    let mut object = Map::new();
+
+    if fields.system {
+        let mut insert = |name: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                object.insert(name.to_owned(), value);
+            }
+        };
+
+        insert("provider_name", Some(record.provider_name.clone().into()));
+        insert(
+            "provider_guid",
+            record.provider_guid.clone().map(Value::from),
+        );
+        insert("event_id", Some(record.event_id.into()));
+        insert("qualifiers", record.qualifiers.map(Value::from));
+        insert("version", record.version.map(Value::from));
+        insert("level", record.level.map(Value::from));
+        insert("task", record.task.map(Value::from));
+        insert("opcode", record.opcode.map(Value::from));
+        insert(
+            "keywords",
+            record
+                .keywords
+                .map(|keywords| format!("0x{:X}", keywords).into()),
+        );
+        insert(
+            "time_created",
+            Some(
+                record
+                    .time_created
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                    .into(),
+            ),
+        );
+        insert("event_record_id", Some(record.event_record_id.into()));
+        insert("activity_id", record.activity_id.clone().map(Value::from));
+        insert(
+            "related_activity_id",
+            record.related_activity_id.clone().map(Value::from),
+        );
+        insert("process_id", record.process_id.map(Value::from));
+        insert("thread_id", record.thread_id.map(Value::from));
+        insert("channel", record.channel.clone().map(Value::from));
+        insert("computer", record.computer.clone().map(Value::from));
+        insert("user_id", record.user_id.clone().map(Value::from));
+    }
+
+    if fields.event_data {
+        object.insert(
+            "event_data".to_owned(),
+            Value::Object(event_data_to_json(&record.event_data)),
+        );
+    }
+    if fields.message {
+        if let Some(message) = &record.message {
+            object.insert("message".to_owned(), message.clone().into());
+        }
+    }
+    if fields.xml {
+        if let Some(xml) = &record.xml {
+            object.insert("xml".to_owned(), xml.clone().into());
+        }
+    }
+
+    object
+}
+
+/// Convert event data to a JSON object. If a name is used more than once, the first value is kept.
+pub fn event_data_to_json(event_data: &EventData) -> Map<String, Value> {
+    let mut object = Map::new();
+
+    for (name, value) in event_data.iter() {
+        if !object.contains_key(name) {
+            object.insert(name.to_owned(), variant_to_json(value));
+        }
+    }
+
+    object
+}
+
+/// Convert a value to JSON.
+///
+/// Numbers and booleans stay numbers and booleans, except for hexadecimal integers, which are strings like
+/// `0x1F` as in the event XML. Arrays become JSON arrays. All other values use their `Display` format.
+pub fn variant_to_json(value: &EventVariantValue) -> Value {
+    fn array<T: Copy + Into<Value>>(values: &[T]) -> Value {
+        Value::Array(values.iter().map(|value| (*value).into()).collect())
+    }
+
+    fn floats<T: Copy + Into<f64>>(values: &[T]) -> Value {
+        Value::Array(values.iter().map(|value| float((*value).into())).collect())
+    }
+
+    fn float(value: f64) -> Value {
+        Number::from_f64(value).map_or_else(|| value.to_string().into(), Value::Number)
+    }
+
+    fn strings<T>(values: &[T], to_string: impl Fn(&T) -> String) -> Value {
+        Value::Array(values.iter().map(|value| to_string(value).into()).collect())
+    }
+
+    match value {
+        EventVariantValue::Null
+        | EventVariantValue::UnknownType(_)
+        | EventVariantValue::UnknownTypeArr(_) => Value::Null,
+        EventVariantValue::Bool(value) => (*value).into(),
+        EventVariantValue::SByte(value) => (*value).into(),
+        EventVariantValue::Int16(value) => (*value).into(),
+        EventVariantValue::Int32(value) => (*value).into(),
+        EventVariantValue::Int64(value) => (*value).into(),
+        EventVariantValue::Byte(value) => (*value).into(),
+        EventVariantValue::UInt16(value) => (*value).into(),
+        EventVariantValue::UInt32(value) => (*value).into(),
+        EventVariantValue::UInt64(value) => (*value).into(),
+        EventVariantValue::SizeT(value) => (*value).into(),
+        EventVariantValue::Single(value) => float(*value as f64),
+        EventVariantValue::Double(value) => float(*value),
+        EventVariantValue::BoolArr(values) => array(values),
+        EventVariantValue::SByteArr(values) => array(values),
+        EventVariantValue::Int16Arr(values) => array(values),
+        EventVariantValue::Int32Arr(values) => array(values),
+        EventVariantValue::Int64Arr(values) => array(values),
+        EventVariantValue::ByteArr(values) => array(values),
+        EventVariantValue::UInt16Arr(values) => array(values),
+        EventVariantValue::UInt32Arr(values) => array(values),
+        EventVariantValue::UInt64Arr(values) => array(values),
+        EventVariantValue::SizeTArr(values) => array(values),
+        EventVariantValue::SingleArr(values) => floats(values),
+        EventVariantValue::DoubleArr(values) => floats(values),
+        EventVariantValue::StringArr(values)
+        | EventVariantValue::AnsiStringArr(values)
+        | EventVariantValue::XmlArr(values)
+        | EventVariantValue::SidArr(values) => strings(values, String::clone),
+        EventVariantValue::HexInt32Arr(values) => strings(values, |value| format!("0x{:X}", value)),
+        EventVariantValue::HexInt64Arr(values) => strings(values, |value| format!("0x{:X}", value)),
+        EventVariantValue::FileTimeArr(values) => strings(values, |value| {
+            EventVariantValue::FileTime(*value).to_string()
+        }),
+        EventVariantValue::SysTimeArr(values) => strings(values, |value| {
+            EventVariantValue::SysTime(*value).to_string()
+        }),
+        EventVariantValue::GuidArr(values) => {
+            strings(values, |value| format_guid(value).to_uppercase())
+        }
+        _ => value.to_string().into(),
+    }
+}
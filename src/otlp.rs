@@ -0,0 +1,560 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::http::{Backoff, HttpClient, HttpOptions, RetryPolicy};
+use crate::ndjson::variant_to_json;
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+
+/// Name of the instrumentation scope of the exported log records.
+pub static SCOPE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Value of an attribute or of the body of a log record (`AnyValue` of the OTLP protocol).
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnyValue {
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Array(Vec<AnyValue>),
+}
+
+impl AnyValue {
+    /// Convert a JSON value. Objects and `null` have no equivalent used here and become `None`, integers that do not
+    /// fit into an `i64` become strings.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::Null | Value::Object(_) => return None,
+            Value::Bool(value) => Self::Bool(*value),
+            Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+                (Some(value), _) => Self::Int(value),
+                (None, Some(value)) if !number.is_u64() => Self::Double(value),
+                _ => Self::String(number.to_string()),
+            },
+            Value::String(value) => Self::String(value.clone()),
+            Value::Array(values) => {
+                Self::Array(values.iter().filter_map(Self::from_json).collect())
+            }
+        })
+    }
+}
+
+impl From<&str> for AnyValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+/// Attribute of a log record or resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: AnyValue,
+}
+
+/// Event in the OpenTelemetry logs data model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub time_unix_nano: u64,
+    pub observed_time_unix_nano: u64,
+    /// Severity number from 1 (TRACE) to 24 (FATAL4).
+    pub severity_number: u8,
+    pub severity_text: String,
+    pub body: Option<AnyValue>,
+    pub attributes: Vec<KeyValue>,
+}
+
+/// OpenTelemetry severity number and text of an event level.
+///
+/// Level 0 (log always), used by the Security channel for all audit events, is informational.
+pub fn otel_severity(level: Option<u8>) -> (u8, &'static str) {
+    match level {
+        Some(1) => (21, "Critical"),
+        Some(2) => (17, "Error"),
+        Some(3) => (13, "Warning"),
+        None | Some(0) | Some(4) => (9, "Information"),
+        Some(_) => (5, "Verbose"),
+    }
+}
+
+/// Convert a record to a log record.
+///
+/// The body is the rendered message. The system properties become `winlog.*` attributes (`process.pid` and
+/// `thread.id` for the process and thread), the event data `winlog.event_data.<name>` attributes. The computer is not
+/// an attribute but the `host.name` of the resource, see `resource_attributes`.
+pub fn log_record(
+    record: &EventRecord,
+    fields: &RecordFields,
+    observed: DateTime<Utc>,
+) -> LogRecord {
+    let (severity_number, severity_text) = otel_severity(record.level);
+    let mut attributes = Vec::new();
+    let mut add = |key: &str, value: Option<AnyValue>| {
+        if let Some(value) = value {
+            attributes.push(KeyValue {
+                key: key.to_owned(),
+                value,
+            });
+        }
+    };
+
+    add(
+        "winlog.provider_name",
+        Some(record.provider_name.as_str().into()),
+    );
+    add(
+        "winlog.event_id",
+        Some(AnyValue::Int(record.event_id.into())),
+    );
+    add(
+        "winlog.record_id",
+        i64::try_from(record.event_record_id)
+            .ok()
+            .map(AnyValue::Int),
+    );
+    add(
+        "winlog.channel",
+        record.channel.as_deref().map(AnyValue::from),
+    );
+    if fields.system {
+        let int = |value: Option<i64>| value.map(AnyValue::Int);
+        add(
+            "winlog.provider_guid",
+            record.provider_guid.as_deref().map(AnyValue::from),
+        );
+        add("winlog.qualifiers", int(record.qualifiers.map(i64::from)));
+        add("winlog.version", int(record.version.map(i64::from)));
+        add("winlog.level", int(record.level.map(i64::from)));
+        add("winlog.task", int(record.task.map(i64::from)));
+        add("winlog.opcode", int(record.opcode.map(i64::from)));
+        add(
+            "winlog.keywords",
+            record
+                .keywords
+                .map(|keywords| AnyValue::String(format!("0x{:X}", keywords))),
+        );
+        add(
+            "winlog.activity_id",
+            record.activity_id.as_deref().map(AnyValue::from),
+        );
+        add(
+            "winlog.related_activity_id",
+            record.related_activity_id.as_deref().map(AnyValue::from),
+        );
+        add(
+            "winlog.user.identifier",
+            record.user_id.as_deref().map(AnyValue::from),
+        );
+        add("process.pid", int(record.process_id.map(i64::from)));
+        add("thread.id", int(record.thread_id.map(i64::from)));
+    }
+    if fields.event_data {
+        let mut seen = std::collections::HashSet::new();
+        for (name, value) in record.event_data.iter() {
+            if seen.insert(name) {
+                add(
+                    &format!("winlog.event_data.{}", name),
+                    AnyValue::from_json(&variant_to_json(value)),
+                );
+            }
+        }
+    }
+
+    LogRecord {
+        time_unix_nano: unix_nanos(record.time_created),
+        observed_time_unix_nano: unix_nanos(observed),
+        severity_number,
+        severity_text: severity_text.to_owned(),
+        body: record
+            .message
+            .as_deref()
+            .filter(|_| fields.message)
+            .map(AnyValue::from),
+        attributes,
+    }
+}
+
+/// Resource attributes of the events of a computer.
+pub fn resource_attributes(computer: Option<&str>) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue {
+        key: "os.type".to_owned(),
+        value: "windows".into(),
+    }];
+    if let Some(computer) = computer {
+        attributes.push(KeyValue {
+            key: "host.name".to_owned(),
+            value: computer.into(),
+        });
+    }
+    attributes
+}
+
+fn unix_nanos(time: DateTime<Utc>) -> u64 {
+    time.timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .unwrap_or_default()
+}
+
+/// Log records of one resource, e.g. the events of one computer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceLogs {
+    pub resource: Vec<KeyValue>,
+    pub log_records: Vec<LogRecord>,
+}
+
+/// Encoding of the OTLP/HTTP request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtlpEncoding {
+    #[default]
+    Protobuf,
+    Json,
+}
+
+impl OtlpEncoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OtlpEncoding::Protobuf => "application/x-protobuf",
+            OtlpEncoding::Json => "application/json",
+        }
+    }
+
+    /// Encode an `ExportLogsServiceRequest`.
+    pub fn encode(&self, resource_logs: &[ResourceLogs]) -> Vec<u8> {
+        match self {
+            OtlpEncoding::Protobuf => encode_protobuf(resource_logs),
+            OtlpEncoding::Json => encode_json(resource_logs).to_string().into_bytes(),
+        }
+    }
+}
+
+/// Protobuf message written field by field.
+#[derive(Default)]
+struct ProtoWriter {
+    buffer: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buffer.push(value as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.tag(field, 0);
+        self.varint(value);
+    }
+
+    fn fixed64(&mut self, field: u32, value: u64) {
+        self.tag(field, 1);
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.tag(field, 2);
+        self.varint(value.len() as u64);
+        self.buffer.extend_from_slice(value);
+    }
+
+    fn message(&mut self, field: u32, write: impl FnOnce(&mut ProtoWriter)) {
+        let mut message = ProtoWriter::default();
+        write(&mut message);
+        self.bytes(field, &message.buffer);
+    }
+
+    fn any_value(&mut self, value: &AnyValue) {
+        match value {
+            AnyValue::String(value) => self.bytes(1, value.as_bytes()),
+            AnyValue::Bool(value) => self.uint(2, *value as u64),
+            AnyValue::Int(value) => self.uint(3, *value as u64),
+            AnyValue::Double(value) => self.fixed64(4, value.to_bits()),
+            AnyValue::Array(values) => self.message(5, |array| {
+                for value in values {
+                    array.message(1, |message| message.any_value(value));
+                }
+            }),
+        }
+    }
+
+    fn attributes(&mut self, field: u32, attributes: &[KeyValue]) {
+        for attribute in attributes {
+            self.message(field, |message| {
+                message.bytes(1, attribute.key.as_bytes());
+                message.message(2, |value| value.any_value(&attribute.value));
+            });
+        }
+    }
+}
+
+fn encode_protobuf(resource_logs: &[ResourceLogs]) -> Vec<u8> {
+    let mut request = ProtoWriter::default();
+
+    for resource_logs in resource_logs {
+        request.message(1, |message| {
+            message.message(1, |resource| {
+                resource.attributes(1, &resource_logs.resource)
+            });
+            message.message(2, |scope_logs| {
+                scope_logs.message(1, |scope| {
+                    scope.bytes(1, SCOPE_NAME.as_bytes());
+                    scope.bytes(2, env!("CARGO_PKG_VERSION").as_bytes());
+                });
+                for record in &resource_logs.log_records {
+                    scope_logs.message(2, |message| {
+                        message.fixed64(1, record.time_unix_nano);
+                        message.uint(2, record.severity_number as u64);
+                        message.bytes(3, record.severity_text.as_bytes());
+                        if let Some(body) = &record.body {
+                            message.message(5, |value| value.any_value(body));
+                        }
+                        message.attributes(6, &record.attributes);
+                        message.fixed64(11, record.observed_time_unix_nano);
+                    });
+                }
+            });
+        });
+    }
+
+    request.buffer
+}
+
+/// Encode an `ExportLogsServiceRequest` in the JSON mapping of OTLP, with 64-bit integers as strings.
+pub fn encode_json(resource_logs: &[ResourceLogs]) -> Value {
+    fn any_value(value: &AnyValue) -> Value {
+        match value {
+            AnyValue::String(value) => json!({ "stringValue": value }),
+            AnyValue::Bool(value) => json!({ "boolValue": value }),
+            AnyValue::Int(value) => json!({ "intValue": value.to_string() }),
+            AnyValue::Double(value) => json!({ "doubleValue": value }),
+            AnyValue::Array(values) => {
+                json!({ "arrayValue": { "values": values.iter().map(any_value).collect::<Vec<_>>() } })
+            }
+        }
+    }
+
+    fn attributes(attributes: &[KeyValue]) -> Value {
+        Value::Array(
+            attributes
+                .iter()
+                .map(|attribute| json!({ "key": attribute.key, "value": any_value(&attribute.value) }))
+                .collect(),
+        )
+    }
+
+    let resource_logs: Vec<Value> = resource_logs
+        .iter()
+        .map(|resource_logs| {
+            let log_records: Vec<Value> = resource_logs
+                .log_records
+                .iter()
+                .map(|record| {
+                    let mut object = Map::new();
+                    object.insert(
+                        "timeUnixNano".to_owned(),
+                        record.time_unix_nano.to_string().into(),
+                    );
+                    object.insert(
+                        "observedTimeUnixNano".to_owned(),
+                        record.observed_time_unix_nano.to_string().into(),
+                    );
+                    object.insert("severityNumber".to_owned(), record.severity_number.into());
+                    object.insert(
+                        "severityText".to_owned(),
+                        record.severity_text.clone().into(),
+                    );
+                    if let Some(body) = &record.body {
+                        object.insert("body".to_owned(), any_value(body));
+                    }
+                    object.insert("attributes".to_owned(), attributes(&record.attributes));
+                    Value::Object(object)
+                })
+                .collect();
+
+            json!({
+                "resource": { "attributes": attributes(&resource_logs.resource) },
+                "scopeLogs": [{
+                    "scope": { "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                    "logRecords": log_records,
+                }],
+            })
+        })
+        .collect();
+
+    json!({ "resourceLogs": resource_logs })
+}
+
+/// Settings of an `OtlpExporter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtlpOptions {
+    /// Endpoint URL, e.g. `http://localhost:4318/v1/logs`, with headers such as an API key.
+    pub http: HttpOptions,
+    pub encoding: OtlpEncoding,
+    /// Number of log records sent per request.
+    pub batch_size: usize,
+    /// Number of log records kept while the endpoint is unreachable. When the queue is full, the oldest record is
+    /// dropped.
+    pub max_queued: usize,
+    pub retry: RetryPolicy,
+    /// Parts of the event to export. The raw XML is never exported.
+    pub fields: RecordFields,
+}
+
+impl OtlpOptions {
+    pub fn new(url: &str) -> Self {
+        Self {
+            http: HttpOptions::new(url),
+            encoding: OtlpEncoding::Protobuf,
+            batch_size: 512,
+            max_queued: 10_000,
+            retry: RetryPolicy::default(),
+            fields: RecordFields::default(),
+        }
+    }
+}
+
+/// Sink exporting events to an OpenTelemetry collector with OTLP/HTTP.
+///
+/// Records are queued by `write` and sent in batches of `OtlpOptions::batch_size`, as soon as a batch is full and by
+/// `flush`. After a retryable status (429, 502, 503, 504) or a network error, the records stay queued and no request is
+/// made before the back-off delay of `OtlpOptions::retry` has passed. `write` never waits for the delay, it makes a
+/// single attempt once the delay has passed. `flush` waits and retries; if all retries fail, the records stay queued
+/// for the next attempt. Records rejected by the collector with another status are dropped, as sending them again
+/// would fail the same way.
+pub struct OtlpExporter {
+    options: OtlpOptions,
+    client: HttpClient,
+    queue: VecDeque<(Option<String>, LogRecord)>,
+    backoff: Backoff,
+    dropped: u64,
+}
+
+impl OtlpExporter {
+    /// Create an exporter. The connection is opened by the first request.
+    pub fn new(options: OtlpOptions) -> Result<Self, String> {
+        Ok(Self {
+            client: HttpClient::new(options.http.clone())?,
+            options,
+            queue: VecDeque::new(),
+            backoff: Backoff::default(),
+            dropped: 0,
+        })
+    }
+
+    /// Number of log records waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of log records dropped because the queue was full or the collector rejected them.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Send the first `count` queued records in one request, without retrying.
+    fn export(&mut self, count: usize) -> Result<(), String> {
+        let mut resource_logs: Vec<(Option<&str>, Vec<LogRecord>)> = Vec::new();
+        for (computer, record) in self.queue.iter().take(count) {
+            match resource_logs
+                .iter_mut()
+                .find(|(resource, _)| *resource == computer.as_deref())
+            {
+                Some((_, records)) => records.push(record.clone()),
+                None => resource_logs.push((computer.as_deref(), vec![record.clone()])),
+            }
+        }
+        let resource_logs: Vec<ResourceLogs> = resource_logs
+            .into_iter()
+            .map(|(computer, log_records)| ResourceLogs {
+                resource: resource_attributes(computer),
+                log_records,
+            })
+            .collect();
+        let body = self.options.encoding.encode(&resource_logs);
+
+        let (error, requested) = match self
+            .client
+            .post(self.options.encoding.content_type(), &body)
+        {
+            Ok(response) if response.is_success() => {
+                self.backoff.reset();
+                self.queue.drain(..count);
+                return Ok(());
+            }
+            Ok(response) if !response.is_retryable() => {
+                self.backoff.reset();
+                self.queue.drain(..count);
+                self.dropped += count as u64;
+                return Err(format!(
+                    "OTLP export rejected with status {}: {}",
+                    response.status,
+                    response.body_text()
+                ));
+            }
+            Ok(response) => (
+                format!("OTLP export failed with status {}", response.status),
+                response.retry_after(),
+            ),
+            Err(error) => (error, None),
+        };
+
+        self.backoff.failed(&self.options.retry, requested);
+        Err(error)
+    }
+
+    /// Send queued records in batches while at least `minimum` are queued.
+    ///
+    /// A batch failing with a retryable error is retried up to `max_retries` times, each after the back-off delay.
+    fn export_batches(&mut self, minimum: usize, max_retries: u32) -> Result<(), String> {
+        let batch_size = self.options.batch_size.max(1);
+        let mut retries = 0;
+        while self.queue.len() >= minimum.max(1) {
+            self.backoff.wait();
+            match self.export(batch_size.min(self.queue.len())) {
+                Ok(()) => retries = 0,
+                Err(error) if !self.backoff.is_failing() || retries >= max_retries => {
+                    return Err(error)
+                }
+                Err(_) => retries += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventSink for OtlpExporter {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        if self.options.max_queued == 0 {
+            self.dropped += 1;
+            return Ok(());
+        }
+        while self.queue.len() >= self.options.max_queued {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back((
+            record.computer.clone(),
+            log_record(record, &self.options.fields, Utc::now()),
+        ));
+
+        if self.queue.len() < self.options.batch_size || self.backoff.is_waiting() {
+            return Ok(());
+        }
+        // After a retryable failure, the records stay queued for a later write or `flush`.
+        match self.export_batches(self.options.batch_size, 0) {
+            Err(_) if self.backoff.is_failing() => Ok(()),
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.export_batches(1, self.options.retry.max_retries)
+    }
+}
@@ -0,0 +1,442 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use windows_sys::core::GUID;
+
+use crate::message_template::expand_message;
+use crate::variant::format_guid;
+
+/// Resource type of message tables (`RT_MESSAGETABLE`).
+pub static RT_MESSAGETABLE: u16 = 11;
+
+/// Resource type name of the compiled instrumentation manifests of event providers.
+pub static WEVT_TEMPLATE: &str = "WEVT_TEMPLATE";
+
+/// Index of the resource table in the data directories of the optional header.
+static RESOURCE_DIRECTORY_INDEX: usize = 2;
+
+static PE32_MAGIC: u16 = 0x10b;
+static PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// Maximum number of entries read from all resource directories of a file, so crafted files with huge or
+/// overlapping directories can not exhaust the memory.
+static MAX_RESOURCE_ENTRIES: usize = 65_536;
+
+/// Flag of message table entries stored as UTF-16.
+static MESSAGE_RESOURCE_UNICODE: u16 = 0x0001;
+
+/// Type, name or language of a resource, identified by number or by name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ResourceId {
+    Id(u16),
+    Name(String),
+}
+
+impl fmt::Display for ResourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceId::Id(id) => write!(f, "#{}", id),
+            ResourceId::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Raw resource of a PE file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeResource {
+    pub resource_type: ResourceId,
+    pub name: ResourceId,
+    /// Language ID, e.g. `0x409` for en-US. `0` for language neutral resources.
+    pub language: u16,
+    pub code_page: u32,
+    pub data: Vec<u8>,
+}
+
+/// Section header, used to map relative virtual addresses to file offsets.
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_size: u32,
+    raw_offset: u32,
+}
+
+/// Resources of a PE file (EXE, DLL or MUI file).
+///
+/// Parsing does not use any Windows APIs, so message files copied from a Windows image can be read on any OS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeImage {
+    pub resources: Vec<PeResource>,
+}
+
+impl PeImage {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path)
+            .map_err(|error| format!("Failed to read '{}': {}", path.display(), error))?;
+
+        Self::parse(&data).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.get(0..2) != Some(b"MZ") {
+            return Err("Not a PE file: missing MZ signature".to_owned());
+        }
+
+        let pe_offset = read_u32(data, 0x3c)? as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+            return Err("Not a PE file: missing PE signature".to_owned());
+        }
+
+        let coff_header = pe_offset + 4;
+        let number_of_sections = read_u16(data, coff_header + 2)? as usize;
+        let optional_header_size = read_u16(data, coff_header + 16)? as usize;
+        let optional_header = coff_header + 20;
+
+        let (rva_count_offset, data_directories) = match read_u16(data, optional_header)? {
+            magic if magic == PE32_MAGIC => (optional_header + 92, optional_header + 96),
+            magic if magic == PE32_PLUS_MAGIC => (optional_header + 108, optional_header + 112),
+            magic => return Err(format!("Unknown optional header magic 0x{:x}", magic)),
+        };
+
+        let sections = (0..number_of_sections)
+            .map(|index| {
+                let header = optional_header + optional_header_size + index * 40;
+                Ok(Section {
+                    virtual_size: read_u32(data, header + 8)?,
+                    virtual_address: read_u32(data, header + 12)?,
+                    raw_size: read_u32(data, header + 16)?,
+                    raw_offset: read_u32(data, header + 20)?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if (read_u32(data, rva_count_offset)? as usize) <= RESOURCE_DIRECTORY_INDEX {
+            return Ok(Self::default());
+        }
+
+        let resource_rva = read_u32(data, data_directories + RESOURCE_DIRECTORY_INDEX * 8)?;
+        if resource_rva == 0 {
+            return Ok(Self::default());
+        }
+
+        let reader = ResourceReader {
+            data,
+            sections: &sections,
+            base: rva_to_offset(&sections, resource_rva)?,
+            visited: Default::default(),
+            entries: Default::default(),
+        };
+
+        Ok(Self {
+            resources: reader.read()?,
+        })
+    }
+
+    /// Resources of a type, in all languages.
+    pub fn resources_of_type<'a>(
+        &'a self,
+        resource_type: &'a ResourceId,
+    ) -> impl Iterator<Item = &'a PeResource> {
+        self.resources
+            .iter()
+            .filter(move |resource| &resource.resource_type == resource_type)
+    }
+
+    /// Message tables of the file, one per language.
+    pub fn message_tables(&self) -> Result<Vec<MessageTable>, String> {
+        self.resources_of_type(&ResourceId::Id(RT_MESSAGETABLE))
+            .map(|resource| MessageTable::parse(resource.language, &resource.data))
+            .collect()
+    }
+
+    /// Message table in the given language.
+    ///
+    /// Falls back to the language neutral table and then to the first table, like the resource loader does for
+    /// files with a single language.
+    pub fn message_table(&self, language: u16) -> Result<Option<MessageTable>, String> {
+        let mut tables = self.message_tables()?;
+
+        let position = tables
+            .iter()
+            .position(|table| table.language == language)
+            .or_else(|| tables.iter().position(|table| table.language == 0))
+            .or(if tables.is_empty() { None } else { Some(0) });
+
+        Ok(position.map(|position| tables.swap_remove(position)))
+    }
+
+    /// Compiled instrumentation manifests (`WEVT_TEMPLATE` resources) of the file.
+    ///
+    /// Only the provider list of each resource is decoded, see `WevtTemplate`.
+    pub fn wevt_templates(&self) -> Result<Vec<WevtTemplate>, String> {
+        self.resources_of_type(&ResourceId::Name(WEVT_TEMPLATE.to_owned()))
+            .map(|resource| WevtTemplate::parse(resource.language, resource.data.clone()))
+            .collect()
+    }
+}
+
+struct ResourceReader<'a> {
+    data: &'a [u8],
+    sections: &'a [Section],
+    /// File offset of the root resource directory. Directory offsets are relative to it.
+    base: usize,
+    /// Offsets of the directories read so far. A directory referenced twice would make the walk repeat itself.
+    visited: RefCell<HashSet<u32>>,
+    /// Number of directory entries read so far, see `MAX_RESOURCE_ENTRIES`.
+    entries: Cell<usize>,
+}
+
+impl ResourceReader<'_> {
+    /// Walk the type, name and language levels of the resource directory.
+    fn read(&self) -> Result<Vec<PeResource>, String> {
+        let mut resources = Vec::new();
+
+        for (resource_type, type_offset) in self.directory(0)? {
+            let type_directory = subdirectory(type_offset, "type")?;
+
+            for (name, name_offset) in self.directory(type_directory)? {
+                let name_directory = subdirectory(name_offset, "name")?;
+
+                for (language, data_entry) in self.directory(name_directory)? {
+                    if data_entry & 0x8000_0000 != 0 {
+                        return Err(format!(
+                            "Unexpected subdirectory below resource {}/{}",
+                            resource_type, name
+                        ));
+                    }
+
+                    let entry = self.base + data_entry as usize;
+                    let data_rva = read_u32(self.data, entry)?;
+                    let size = read_u32(self.data, entry + 4)? as usize;
+                    let offset = rva_to_offset(self.sections, data_rva)?;
+
+                    let data = self.data.get(offset..offset + size).ok_or_else(|| {
+                        format!(
+                            "Data of resource {}/{} is out of bounds",
+                            resource_type, name
+                        )
+                    })?;
+
+                    resources.push(PeResource {
+                        resource_type: resource_type.clone(),
+                        name: name.clone(),
+                        language: match language {
+                            ResourceId::Id(language) => language,
+                            ResourceId::Name(_) => 0,
+                        },
+                        code_page: read_u32(self.data, entry + 8)?,
+                        data: data.to_vec(),
+                    });
+                }
+            }
+        }
+
+        Ok(resources)
+    }
+
+    /// Read the entries of a directory at an offset relative to the root.
+    fn directory(&self, offset: u32) -> Result<Vec<(ResourceId, u32)>, String> {
+        if !self.visited.borrow_mut().insert(offset) {
+            return Err(format!(
+                "Resource directory at 0x{:x} is referenced more than once",
+                offset
+            ));
+        }
+
+        let directory = self.base + offset as usize;
+        let count = read_u16(self.data, directory + 12)? as usize
+            + read_u16(self.data, directory + 14)? as usize;
+
+        let entries = self.entries.get() + count;
+        if entries > MAX_RESOURCE_ENTRIES {
+            return Err(format!(
+                "Resource directory has more than {} entries",
+                MAX_RESOURCE_ENTRIES
+            ));
+        }
+        self.entries.set(entries);
+
+        (0..count)
+            .map(|index| {
+                let entry = directory + 16 + index * 8;
+                let name = read_u32(self.data, entry)?;
+                let target = read_u32(self.data, entry + 4)?;
+
+                let id = if name & 0x8000_0000 != 0 {
+                    let string = self.base + (name & 0x7fff_ffff) as usize;
+                    let length = read_u16(self.data, string)? as usize;
+                    let units = (0..length)
+                        .map(|index| read_u16(self.data, string + 2 + index * 2))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    ResourceId::Name(String::from_utf16_lossy(&units))
+                } else {
+                    ResourceId::Id(name as u16)
+                };
+
+                Ok((id, target))
+            })
+            .collect()
+    }
+}
+
+fn subdirectory(target: u32, level: &str) -> Result<u32, String> {
+    if target & 0x8000_0000 == 0 {
+        return Err(format!("Expected a resource {} directory", level));
+    }
+    Ok(target & 0x7fff_ffff)
+}
+
+fn rva_to_offset(sections: &[Section], rva: u32) -> Result<usize, String> {
+    sections
+        .iter()
+        .find(|section| {
+            let size = section.virtual_size.max(section.raw_size);
+            rva >= section.virtual_address && rva - section.virtual_address < size
+        })
+        .ok_or_else(|| format!("Address 0x{:x} is not in any section", rva))
+        .and_then(|section| {
+            (rva - section.virtual_address)
+                .checked_add(section.raw_offset)
+                .map(|offset| offset as usize)
+                .ok_or_else(|| format!("Address 0x{:x} is outside of the file", rva))
+        })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| format!("Unexpected end of file at offset 0x{:x}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| format!("Unexpected end of file at offset 0x{:x}", offset))
+}
+
+/// Messages of a `MESSAGETABLE` resource, by message ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageTable {
+    pub language: u16,
+    pub messages: BTreeMap<u32, String>,
+}
+
+impl MessageTable {
+    /// Parse the data of a `MESSAGETABLE` resource (`MESSAGE_RESOURCE_DATA`).
+    ///
+    /// ANSI entries are decoded as Latin-1, which matches the code page of the message compiler's default output
+    /// for ASCII text.
+    pub fn parse(language: u16, data: &[u8]) -> Result<Self, String> {
+        let block_count = read_u32(data, 0)? as usize;
+        let mut messages = BTreeMap::new();
+
+        for block in 0..block_count {
+            let block = 4 + block * 12;
+            let low_id = read_u32(data, block)?;
+            let high_id = read_u32(data, block + 4)?;
+            let mut entry = read_u32(data, block + 8)? as usize;
+
+            for message_id in low_id..=high_id {
+                let length = read_u16(data, entry)? as usize;
+                let flags = read_u16(data, entry + 2)?;
+                if length < 4 {
+                    return Err(format!("Invalid length of message {}", message_id));
+                }
+
+                let text = data
+                    .get(entry + 4..entry + length)
+                    .ok_or_else(|| format!("Message {} is out of bounds", message_id))?;
+
+                let text = if flags & MESSAGE_RESOURCE_UNICODE != 0 {
+                    let units: Vec<u16> = text
+                        .chunks_exact(2)
+                        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                        .collect();
+                    String::from_utf16_lossy(&units)
+                } else {
+                    text.iter().map(|byte| char::from(*byte)).collect()
+                };
+
+                messages.insert(message_id, text.trim_end_matches('\0').to_owned());
+                entry += length;
+            }
+        }
+
+        Ok(Self { language, messages })
+    }
+
+    pub fn get(&self, message_id: u32) -> Option<&str> {
+        self.messages.get(&message_id).map(String::as_str)
+    }
+
+    /// Format a message with the same expansion rules as `EvtFormatMessage`.
+    ///
+    /// `parameter` resolves `%%NNNN` insertions, usually from the message table of the publisher's parameter file.
+    pub fn format<F>(&self, message_id: u32, insertions: &[String], parameter: F) -> Option<String>
+    where
+        F: Fn(u32) -> Option<String>,
+    {
+        self.get(message_id)
+            .map(|template| expand_message(template, insertions, parameter))
+    }
+}
+
+/// Message ID of an event of a classic event source, built from the event's qualifiers and ID.
+pub fn classic_message_id(qualifiers: u16, event_id: u16) -> u32 {
+    ((qualifiers as u32) << 16) | event_id as u32
+}
+
+/// Compiled instrumentation manifest of a `WEVT_TEMPLATE` resource.
+///
+/// Only the `CRIM` header and the provider list are decoded. The provider data, i.e. the event, template and name
+/// tables, is kept as it is, at the offsets listed in `providers`. Messages of manifest-based providers therefore
+/// cannot be formatted from this resource; add the provider's instrumentation manifest to a `MessageCatalog` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WevtTemplate {
+    pub language: u16,
+    pub major_version: u16,
+    pub minor_version: u16,
+    /// Provider GUIDs (formatted like `{xxxxxxxx-...}`) and the offsets of their data.
+    pub providers: Vec<(String, u32)>,
+    /// The whole resource, including the undecoded provider data.
+    pub data: Vec<u8>,
+}
+
+impl WevtTemplate {
+    pub fn parse(language: u16, data: Vec<u8>) -> Result<Self, String> {
+        if data.get(0..4) != Some(b"CRIM") {
+            return Err("WEVT_TEMPLATE resource without CRIM signature".to_owned());
+        }
+
+        let provider_count = read_u32(&data, 12)? as usize;
+        let providers = (0..provider_count)
+            .map(|index| {
+                let descriptor = 16 + index * 20;
+                let guid = data
+                    .get(descriptor..descriptor + 16)
+                    .ok_or("WEVT_TEMPLATE provider list is out of bounds")?;
+                let guid = GUID {
+                    data1: u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                    data2: u16::from_le_bytes([guid[4], guid[5]]),
+                    data3: u16::from_le_bytes([guid[6], guid[7]]),
+                    data4: [
+                        guid[8], guid[9], guid[10], guid[11], guid[12], guid[13], guid[14],
+                        guid[15],
+                    ],
+                };
+
+                Ok((format_guid(&guid), read_u32(&data, descriptor + 16)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            language,
+            major_version: read_u16(&data, 8)?,
+            minor_version: read_u16(&data, 10)?,
+            providers,
+            data,
+        })
+    }
+}
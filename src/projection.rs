@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use windows_strings::HSTRING;
+use windows_sys::Win32::System::EventLog::{EvtRenderContextValues, EvtRenderEventValues};
+
+use crate::model::{
+    event_render_into_buffer, EventRenderContext, EventVariantBuffer, EventVariantValue,
+    WindowsEventHandle,
+};
+
+/// Selection of event values by XPath value paths, rendered with a single `EvtRender` call per event.
+///
+/// The render context is created once, so a projection is meant to be reused for all events of a subscription or
+/// query. Only the selected values are rendered, which is much cheaper than rendering the XML or all user data.
+pub struct Projection {
+    names: Arc<[String]>,
+    paths: Vec<String>,
+    context: EventRenderContext,
+}
+
+impl Projection {
+    /// Create a projection of named value paths, e.g.
+    /// `("TargetUserName", "Event/EventData/Data[@Name='TargetUserName']")` or `("EventID", "Event/System/EventID")`.
+    ///
+    /// Fails if no fields are given, a name is used twice or a value path is not a valid XPath expression.
+    pub fn new(fields: &[(&str, &str)]) -> Result<Self, String> {
+        if fields.is_empty() {
+            return Err("A projection requires at least one field".to_owned());
+        }
+
+        for (index, (name, _)) in fields.iter().enumerate() {
+            if fields[..index].iter().any(|(other, _)| other == name) {
+                return Err(format!("Duplicate projection field '{}'", name));
+            }
+        }
+
+        let paths: Vec<HSTRING> = fields
+            .iter()
+            .map(|(_, path)| HSTRING::from(*path))
+            .collect();
+        let path_ptrs: Vec<_> = paths.iter().map(|path| path.as_ptr()).collect();
+
+        let context =
+            EventRenderContext::create(&path_ptrs, EvtRenderContextValues).map_err(|error| {
+                format!(
+                    "Failed to create render context for projection: {:?}",
+                    error.message()
+                )
+            })?;
+
+        Ok(Self {
+            names: fields.iter().map(|(name, _)| name.to_string()).collect(),
+            paths: fields.iter().map(|(_, path)| path.to_string()).collect(),
+            context,
+        })
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Render the selected values of an event.
+    ///
+    /// Values that do not exist in the event, e.g. a `Data` element of another event ID, are `EventVariantValue::Null`.
+    pub fn render<T: WindowsEventHandle>(&self, event: &T) -> Result<ProjectedRecord, String> {
+        let (raw_buffer, property_count) =
+            event_render_into_buffer(&self.context, event.get_handle(), EvtRenderEventValues)?;
+        let buffer = unsafe { EventVariantBuffer::from_raw_buffer(raw_buffer, property_count) };
+
+        Ok(ProjectedRecord {
+            names: self.names.clone(),
+            values: buffer.into_iter().collect(),
+        })
+    }
+}
+
+/// Values of one event selected by a `Projection`, in the order of the projection's fields.
+///
+/// The field names are shared by all records of a projection.
+#[derive(Debug)]
+pub struct ProjectedRecord {
+    names: Arc<[String]>,
+    values: Vec<EventVariantValue>,
+}
+
+impl ProjectedRecord {
+    pub fn new(names: Arc<[String]>, values: Vec<EventVariantValue>) -> Self {
+        Self { names, values }
+    }
+
+    /// Get the value of a field. Fields missing in the event are `EventVariantValue::Null`.
+    pub fn get(&self, name: &str) -> Option<&EventVariantValue> {
+        self.names
+            .iter()
+            .position(|field| field == name)
+            .and_then(|index| self.values.get(index))
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(&str, &EventVariantValue)> {
+        Some((self.names.get(index)?.as_str(), self.values.get(index)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &EventVariantValue)> {
+        self.names
+            .iter()
+            .map(String::as_str)
+            .zip(self.values.iter())
+    }
+
+    pub fn into_values(self) -> Vec<EventVariantValue> {
+        self.values
+    }
+}
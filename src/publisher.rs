@@ -0,0 +1,459 @@
+use std::cell::RefCell;
+use std::ptr::null;
+use std::sync::Arc;
+
+use windows_result::{Error as WindowsError, HRESULT};
+use windows_strings::HSTRING;
+use windows_sys::core::GUID;
+use windows_sys::Win32::Foundation::{ERROR_NO_MORE_ITEMS, FALSE};
+use windows_sys::Win32::System::EventLog::*;
+
+use crate::cache::LruCache;
+use crate::model::{
+    format_message, get_variant_property, EventVariantValue, OwnedWindowsEventHandle,
+    WindowsEventHandle,
+};
+use crate::session::{session_handle, session_target, RemoteSession};
+
+static NULL_EVT_HANDLE: EVT_HANDLE = 0 as EVT_HANDLE;
+
+/// Message ID used by the publisher metadata for entries without a message.
+static NO_MESSAGE_ID: u32 = u32::MAX;
+
+/// Default number of publishers kept open by the per-thread cache used for message rendering.
+pub static DEFAULT_PUBLISHER_CACHE_CAPACITY: usize = 64;
+
+/// Named value defined by a publisher, like a level, task, opcode, keyword or channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublisherMetadataEntry {
+    /// Name of the entry in the instrumentation manifest, e.g. `win:Informational`, or the path for channels.
+    pub name: String,
+    /// Value of the entry. For opcodes, the high word contains the task and the low word the opcode.
+    pub value: u64,
+    /// Localized display name, if the publisher provides one.
+    pub message: Option<String>,
+}
+
+/// Definition of an event in the metadata of its publisher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublisherEventMetadata {
+    /// Event ID. For classic event sources, the qualifiers are in the high word.
+    pub id: u32,
+    pub version: u8,
+    pub channel: u32,
+    pub level: u32,
+    pub opcode: u32,
+    pub task: u32,
+    pub keywords: u64,
+    /// Localized message template, with the insertions (`%1`, ...) left in place.
+    pub message: Option<String>,
+    /// XML template of the event's user data.
+    pub template: Option<String>,
+}
+
+/// Metadata of an event publisher, opened with `EvtOpenPublisherMetadata`.
+///
+/// The handle is closed when the struct is dropped.
+pub struct PublisherMetadata {
+    handle: OwnedWindowsEventHandle,
+    provider_name: String,
+    locale: u32,
+    // Keeps the remote session open for as long as the metadata exists.
+    _session: Option<RemoteSession>,
+}
+
+impl PublisherMetadata {
+    /// Open the metadata of a publisher registered on the computer of `session`, or on the local computer.
+    ///
+    /// `locale` selects the language of the localized strings, `0` selects the locale of the calling thread.
+    pub fn open(
+        session: Option<&RemoteSession>,
+        provider_name: &str,
+        locale: u32,
+    ) -> Result<Self, String> {
+        let provider = HSTRING::from(provider_name);
+
+        let handle = unsafe {
+            EvtOpenPublisherMetadata(
+                session_handle(session),
+                provider.as_ptr(),
+                null(),
+                locale,
+                0,
+            )
+        };
+
+        if handle == NULL_EVT_HANDLE {
+            let last_error = WindowsError::from_win32();
+            return Err(format!(
+                "Failed to open publisher metadata for '{}' on {}: {:?}",
+                provider_name,
+                session_target(session),
+                last_error.message()
+            ));
+        }
+
+        Ok(Self {
+            handle: OwnedWindowsEventHandle::new(handle),
+            provider_name: provider_name.to_owned(),
+            locale,
+            _session: session.cloned(),
+        })
+    }
+
+    pub fn provider_name(&self) -> &str {
+        &self.provider_name
+    }
+
+    pub fn locale(&self) -> u32 {
+        self.locale
+    }
+
+    /// Get the underlying `EVT_HANDLE` of the publisher metadata.
+    pub fn get_handle(&self) -> &EVT_HANDLE {
+        self.handle.get_handle()
+    }
+
+    pub fn guid(&self) -> Result<Option<GUID>, String> {
+        Ok(match self.property(EvtPublisherMetadataPublisherGuid)? {
+            EventVariantValue::Guid(guid) => Some(*guid),
+            _ => None,
+        })
+    }
+
+    /// Path of the file containing the publisher's event messages.
+    pub fn message_file_path(&self) -> Result<Option<String>, String> {
+        self.string_property(EvtPublisherMetadataMessageFilePath)
+    }
+
+    /// Path of the file containing the strings used for `%%N` parameter insertions.
+    pub fn parameter_file_path(&self) -> Result<Option<String>, String> {
+        self.string_property(EvtPublisherMetadataParameterFilePath)
+    }
+
+    /// Path of the file containing the publisher's templates and metadata resources.
+    pub fn resource_file_path(&self) -> Result<Option<String>, String> {
+        self.string_property(EvtPublisherMetadataResourceFilePath)
+    }
+
+    /// Localized display name of the publisher.
+    pub fn message(&self) -> Result<Option<String>, String> {
+        match self
+            .property(EvtPublisherMetadataPublisherMessageID)?
+            .as_u32()
+        {
+            Some(message_id) => self.format_message_id(message_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Channels the publisher writes to. The entry names are the channel paths.
+    pub fn channels(&self) -> Result<Vec<PublisherMetadataEntry>, String> {
+        self.entries(
+            EvtPublisherMetadataChannelReferences,
+            EvtPublisherMetadataChannelReferencePath,
+            EvtPublisherMetadataChannelReferenceID,
+            EvtPublisherMetadataChannelReferenceMessageID,
+        )
+    }
+
+    pub fn levels(&self) -> Result<Vec<PublisherMetadataEntry>, String> {
+        self.entries(
+            EvtPublisherMetadataLevels,
+            EvtPublisherMetadataLevelName,
+            EvtPublisherMetadataLevelValue,
+            EvtPublisherMetadataLevelMessageID,
+        )
+    }
+
+    pub fn tasks(&self) -> Result<Vec<PublisherMetadataEntry>, String> {
+        self.entries(
+            EvtPublisherMetadataTasks,
+            EvtPublisherMetadataTaskName,
+            EvtPublisherMetadataTaskValue,
+            EvtPublisherMetadataTaskMessageID,
+        )
+    }
+
+    pub fn opcodes(&self) -> Result<Vec<PublisherMetadataEntry>, String> {
+        self.entries(
+            EvtPublisherMetadataOpcodes,
+            EvtPublisherMetadataOpcodeName,
+            EvtPublisherMetadataOpcodeValue,
+            EvtPublisherMetadataOpcodeMessageID,
+        )
+    }
+
+    pub fn keywords(&self) -> Result<Vec<PublisherMetadataEntry>, String> {
+        self.entries(
+            EvtPublisherMetadataKeywords,
+            EvtPublisherMetadataKeywordName,
+            EvtPublisherMetadataKeywordValue,
+            EvtPublisherMetadataKeywordMessageID,
+        )
+    }
+
+    /// Definitions of the events of the publisher, read with `EvtOpenEventMetadataEnum`.
+    pub fn events(&self) -> Result<Vec<PublisherEventMetadata>, String> {
+        let enumeration = unsafe { EvtOpenEventMetadataEnum(*self.get_handle(), 0) };
+        if enumeration == NULL_EVT_HANDLE {
+            return Err(self.error("enumerate events", WindowsError::from_win32()));
+        }
+        let enumeration = OwnedWindowsEventHandle::new(enumeration);
+
+        let mut events = Vec::new();
+        loop {
+            let event = unsafe { EvtNextEventMetadata(*enumeration.get_handle(), 0) };
+            if event == NULL_EVT_HANDLE {
+                let last_error = WindowsError::from_win32();
+                if last_error.code() == HRESULT::from_win32(ERROR_NO_MORE_ITEMS) {
+                    break;
+                }
+                return Err(self.error("enumerate events", last_error));
+            }
+            let event = OwnedWindowsEventHandle::new(event);
+
+            let property = |property_id: EVT_EVENT_METADATA_PROPERTY_ID| {
+                get_variant_property(|size, buffer, used| unsafe {
+                    EvtGetEventMetadataProperty(
+                        *event.get_handle(),
+                        property_id,
+                        0,
+                        size,
+                        buffer,
+                        used,
+                    )
+                })
+                .map_err(|error| {
+                    self.error(
+                        &format!("get event metadata property {}", property_id),
+                        error,
+                    )
+                })
+            };
+            let number = |property_id| -> Result<u64, String> {
+                Ok(property(property_id)?.as_u64().unwrap_or(0))
+            };
+
+            events.push(PublisherEventMetadata {
+                id: number(EventMetadataEventID)? as u32,
+                version: number(EventMetadataEventVersion)? as u8,
+                channel: number(EventMetadataEventChannel)? as u32,
+                level: number(EventMetadataEventLevel)? as u32,
+                opcode: number(EventMetadataEventOpcode)? as u32,
+                task: number(EventMetadataEventTask)? as u32,
+                keywords: number(EventMetadataEventKeyword)?,
+                // Events without a message are common, so formatting errors only leave the message empty.
+                message: property(EventMetadataEventMessageID)?
+                    .as_u32()
+                    .and_then(|message_id| self.format_message_id(message_id).ok())
+                    .flatten(),
+                template: property(EventMetadataEventTemplate)?
+                    .as_str()
+                    .filter(|template| !template.is_empty())
+                    .map(str::to_owned),
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Format a message of the publisher's message table. Returns `None` for the "no message" ID.
+    pub fn format_message_id(&self, message_id: u32) -> Result<Option<String>, String> {
+        if message_id == NO_MESSAGE_ID {
+            return Ok(None);
+        }
+
+        format_message(
+            *self.get_handle(),
+            NULL_EVT_HANDLE,
+            message_id,
+            EvtFormatMessageId,
+        )
+        .map(Some)
+        .map_err(|error| self.error(&format!("format message {}", message_id), error))
+    }
+
+    fn error(&self, action: &str, error: WindowsError) -> String {
+        format!(
+            "Failed to {} of publisher '{}': {:?}",
+            action,
+            self.provider_name,
+            error.message()
+        )
+    }
+
+    fn property(
+        &self,
+        property_id: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+    ) -> Result<EventVariantValue, String> {
+        get_variant_property(|size, buffer, used| unsafe {
+            EvtGetPublisherMetadataProperty(*self.get_handle(), property_id, 0, size, buffer, used)
+        })
+        .map_err(|error| self.error(&format!("get metadata property {}", property_id), error))
+    }
+
+    fn string_property(
+        &self,
+        property_id: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+    ) -> Result<Option<String>, String> {
+        Ok(match self.property(property_id)? {
+            EventVariantValue::String(value) if !value.is_empty() => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Read the entries of one of the object array properties (levels, tasks, ...).
+    fn entries(
+        &self,
+        array_property: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+        name_property: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+        value_property: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+        message_property: EVT_PUBLISHER_METADATA_PROPERTY_ID,
+    ) -> Result<Vec<PublisherMetadataEntry>, String> {
+        let array = match self.property(array_property)? {
+            EventVariantValue::EvtHandle(handle) if handle != NULL_EVT_HANDLE => {
+                OwnedWindowsEventHandle::new(handle)
+            }
+            EventVariantValue::EvtHandle(_) | EventVariantValue::Null => return Ok(Vec::new()),
+            other => {
+                return Err(format!(
+                    "Unexpected value for metadata property {} of publisher '{}': {:?}",
+                    array_property, self.provider_name, other
+                ))
+            }
+        };
+
+        let mut array_size: u32 = 0;
+        if unsafe { EvtGetObjectArraySize(*array.get_handle(), &mut array_size) } == FALSE {
+            return Err(self.error(
+                &format!("get size of metadata property {}", array_property),
+                WindowsError::from_win32(),
+            ));
+        }
+
+        (0..array_size)
+            .map(|index| {
+                let item = |property_id: EVT_PUBLISHER_METADATA_PROPERTY_ID| {
+                    get_variant_property(|size, buffer, used| unsafe {
+                        EvtGetObjectArrayProperty(
+                            *array.get_handle(),
+                            property_id as u32,
+                            index,
+                            0,
+                            size,
+                            buffer,
+                            used,
+                        )
+                    })
+                    .map_err(|error| {
+                        self.error(&format!("get metadata property {}", property_id), error)
+                    })
+                };
+
+                Ok(PublisherMetadataEntry {
+                    name: item(name_property)?.as_str().unwrap_or_default().to_owned(),
+                    value: item(value_property)?.as_u64().unwrap_or(0),
+                    // A missing display name does not make the entry itself unusable.
+                    message: item(message_property)?
+                        .as_u32()
+                        .and_then(|message_id| self.format_message_id(message_id).ok())
+                        .flatten(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Key of the publisher metadata cache: the provider name, the session it was opened in and the locale.
+///
+/// Sessions are told apart by `RemoteSession::id`, not by host, as sessions to the same host may use different
+/// credentials. The cached metadata keeps its session alive, so the ID cannot be reused by another session meanwhile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PublisherCacheKey {
+    session: Option<usize>,
+    provider_name: String,
+    locale: u32,
+}
+
+/// Bounded cache of open publisher metadata, keyed by provider name.
+///
+/// Failed opens are cached as well, so a missing provider is not looked up again for every event. Evicted metadata
+/// is closed once the last clone handed out by the cache is dropped.
+pub struct PublisherMetadataCache {
+    entries: LruCache<PublisherCacheKey, Result<Arc<PublisherMetadata>, String>>,
+}
+
+impl PublisherMetadataCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::new(capacity),
+        }
+    }
+
+    /// Get the metadata of a publisher in the given locale, opening it if it is not cached yet.
+    ///
+    /// If the publisher could not be opened, the same error is returned until the entry is evicted.
+    pub fn get_or_open(
+        &mut self,
+        session: Option<&RemoteSession>,
+        provider_name: &str,
+        locale: u32,
+    ) -> Result<Arc<PublisherMetadata>, String> {
+        let key = PublisherCacheKey {
+            session: session.map(RemoteSession::id),
+            provider_name: provider_name.to_owned(),
+            locale,
+        };
+
+        if let Some(entry) = self.entries.get(&key) {
+            return entry.clone();
+        }
+
+        let entry = PublisherMetadata::open(session, provider_name, locale).map(Arc::new);
+        self.entries.insert(key, entry.clone());
+        entry
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+thread_local! {
+    static PUBLISHER_CACHE: RefCell<PublisherMetadataCache> =
+        RefCell::new(PublisherMetadataCache::new(DEFAULT_PUBLISHER_CACHE_CAPACITY));
+}
+
+/// Run `f` with the metadata of a publisher from the cache of the current thread.
+///
+/// The cache is not borrowed while `f` runs, so `f` may look up other publishers.
+pub fn with_publisher_metadata<R, F>(
+    session: Option<&RemoteSession>,
+    provider_name: &str,
+    locale: u32,
+    f: F,
+) -> Result<R, String>
+where
+    F: FnOnce(&PublisherMetadata) -> Result<R, String>,
+{
+    let metadata = PUBLISHER_CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .get_or_open(session, provider_name, locale)
+    })?;
+    f(&metadata)
+}
+
+/// Replace the publisher metadata cache of the current thread with an empty cache of the given capacity.
+pub fn set_publisher_cache_capacity(capacity: usize) {
+    PUBLISHER_CACHE.with(|cache| *cache.borrow_mut() = PublisherMetadataCache::new(capacity));
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+
+#[cfg(windows)]
+use crate::conversions::WindowsConversionTo;
+use crate::event_data::EventData;
+#[cfg(windows)]
+use crate::model::{format_guid, EventSystemContext, WindowsEventRender};
+#[cfg(windows)]
+use crate::sink::RecordFields;
+
+/// Owned representation of an event as written by the event sinks.
+///
+/// Unlike the event handle, a record can be kept after the next batch of events is read and can be built by hand,
+/// e.g. to test a sink.
+#[derive(Debug, Default)]
+pub struct EventRecord {
+    pub provider_name: String,
+    /// Provider GUID in registry format, e.g. `{54849625-5478-4994-a5ba-3e3b0328c30d}`.
+    pub provider_guid: Option<String>,
+    pub event_id: u16,
+    pub qualifiers: Option<u16>,
+    pub version: Option<u8>,
+    pub level: Option<u8>,
+    pub task: Option<u16>,
+    pub opcode: Option<u8>,
+    pub keywords: Option<u64>,
+    pub time_created: DateTime<Utc>,
+    pub event_record_id: u64,
+    pub activity_id: Option<String>,
+    pub related_activity_id: Option<String>,
+    pub process_id: Option<u32>,
+    pub thread_id: Option<u32>,
+    pub channel: Option<String>,
+    pub computer: Option<String>,
+    /// Security identifier of the user in its string form, e.g. `S-1-5-18`.
+    pub user_id: Option<String>,
+    pub event_data: EventData,
+    pub message: Option<String>,
+    pub xml: Option<String>,
+}
+
+impl EventRecord {
+    /// Create a record with the system properties of an event and no event data, message or XML.
+    #[cfg(windows)]
+    pub fn from_system_context(system: EventSystemContext) -> Self {
+        Self {
+            provider_name: system.provider_name,
+            provider_guid: system.provider_guid.as_ref().map(format_guid),
+            event_id: system.event_id,
+            qualifiers: system.qualifiers,
+            version: system.version,
+            level: system.level,
+            task: system.task,
+            opcode: system.opcode,
+            keywords: system.keywords.map(|keywords| keywords as u64),
+            time_created: system.time_created.win_into(),
+            event_record_id: system.event_record_id,
+            activity_id: system.activity_id.as_ref().map(format_guid),
+            related_activity_id: system.related_activity_id.as_ref().map(format_guid),
+            process_id: system.process_id,
+            thread_id: system.thread_id,
+            channel: system.channel,
+            computer: system.computer,
+            user_id: system.user_id,
+            event_data: EventData::default(),
+            message: None,
+            xml: None,
+        }
+    }
+
+    /// Render the parts of an event selected by `fields`.
+    ///
+    /// The system properties are always rendered. Events whose message cannot be formatted, e.g. because the
+    /// publisher is not installed, get no message instead of failing.
+    #[cfg(windows)]
+    pub fn render<T: WindowsEventRender + ?Sized>(
+        event: &T,
+        fields: &RecordFields,
+    ) -> Result<Self, String> {
+        let mut record = Self::from_system_context(event.render_system_context()?);
+
+        if fields.event_data {
+            record.event_data = event.render_event_data()?;
+        }
+        if fields.message {
+            record.message = event.render_message().ok();
+        }
+        if fields.xml {
+            record.xml = Some(event.render_xml()?);
+        }
+
+        Ok(record)
+    }
+}
@@ -0,0 +1,128 @@
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+
+use windows_strings::PCWSTR;
+use windows_sys::core::PCWSTR as PCWSTR_SYS;
+
+use crate::cache::LruCache;
+use crate::model::EventRenderContext;
+
+/// Number of render contexts kept per thread. Each distinct set of value paths uses one context.
+pub static RENDER_CONTEXT_CACHE_CAPACITY: usize = 32;
+
+/// Number of scratch buffers kept per thread. More than one is needed when rendered buffers are alive while other
+/// values of the same event are rendered.
+static MAX_POOLED_BUFFERS: usize = 4;
+
+/// Scratch buffers growing beyond this size, e.g. for the XML of an unusually large event, are released after use.
+static MAX_POOLED_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Key of a cached render context: the context flags, followed by the UTF-16 value paths it was created for, each
+/// terminated by a null character.
+///
+/// The key borrows as `[u16]`, so a cached context is found by encoding the key into a reused buffer, without an
+/// allocation per render.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RenderContextKey(Vec<u16>);
+
+impl Borrow<[u16]> for RenderContextKey {
+    fn borrow(&self) -> &[u16] {
+        &self.0
+    }
+}
+
+/// Replace the contents of `key` with the encoded key of a render context, see `RenderContextKey`.
+fn encode_key(flags: u32, value_paths: &[PCWSTR_SYS], key: &mut Vec<u16>) {
+    key.clear();
+    key.extend_from_slice(&[flags as u16, (flags >> 16) as u16]);
+    for path in value_paths {
+        key.extend_from_slice(unsafe { PCWSTR::from_raw(*path).as_wide() });
+        key.push(0);
+    }
+}
+
+thread_local! {
+    static RENDER_CACHING: Cell<bool> = const { Cell::new(true) };
+    static RENDER_CONTEXTS: RefCell<LruCache<RenderContextKey, EventRenderContext>> =
+        RefCell::new(LruCache::new(RENDER_CONTEXT_CACHE_CAPACITY));
+    static RENDER_BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    static RENDER_CONTEXT_KEY: RefCell<Vec<u16>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Enable or disable the reuse of render contexts and scratch buffers on the current thread.
+///
+/// Caching is enabled by default. Disabling it also releases the cached contexts and buffers.
+pub fn set_render_caching(enabled: bool) {
+    RENDER_CACHING.with(|caching| caching.set(enabled));
+
+    if !enabled {
+        RENDER_CONTEXTS.with(|contexts| contexts.borrow_mut().clear());
+        RENDER_BUFFERS.with(|buffers| buffers.borrow_mut().clear());
+    }
+}
+
+pub fn render_caching() -> bool {
+    RENDER_CACHING.with(Cell::get)
+}
+
+/// Run `f` with the render context of the current thread for `flags` and the null-terminated `value_paths`, creating
+/// the context with `create` if needed.
+///
+/// Without caching, a new context is created for every call and closed afterwards.
+pub fn with_render_context<R, C, F>(
+    flags: u32,
+    value_paths: &[PCWSTR_SYS],
+    create: C,
+    f: F,
+) -> Result<R, String>
+where
+    C: FnOnce() -> Result<EventRenderContext, String>,
+    F: FnOnce(&EventRenderContext) -> R,
+{
+    if !render_caching() {
+        return Ok(f(&create()?));
+    }
+
+    RENDER_CONTEXT_KEY.with(|key| {
+        let mut key = key.borrow_mut();
+        encode_key(flags, value_paths, &mut key);
+
+        RENDER_CONTEXTS.with(|contexts| {
+            let mut contexts = contexts.borrow_mut();
+            if !contexts.contains_key(key.as_slice()) {
+                contexts.insert(RenderContextKey(key.clone()), create()?);
+            }
+            let context = contexts
+                .get(key.as_slice())
+                .expect("context was inserted above");
+            Ok(f(context))
+        })
+    })
+}
+
+/// Take a scratch buffer of the current thread. Its length is its full capacity, which is zero for a new buffer.
+pub fn take_render_buffer() -> Vec<u8> {
+    if !render_caching() {
+        return Vec::new();
+    }
+
+    let mut buffer = RENDER_BUFFERS
+        .with(|buffers| buffers.borrow_mut().pop())
+        .unwrap_or_default();
+    buffer.resize(buffer.capacity(), 0);
+    buffer
+}
+
+/// Return a scratch buffer taken with `take_render_buffer`, so the next render can skip the size probe.
+pub fn recycle_render_buffer(buffer: Vec<u8>) {
+    if !render_caching() || buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_BUFFER_SIZE {
+        return;
+    }
+
+    RENDER_BUFFERS.with(|buffers| {
+        let mut buffers = buffers.borrow_mut();
+        if buffers.len() < MAX_POOLED_BUFFERS {
+            buffers.push(buffer);
+        }
+    });
+}
@@ -0,0 +1,200 @@
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::Arc;
+
+use windows_result::Error as WindowsError;
+use windows_sys::Win32::System::EventLog::*;
+use zeroize::Zeroize;
+
+static NULL_EVT_HANDLE: EVT_HANDLE = 0 as EVT_HANDLE;
+
+/// Authentication method used by `EvtOpenSession` to log on to the remote computer.
+///
+/// See https://learn.microsoft.com/en-us/windows/win32/api/winevt/ne-winevt-evt_rpc_login_flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemoteAuthMethod {
+    #[default]
+    Default,
+    Negotiate,
+    Kerberos,
+    Ntlm,
+}
+
+impl RemoteAuthMethod {
+    fn as_flags(self) -> u32 {
+        match self {
+            RemoteAuthMethod::Default => EvtRpcLoginAuthDefault,
+            RemoteAuthMethod::Negotiate => EvtRpcLoginAuthNegotiate,
+            RemoteAuthMethod::Kerberos => EvtRpcLoginAuthKerberos,
+            RemoteAuthMethod::Ntlm => EvtRpcLoginAuthNTLM,
+        }
+    }
+}
+
+/// Null-terminated UTF-16 copies of the login data handed to `EvtOpenSession`.
+///
+/// The buffers are overwritten with zeros when the struct is dropped, so the credentials do not linger in memory
+/// after the session has been opened.
+struct RemoteLoginBuffers {
+    server: Vec<u16>,
+    domain: Option<Vec<u16>>,
+    user: Option<Vec<u16>>,
+    password: Option<Vec<u16>>,
+}
+
+impl RemoteLoginBuffers {
+    fn new(server: &str, domain: Option<&str>, user: Option<&str>, password: Option<&str>) -> Self {
+        let to_wide = |value: &str| -> Vec<u16> { value.encode_utf16().chain(Some(0)).collect() };
+
+        Self {
+            server: to_wide(server),
+            domain: domain.map(to_wide),
+            user: user.map(to_wide),
+            password: password.map(to_wide),
+        }
+    }
+
+    /// Build the `EVT_RPC_LOGIN` structure. The pointers it contains are only valid as long as `self` is alive.
+    fn as_login(&mut self, auth_method: RemoteAuthMethod) -> EVT_RPC_LOGIN {
+        let as_pwstr = |value: &mut Option<Vec<u16>>| {
+            value
+                .as_mut()
+                .map_or(std::ptr::null_mut(), |buffer| buffer.as_mut_ptr())
+        };
+
+        EVT_RPC_LOGIN {
+            Server: self.server.as_mut_ptr(),
+            User: as_pwstr(&mut self.user),
+            Domain: as_pwstr(&mut self.domain),
+            Password: as_pwstr(&mut self.password),
+            Flags: auth_method.as_flags(),
+        }
+    }
+}
+
+impl Drop for RemoteLoginBuffers {
+    fn drop(&mut self) {
+        self.server.zeroize();
+        self.domain.zeroize();
+        self.user.zeroize();
+        self.password.zeroize();
+    }
+}
+
+struct RemoteSessionHandle {
+    handle: EVT_HANDLE,
+    server: String,
+}
+
+/// Implement trait `Drop` to enforce proper disposal of the underlying Windows object.
+impl Drop for RemoteSessionHandle {
+    fn drop(&mut self) {
+        if self.handle != NULL_EVT_HANDLE {
+            unsafe {
+                EvtClose(self.handle);
+            }
+        }
+    }
+}
+
+/// Session on a remote computer, opened with `EvtOpenSession`.
+///
+/// Subscriptions, queries and publisher metadata opened against a session keep a clone of it, so the underlying
+/// handle stays open for as long as anything still uses it.
+///
+/// Note that `EvtOpenSession` does not connect to the remote computer. Connection and authentication errors are only
+/// reported by the first call that uses the session, which is why those calls include the host name in their errors.
+#[derive(Clone)]
+pub struct RemoteSession {
+    inner: Arc<RemoteSessionHandle>,
+}
+
+impl RemoteSession {
+    /// Open a session on `server`.
+    ///
+    /// # Parameters
+    /// - `server`: Name or address of the remote computer.
+    /// - `domain`, `user`, `password`: Credentials to log on with. If `None`, the credentials of the current user
+    ///   are used.
+    /// - `auth_method`: Authentication method used for the logon.
+    pub fn open(
+        server: &str,
+        domain: Option<&str>,
+        user: Option<&str>,
+        password: Option<&str>,
+        auth_method: RemoteAuthMethod,
+    ) -> Result<Self, String> {
+        let mut buffers = RemoteLoginBuffers::new(server, domain, user, password);
+        let login = buffers.as_login(auth_method);
+
+        let handle = unsafe {
+            EvtOpenSession(
+                EvtRpcLogin,
+                &login as *const EVT_RPC_LOGIN as *const c_void,
+                0,
+                0,
+            )
+        };
+
+        // Zero the credentials right away instead of waiting for the end of the scope.
+        drop(buffers);
+
+        if handle == NULL_EVT_HANDLE {
+            let last_error = WindowsError::from_win32();
+            return Err(format!(
+                "Failed to open session on remote host '{}': {:?}",
+                server,
+                last_error.message()
+            ));
+        }
+
+        Ok(Self {
+            inner: Arc::new(RemoteSessionHandle {
+                handle,
+                server: server.to_owned(),
+            }),
+        })
+    }
+
+    /// Name of the remote computer the session was opened for.
+    pub fn server(&self) -> &str {
+        &self.inner.server
+    }
+
+    /// Identity of the session, shared by its clones and different for every opened session.
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+
+    /// Number of clones of the session that are alive, including those kept by subscriptions, queries and publisher
+    /// metadata. The handle is closed when the last clone is dropped.
+    pub fn use_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+
+    /// Get the underlying `EVT_HANDLE` of the session.
+    pub fn get_handle(&self) -> &EVT_HANDLE {
+        &self.inner.handle
+    }
+}
+
+impl fmt::Debug for RemoteSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSession")
+            .field("server", &self.inner.server)
+            .finish()
+    }
+}
+
+/// Get the session handle to pass to the Windows API. `None` selects the local computer (`NULL` session).
+pub fn session_handle(session: Option<&RemoteSession>) -> EVT_HANDLE {
+    session.map_or(NULL_EVT_HANDLE, |session| *session.get_handle())
+}
+
+/// Describe the computer an operation runs on, for use in error messages.
+pub fn session_target(session: Option<&RemoteSession>) -> String {
+    match session {
+        Some(session) => format!("remote host '{}'", session.server()),
+        None => "local computer".to_owned(),
+    }
+}
@@ -0,0 +1,131 @@
+use crate::record::EventRecord;
+
+/// Destination of rendered events, e.g. a file or a log server.
+///
+/// Sinks may buffer records. Only records written before a successful `flush` are guaranteed to be delivered, so a
+/// bookmark must not be saved before the sink was flushed (see `CheckpointedSink`).
+pub trait EventSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String>;
+    fn flush(&mut self) -> Result<(), String>;
+}
+
+impl<S: EventSink + ?Sized> EventSink for Box<S> {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        (**self).write(record)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        (**self).flush()
+    }
+}
+
+/// Parts of an event that are rendered and written by a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordFields {
+    /// The system properties, e.g. provider, event ID and time. The sinks need some of them for their own headers,
+    /// so this only controls whether they are written as separate fields.
+    pub system: bool,
+    pub event_data: bool,
+    pub message: bool,
+    pub xml: bool,
+}
+
+impl RecordFields {
+    pub fn all() -> Self {
+        Self {
+            system: true,
+            event_data: true,
+            message: true,
+            xml: true,
+        }
+    }
+}
+
+/// All fields except the raw XML, which is as large as all other fields together.
+impl Default for RecordFields {
+    fn default() -> Self {
+        Self {
+            xml: false,
+            ..Self::all()
+        }
+    }
+}
+
+/// Sink wrapper that flushes every `batch_size` records and then saves a checkpoint.
+///
+/// The checkpoint is usually the XML of a bookmark that is updated after every written event. As the sink is flushed
+/// first, a saved bookmark never points beyond the delivered events: after a crash, events are delivered again
+/// rather than lost.
+pub struct CheckpointedSink<S, C>
+where
+    S: EventSink,
+    C: FnMut() -> Result<(), String>,
+{
+    sink: S,
+    checkpoint: C,
+    batch_size: usize,
+    pending: usize,
+}
+
+impl<S, C> CheckpointedSink<S, C>
+where
+    S: EventSink,
+    C: FnMut() -> Result<(), String>,
+{
+    /// Wrap a sink. A batch size of zero is treated as one, i.e. a checkpoint after every record.
+    pub fn new(sink: S, batch_size: usize, checkpoint: C) -> Self {
+        Self {
+            sink,
+            checkpoint,
+            batch_size: batch_size.max(1),
+            pending: 0,
+        }
+    }
+
+    /// Write a record. The checkpoint of the record, e.g. the bookmark update, must be done before the next call of
+    /// `checkpoint_if_due`.
+    pub fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        self.sink.write(record)?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Flush the sink and save a checkpoint if `batch_size` records were written since the last checkpoint.
+    ///
+    /// Returns whether a checkpoint was saved.
+    pub fn checkpoint_if_due(&mut self) -> Result<bool, String> {
+        if self.pending < self.batch_size {
+            return Ok(false);
+        }
+
+        self.checkpoint()?;
+        Ok(true)
+    }
+
+    /// Flush the sink and save a checkpoint, e.g. before shutting down or when no more events are available.
+    ///
+    /// Does nothing if no records were written since the last checkpoint.
+    pub fn checkpoint(&mut self) -> Result<(), String> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+
+        self.sink.flush()?;
+        (self.checkpoint)()?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Number of records written since the last checkpoint.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Map, Value};
+
+use crate::http::{Backoff, HttpClient, HttpOptions, HttpResponse, RetryPolicy};
+use crate::ndjson::record_to_json;
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+
+/// Settings of a `SplunkSink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplunkOptions {
+    /// Event endpoint, e.g. `https://splunk:8088/services/collector/event`.
+    pub http: HttpOptions,
+    /// HEC token, sent as `Authorization: Splunk <token>`.
+    pub token: String,
+    pub sourcetype: String,
+    /// Index of the events. Without an index, the default index of the token is used.
+    pub index: Option<String>,
+    /// Number of events sent per request.
+    pub batch_size: usize,
+    /// Number of events kept while the endpoint is unreachable. When the queue is full, the oldest event is dropped.
+    pub max_queued: usize,
+    /// Compress requests with gzip.
+    pub gzip: bool,
+    /// Wait for indexer acknowledgement of the sent events in `flush`. The token must have acknowledgement enabled.
+    pub acknowledgement: bool,
+    /// Delay between polls of the acknowledgement status.
+    pub ack_interval: Duration,
+    /// Time after which events that were not acknowledged are sent again.
+    pub ack_timeout: Duration,
+    pub retry: RetryPolicy,
+    /// Parts of the event to send, see `hec_event`.
+    pub fields: RecordFields,
+}
+
+impl SplunkOptions {
+    /// Create settings sending the event XML with the source type `XmlWinEventLog` of the Splunk Add-on for Windows.
+    pub fn new(url: &str, token: &str) -> Self {
+        Self {
+            http: HttpOptions::new(url),
+            token: token.to_owned(),
+            sourcetype: "XmlWinEventLog".to_owned(),
+            index: None,
+            batch_size: 100,
+            max_queued: 10_000,
+            gzip: false,
+            acknowledgement: false,
+            ack_interval: Duration::from_secs(1),
+            ack_timeout: Duration::from_secs(60),
+            retry: RetryPolicy::default(),
+            fields: RecordFields::all(),
+        }
+    }
+}
+
+/// Events sent in one request, waiting for indexer acknowledgement.
+struct PendingBatch {
+    ack_id: u64,
+    events: Vec<Vec<u8>>,
+    sent: Instant,
+}
+
+/// Sink sending events to a Splunk HTTP Event Collector (HEC).
+///
+/// Events are queued by `write` and sent in batches of `SplunkOptions::batch_size`, as soon as a batch is full and by
+/// `flush`. After a retryable status (e.g. 503 when the indexers are busy) or a network error, the events stay queued
+/// and no request is made before the back-off delay of `SplunkOptions::retry` has passed. `write` never waits for the
+/// delay, it makes a single attempt once the delay has passed. `flush` waits and retries; if all retries fail, the
+/// events stay queued for the next attempt. Events rejected with another status are dropped, as sending them again
+/// would fail the same way.
+///
+/// With indexer acknowledgement, `flush` only returns after all sent events were acknowledged, so a checkpoint saved
+/// after it never skips events that were lost by Splunk. Events not acknowledged within `SplunkOptions::ack_timeout`
+/// are queued again, and may then be indexed twice.
+pub struct SplunkSink {
+    options: SplunkOptions,
+    client: HttpClient,
+    /// Path of the acknowledgement endpoint, below the `/services/collector` path of the event endpoint.
+    ack_path: String,
+    queue: VecDeque<Vec<u8>>,
+    pending: Vec<PendingBatch>,
+    backoff: Backoff,
+    dropped: u64,
+}
+
+impl SplunkSink {
+    /// Create a sink. The connection is opened by the first request.
+    pub fn new(options: SplunkOptions) -> Result<Self, String> {
+        let mut http = options.http.clone();
+        http.headers.push((
+            "Authorization".to_owned(),
+            format!("Splunk {}", options.token),
+        ));
+        if options.acknowledgement {
+            http.headers
+                .push(("X-Splunk-Request-Channel".to_owned(), random_channel()));
+        }
+
+        let client = HttpClient::new(http)?;
+        let path = client.path();
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        let ack_path = match path.find("/services/collector") {
+            Some(index) => format!("{}/services/collector/ack", &path[..index]),
+            None => "/services/collector/ack".to_owned(),
+        };
+
+        Ok(Self {
+            options,
+            client,
+            ack_path,
+            queue: VecDeque::new(),
+            pending: Vec::new(),
+            backoff: Backoff::default(),
+            dropped: 0,
+        })
+    }
+
+    /// Number of events waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of sent events waiting for indexer acknowledgement.
+    pub fn pending(&self) -> usize {
+        self.pending.iter().map(|batch| batch.events.len()).sum()
+    }
+
+    /// Number of events dropped because the queue was full, the collector rejected them or their acknowledgement can not
+    /// be tracked.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Send a request. A retryable failure is retried up to `max_retries` times, each after the back-off delay.
+    fn post(
+        &mut self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        max_retries: u32,
+    ) -> Result<HttpResponse, String> {
+        let mut retry = 0;
+        loop {
+            self.backoff.wait();
+            let (error, requested) = match self.client.request("POST", path, headers, body) {
+                Ok(response) if !response.is_retryable() => {
+                    self.backoff.reset();
+                    return Ok(response);
+                }
+                Ok(response) => (
+                    format!("Splunk HEC request failed with status {}", response.status),
+                    response.retry_after(),
+                ),
+                Err(error) => (error, None),
+            };
+
+            self.backoff.failed(&self.options.retry, requested);
+            if retry >= max_retries {
+                return Err(error);
+            }
+            retry += 1;
+        }
+    }
+
+    /// Send the first `count` queued events in one request.
+    fn send(&mut self, count: usize, max_retries: u32) -> Result<(), String> {
+        let mut body = Vec::new();
+        for event in self.queue.iter().take(count) {
+            body.extend_from_slice(event);
+            body.push(b'\n');
+        }
+        let mut headers = vec![("Content-Type", "application/json")];
+        if self.options.gzip {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            body = encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map_err(|error| format!("Failed to compress Splunk HEC request: {}", error))?;
+            headers.push(("Content-Encoding", "gzip"));
+        }
+
+        let path = self.client.path().to_owned();
+        let response = self.post(&path, &headers, &body, max_retries)?;
+        let events: Vec<Vec<u8>> = self.queue.drain(..count).collect();
+        if !response.is_success() {
+            self.dropped += count as u64;
+            return Err(format!(
+                "Splunk HEC request rejected with status {}: {}",
+                response.status,
+                response.body_text()
+            ));
+        }
+
+        if self.options.acknowledgement {
+            let ack_id = serde_json::from_slice::<Value>(&response.body)
+                .ok()
+                .and_then(|body| body["ackId"].as_u64());
+            match ack_id {
+                Some(ack_id) => self.pending.push(PendingBatch {
+                    ack_id,
+                    events,
+                    sent: Instant::now(),
+                }),
+                // The events were accepted, so sending them again would index them twice.
+                None => {
+                    self.dropped += count as u64;
+                    return Err(format!(
+                        "Splunk HEC response has no acknowledgement ID, is acknowledgement enabled for the token? {}",
+                        response.body_text()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send_batches(&mut self, minimum: usize, max_retries: u32) -> Result<(), String> {
+        let batch_size = self.options.batch_size.max(1);
+        while self.queue.len() >= minimum.max(1) {
+            self.send(batch_size.min(self.queue.len()), max_retries)?;
+        }
+        Ok(())
+    }
+
+    /// Query the acknowledgement status of the pending batches and forget the acknowledged ones. Batches that timed out
+    /// are queued again; returns their number of events.
+    fn poll_acks(&mut self, max_retries: u32) -> Result<usize, String> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+
+        let ack_ids: Vec<u64> = self.pending.iter().map(|batch| batch.ack_id).collect();
+        let body = json!({ "acks": ack_ids }).to_string();
+        let path = self.ack_path.clone();
+        let response = self.post(
+            &path,
+            &[("Content-Type", "application/json")],
+            body.as_bytes(),
+            max_retries,
+        )?;
+        if !response.is_success() {
+            return Err(format!(
+                "Splunk HEC acknowledgement query failed with status {}: {}",
+                response.status,
+                response.body_text()
+            ));
+        }
+        let acks = serde_json::from_slice::<Value>(&response.body)
+            .map_err(|error| format!("Failed to parse Splunk HEC acknowledgements: {}", error))?;
+
+        let mut requeued = 0;
+        let mut timed_out = Vec::new();
+        self.pending.retain_mut(|batch| {
+            if acks["acks"][batch.ack_id.to_string()].as_bool() == Some(true) {
+                return false;
+            }
+            if batch.sent.elapsed() >= self.options.ack_timeout {
+                requeued += batch.events.len();
+                timed_out.push(std::mem::take(&mut batch.events));
+                return false;
+            }
+            true
+        });
+        for event in timed_out.into_iter().flatten().rev() {
+            self.queue.push_front(event);
+        }
+        Ok(requeued)
+    }
+}
+
+impl EventSink for SplunkSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        if self.options.max_queued == 0 {
+            self.dropped += 1;
+            return Ok(());
+        }
+        while self.queue.len() >= self.options.max_queued {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        let event = Value::Object(hec_event(record, &self.options));
+        self.queue.push_back(event.to_string().into_bytes());
+
+        if self.queue.len() < self.options.batch_size || self.backoff.is_waiting() {
+            return Ok(());
+        }
+        // After a retryable failure, the events stay queued for a later write or `flush`.
+        let result = self
+            .send_batches(self.options.batch_size, 0)
+            .and_then(|_| self.poll_acks(0));
+        match result {
+            Err(_) if self.backoff.is_failing() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let max_retries = self.options.retry.max_retries;
+        self.send_batches(1, max_retries)?;
+
+        while !self.pending.is_empty() {
+            std::thread::sleep(self.options.ack_interval);
+            let requeued = self.poll_acks(max_retries)?;
+            if requeued > 0 {
+                return Err(format!(
+                    "{} events were not acknowledged by Splunk within {:?} and are queued again",
+                    requeued, self.options.ack_timeout
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert a record to a HEC event.
+///
+/// `time` is the creation time in seconds, `host` the computer and `source` the channel. The event is the XML if
+/// `RecordFields::xml` is set and the record has it, as expected by the `XmlWinEventLog` source type, and otherwise the
+/// JSON object of `record_to_json`.
+pub fn hec_event(record: &EventRecord, options: &SplunkOptions) -> Map<String, Value> {
+    let mut event = Map::new();
+
+    event.insert(
+        "time".to_owned(),
+        (record.time_created.timestamp_millis() as f64 / 1000.0).into(),
+    );
+    if let Some(computer) = &record.computer {
+        event.insert("host".to_owned(), computer.as_str().into());
+    }
+    if let Some(channel) = &record.channel {
+        event.insert("source".to_owned(), channel.as_str().into());
+    }
+    event.insert("sourcetype".to_owned(), options.sourcetype.as_str().into());
+    if let Some(index) = &options.index {
+        event.insert("index".to_owned(), index.as_str().into());
+    }
+
+    let body = match record.xml.as_deref().filter(|_| options.fields.xml) {
+        Some(xml) => Value::from(xml),
+        None => Value::Object(record_to_json(record, &options.fields)),
+    };
+    event.insert("event".to_owned(), body);
+
+    event
+}
+
+/// Random channel identifier in GUID format, required for indexer acknowledgement.
+fn random_channel() -> String {
+    let random = RandomState::new();
+    let mut bytes = [0u8; 16];
+    for (index, half) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = random.build_hasher();
+        hasher.write_usize(index);
+        half.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    // Version 4 (random), variant 1.
+    bytes[6] = (bytes[6] & 0x0F) | 0x40;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
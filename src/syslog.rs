@@ -0,0 +1,254 @@
+use chrono::SecondsFormat;
+
+use crate::record::EventRecord;
+use crate::sink::{EventSink, RecordFields};
+use crate::transport::{NetworkOptions, QueuedSender, Transport};
+
+/// Private enterprise number reserved for documentation (RFC 5612), used in the structured data IDs by default.
+pub static EXAMPLE_ENTERPRISE_NUMBER: u32 = 32473;
+
+/// Settings of a `SyslogSink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogOptions {
+    pub network: NetworkOptions,
+    /// Syslog facility from 0 to 23, e.g. 1 for user-level or 4 for security messages.
+    pub facility: u8,
+    /// Enterprise number of the structured data IDs `event@<number>` (system properties) and `data@<number>`
+    /// (event data).
+    pub enterprise_number: u32,
+    /// Maximum size of a UDP datagram. Longer messages are truncated, as allowed by RFC 5426.
+    pub max_datagram_size: usize,
+    /// Parts of the event to send. The raw XML is never sent.
+    pub fields: RecordFields,
+}
+
+impl SyslogOptions {
+    pub fn new(address: &str, transport: Transport) -> Self {
+        Self {
+            network: NetworkOptions::new(address, transport),
+            facility: 1,
+            enterprise_number: EXAMPLE_ENTERPRISE_NUMBER,
+            max_datagram_size: 2048,
+            fields: RecordFields::default(),
+        }
+    }
+}
+
+/// Sink forwarding events as RFC 5424 syslog messages.
+///
+/// Over TCP and TLS, messages are framed with octet counting (RFC 5425 and RFC 6587). Over UDP, each message is sent
+/// as one datagram (RFC 5426). Messages are queued by `write` and sent by `flush`; while the server is unreachable,
+/// they stay queued up to `NetworkOptions::max_queued` messages.
+pub struct SyslogSink {
+    options: SyslogOptions,
+    sender: QueuedSender,
+}
+
+impl SyslogSink {
+    /// Create a sink. The connection is opened by the first `flush`.
+    pub fn new(options: SyslogOptions) -> Self {
+        Self {
+            sender: QueuedSender::new(options.network.clone()),
+            options,
+        }
+    }
+
+    /// Number of messages waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.sender.queued()
+    }
+
+    /// Number of messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.sender.dropped()
+    }
+}
+
+impl EventSink for SyslogSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let message = format_syslog_message(record, &self.options);
+
+        let framed = match self.options.network.transport {
+            Transport::Udp => truncate_utf8(message, self.options.max_datagram_size).into_bytes(),
+            Transport::Tcp | Transport::Tls => {
+                let mut framed = format!("{} ", message.len()).into_bytes();
+                framed.extend_from_slice(message.as_bytes());
+                framed
+            }
+        };
+
+        self.sender.enqueue(framed);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.sender.send_queued()
+    }
+}
+
+/// Syslog severity of an event level: critical (2), error (3), warning (4), informational (6) or debug (7).
+///
+/// Level 0 (log always), used by the Security channel for all audit events, is informational.
+pub fn syslog_severity(level: Option<u8>) -> u8 {
+    match level {
+        Some(1) => 2,
+        Some(2) => 3,
+        Some(3) => 4,
+        None | Some(0) | Some(4) => 6,
+        Some(_) => 7,
+    }
+}
+
+/// Format a record as RFC 5424 syslog message, without framing.
+///
+/// HOSTNAME is the computer, APP-NAME the provider, PROCID the process ID and MSGID the event ID. The system properties
+/// and the event data are structured data elements, and MSG is the rendered message.
+pub fn format_syslog_message(record: &EventRecord, options: &SyslogOptions) -> String {
+    let priority = options.facility.min(23) as u32 * 8 + syslog_severity(record.level) as u32;
+
+    let mut message = format!(
+        "<{}>1 {} {} {} {} {} ",
+        priority,
+        record
+            .time_created
+            .to_rfc3339_opts(SecondsFormat::Micros, true),
+        header_field(record.computer.as_deref().unwrap_or_default(), 255),
+        header_field(&record.provider_name, 48),
+        header_field(
+            &record
+                .process_id
+                .map(|process_id| process_id.to_string())
+                .unwrap_or_default(),
+            128
+        ),
+        record.event_id,
+    );
+
+    let mut structured_data = String::new();
+    if options.fields.system {
+        let mut parameters = Vec::new();
+        let mut add = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                parameters.push((name.to_owned(), value));
+            }
+        };
+
+        add("Channel", record.channel.clone());
+        add("EventRecordID", Some(record.event_record_id.to_string()));
+        add("ProviderGuid", record.provider_guid.clone());
+        add("Level", record.level.map(|level| level.to_string()));
+        add("Task", record.task.map(|task| task.to_string()));
+        add("Opcode", record.opcode.map(|opcode| opcode.to_string()));
+        add(
+            "Keywords",
+            record.keywords.map(|keywords| format!("0x{:X}", keywords)),
+        );
+        add("Version", record.version.map(|version| version.to_string()));
+        add(
+            "Qualifiers",
+            record.qualifiers.map(|qualifiers| qualifiers.to_string()),
+        );
+        add(
+            "ThreadID",
+            record.thread_id.map(|thread_id| thread_id.to_string()),
+        );
+        add("ActivityID", record.activity_id.clone());
+        add("RelatedActivityID", record.related_activity_id.clone());
+        add("UserID", record.user_id.clone());
+
+        push_element(
+            &mut structured_data,
+            &format!("event@{}", options.enterprise_number),
+            parameters
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        );
+    }
+    if options.fields.event_data && !record.event_data.is_empty() {
+        push_element(
+            &mut structured_data,
+            &format!("data@{}", options.enterprise_number),
+            record
+                .event_data
+                .iter()
+                .map(|(name, value)| (name, value.to_string())),
+        );
+    }
+
+    if structured_data.is_empty() {
+        message.push('-');
+    } else {
+        message.push_str(&structured_data);
+    }
+
+    if options.fields.message {
+        if let Some(text) = record.message.as_deref().filter(|text| !text.is_empty()) {
+            message.push(' ');
+            message.push('\u{FEFF}');
+            message.push_str(text);
+        }
+    }
+
+    message
+}
+
+/// Append a structured data element. Parameter names are reduced to the characters allowed in an SD-NAME, and
+/// parameters whose name is empty afterwards are skipped.
+fn push_element<'a>(
+    structured_data: &mut String,
+    id: &str,
+    parameters: impl Iterator<Item = (&'a str, String)>,
+) {
+    structured_data.push('[');
+    structured_data.push_str(id);
+
+    for (name, value) in parameters {
+        let name: String = name
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+            .take(32)
+            .collect();
+        if name.is_empty() {
+            continue;
+        }
+
+        structured_data.push(' ');
+        structured_data.push_str(&name);
+        structured_data.push_str("=\"");
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                structured_data.push('\\');
+            }
+            structured_data.push(c);
+        }
+        structured_data.push('"');
+    }
+
+    structured_data.push(']');
+}
+
+/// Header field of printable US-ASCII characters, with other characters replaced by `_`. Empty values are the NILVALUE
+/// `-`.
+fn header_field(value: &str, max_length: usize) -> String {
+    if value.is_empty() {
+        return "-".to_owned();
+    }
+
+    value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_length)
+        .collect()
+}
+
+/// Truncate a string to at most `max_length` bytes without splitting a character.
+fn truncate_utf8(mut value: String, max_length: usize) -> String {
+    if value.len() > max_length {
+        let mut end = max_length;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    value
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::SecondsFormat;
+use serde_json::Value;
+
+use crate::ndjson::variant_to_json;
+use crate::record::EventRecord;
+use crate::sink::EventSink;
+use crate::variant::EventVariantValue;
+
+/// System property of an event usable as column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemField {
+    ProviderName,
+    ProviderGuid,
+    EventId,
+    Qualifiers,
+    Version,
+    Level,
+    Task,
+    Opcode,
+    Keywords,
+    TimeCreated,
+    EventRecordId,
+    ActivityId,
+    RelatedActivityId,
+    ProcessId,
+    ThreadId,
+    Channel,
+    Computer,
+    UserId,
+}
+
+/// System fields with their column names, which are the names used by `EventSystemContext`.
+static SYSTEM_FIELDS: &[(SystemField, &str)] = &[
+    (SystemField::ProviderName, "provider_name"),
+    (SystemField::ProviderGuid, "provider_guid"),
+    (SystemField::EventId, "event_id"),
+    (SystemField::Qualifiers, "qualifiers"),
+    (SystemField::Version, "version"),
+    (SystemField::Level, "level"),
+    (SystemField::Task, "task"),
+    (SystemField::Opcode, "opcode"),
+    (SystemField::Keywords, "keywords"),
+    (SystemField::TimeCreated, "time_created"),
+    (SystemField::EventRecordId, "event_record_id"),
+    (SystemField::ActivityId, "activity_id"),
+    (SystemField::RelatedActivityId, "related_activity_id"),
+    (SystemField::ProcessId, "process_id"),
+    (SystemField::ThreadId, "thread_id"),
+    (SystemField::Channel, "channel"),
+    (SystemField::Computer, "computer"),
+    (SystemField::UserId, "user_id"),
+];
+
+impl SystemField {
+    pub fn name(&self) -> &'static str {
+        SYSTEM_FIELDS
+            .iter()
+            .find(|(field, _)| field == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+
+    fn value(&self, record: &EventRecord) -> Option<String> {
+        match self {
+            SystemField::ProviderName => Some(record.provider_name.clone()),
+            SystemField::ProviderGuid => record.provider_guid.clone(),
+            SystemField::EventId => Some(record.event_id.to_string()),
+            SystemField::Qualifiers => record.qualifiers.map(|value| value.to_string()),
+            SystemField::Version => record.version.map(|value| value.to_string()),
+            SystemField::Level => record.level.map(|value| value.to_string()),
+            SystemField::Task => record.task.map(|value| value.to_string()),
+            SystemField::Opcode => record.opcode.map(|value| value.to_string()),
+            SystemField::Keywords => record.keywords.map(|value| format!("0x{:X}", value)),
+            SystemField::TimeCreated => Some(
+                record
+                    .time_created
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ),
+            SystemField::EventRecordId => Some(record.event_record_id.to_string()),
+            SystemField::ActivityId => record.activity_id.clone(),
+            SystemField::RelatedActivityId => record.related_activity_id.clone(),
+            SystemField::ProcessId => record.process_id.map(|value| value.to_string()),
+            SystemField::ThreadId => record.thread_id.map(|value| value.to_string()),
+            SystemField::Channel => record.channel.clone(),
+            SystemField::Computer => record.computer.clone(),
+            SystemField::UserId => record.user_id.clone(),
+        }
+    }
+}
+
+/// Column of a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    System(SystemField),
+    /// Event data value with the name.
+    EventData(String),
+    /// The rendered message.
+    Message,
+}
+
+impl Column {
+    /// Parse a column name: the name of a system field such as `event_id`, `message`, or the name of an event data
+    /// value. Event data values whose names are also system field names are selected with the prefix `event_data.`,
+    /// e.g. `event_data.level`.
+    pub fn parse(name: &str) -> Self {
+        if let Some(name) = name.strip_prefix("event_data.") {
+            return Column::EventData(name.to_owned());
+        }
+        if name == "message" {
+            return Column::Message;
+        }
+
+        SYSTEM_FIELDS
+            .iter()
+            .find(|(_, system_name)| *system_name == name)
+            .map_or_else(
+                || Column::EventData(name.to_owned()),
+                |(field, _)| Column::System(*field),
+            )
+    }
+
+    /// Name of the column in the header.
+    pub fn name(&self) -> &str {
+        match self {
+            Column::System(field) => field.name(),
+            Column::EventData(name) => name,
+            Column::Message => "message",
+        }
+    }
+}
+
+/// Format of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableFormat {
+    /// Comma-separated values as in RFC 4180: fields with commas, quotes or line breaks are quoted, quotes are
+    /// doubled, and lines end with CRLF.
+    #[default]
+    Csv,
+    /// Tab-separated values: tabs, line breaks and backslashes in fields are escaped as `\t`, `\n`, `\r` and `\\`, and
+    /// lines end with LF.
+    Tsv,
+}
+
+impl TableFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Tsv => "tsv",
+        }
+    }
+}
+
+/// How array values of the event data are written into a single field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArrayFlattening {
+    /// Elements joined with the separator, e.g. `1;2;3`.
+    Join(String),
+    /// JSON array, e.g. `[1,2,3]` or `["a","b"]`.
+    Json,
+}
+
+impl Default for ArrayFlattening {
+    fn default() -> Self {
+        ArrayFlattening::Join(";".to_owned())
+    }
+}
+
+/// Settings of the table sinks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableOptions {
+    pub format: TableFormat,
+    /// Columns in the order they are written. Events without a value for a column get an empty field, so all rows
+    /// have the same columns regardless of the event.
+    pub columns: Vec<Column>,
+    pub arrays: ArrayFlattening,
+    /// Write a header line with the column names.
+    pub header: bool,
+}
+
+impl TableOptions {
+    pub fn new(format: TableFormat, columns: Vec<Column>) -> Self {
+        Self {
+            format,
+            columns,
+            arrays: ArrayFlattening::default(),
+            header: true,
+        }
+    }
+
+    /// Columns for an overview of events of any kind: time, computer, channel, provider, event ID, level, record ID
+    /// and message.
+    pub fn default_columns() -> Vec<Column> {
+        vec![
+            Column::System(SystemField::TimeCreated),
+            Column::System(SystemField::Computer),
+            Column::System(SystemField::Channel),
+            Column::System(SystemField::ProviderName),
+            Column::System(SystemField::EventId),
+            Column::System(SystemField::Level),
+            Column::System(SystemField::EventRecordId),
+            Column::Message,
+        ]
+    }
+}
+
+/// Value of a column for a record. Missing values are empty.
+pub fn column_value(record: &EventRecord, column: &Column, arrays: &ArrayFlattening) -> String {
+    match column {
+        Column::System(field) => field.value(record).unwrap_or_default(),
+        Column::EventData(name) => record
+            .event_data
+            .get(name)
+            .map(|value| flatten_value(value, arrays))
+            .unwrap_or_default(),
+        Column::Message => record.message.clone().unwrap_or_default(),
+    }
+}
+
+fn flatten_value(value: &EventVariantValue, arrays: &ArrayFlattening) -> String {
+    let Value::Array(elements) = variant_to_json(value) else {
+        return match value {
+            EventVariantValue::Null => String::new(),
+            value => value.to_string(),
+        };
+    };
+
+    match arrays {
+        ArrayFlattening::Join(separator) => elements
+            .iter()
+            .map(|element| match element {
+                Value::String(text) => text.clone(),
+                element => element.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(separator),
+        ArrayFlattening::Json => Value::Array(elements).to_string(),
+    }
+}
+
+/// Format one line of a table, including the line ending.
+pub fn format_row<'a>(format: TableFormat, fields: impl IntoIterator<Item = &'a str>) -> String {
+    let mut line = String::new();
+
+    for (index, field) in fields.into_iter().enumerate() {
+        match format {
+            TableFormat::Csv => {
+                if index > 0 {
+                    line.push(',');
+                }
+                if field.contains([',', '"', '\r', '\n']) {
+                    line.push('"');
+                    line.push_str(&field.replace('"', "\"\""));
+                    line.push('"');
+                } else {
+                    line.push_str(field);
+                }
+            }
+            TableFormat::Tsv => {
+                if index > 0 {
+                    line.push('\t');
+                }
+                for c in field.chars() {
+                    match c {
+                        '\\' => line.push_str("\\\\"),
+                        '\t' => line.push_str("\\t"),
+                        '\n' => line.push_str("\\n"),
+                        '\r' => line.push_str("\\r"),
+                        c => line.push(c),
+                    }
+                }
+            }
+        }
+    }
+
+    line.push_str(match format {
+        TableFormat::Csv => "\r\n",
+        TableFormat::Tsv => "\n",
+    });
+    line
+}
+
+/// Sink writing events as rows of a CSV or TSV table.
+pub struct TableSink<W: Write> {
+    writer: BufWriter<W>,
+    options: TableOptions,
+    header_written: bool,
+}
+
+impl<W: Write> TableSink<W> {
+    /// Create a sink. The header is written with the first row.
+    pub fn new(writer: W, options: TableOptions) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            options,
+            header_written: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flush the buffer and return the underlying writer.
+    pub fn into_inner(self) -> Result<W, String> {
+        self.writer
+            .into_inner()
+            .map_err(|error| format!("Failed to flush table: {}", error.error()))
+    }
+}
+
+impl<W: Write> EventSink for TableSink<W> {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let mut lines = String::new();
+        if self.options.header && !self.header_written {
+            lines.push_str(&format_row(
+                self.options.format,
+                self.options.columns.iter().map(Column::name),
+            ));
+        }
+
+        let values: Vec<String> = self
+            .options
+            .columns
+            .iter()
+            .map(|column| column_value(record, column, &self.options.arrays))
+            .collect();
+        lines.push_str(&format_row(
+            self.options.format,
+            values.iter().map(String::as_str),
+        ));
+
+        self.writer
+            .write_all(lines.as_bytes())
+            .map_err(|error| format!("Failed to write table row: {}", error))?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|error| format!("Failed to flush table: {}", error))
+    }
+}
+
+/// Sink writing one table file per provider and event ID, e.g. `events-Microsoft-Windows-Security-Auditing-4624.csv`,
+/// so each file can have the columns of its event. Event IDs are only unique within a provider.
+///
+/// The columns of an event are those of `columns_for` if set for it, and the default columns otherwise. Existing
+/// files are replaced. Events of providers whose names map to the same file name (e.g. `Contoso/Logon` and
+/// `Contoso:Logon`) are rejected once the file is used by the other provider.
+pub struct SplitTableSink {
+    directory: PathBuf,
+    prefix: String,
+    options: TableOptions,
+    columns_for: HashMap<(String, u16), Vec<Column>>,
+    sinks: HashMap<(String, u16), TableSink<File>>,
+    /// Provider name and event ID each created file belongs to.
+    paths: HashMap<PathBuf, (String, u16)>,
+}
+
+impl SplitTableSink {
+    /// Create a sink writing `<prefix><provider>-<event id>.<csv|tsv>` files into the directory. Files are created
+    /// when the first event of their provider and ID is written.
+    pub fn new(directory: &Path, prefix: &str, options: TableOptions) -> Self {
+        Self {
+            directory: directory.to_owned(),
+            prefix: prefix.to_owned(),
+            options,
+            columns_for: HashMap::new(),
+            sinks: HashMap::new(),
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Set the columns of the file of an event, e.g. the event data of a Security event.
+    pub fn set_columns(&mut self, provider_name: &str, event_id: u16, columns: Vec<Column>) {
+        self.columns_for
+            .insert((provider_name.to_owned(), event_id), columns);
+    }
+
+    /// Path of the file of an event. Characters of the provider name that are not allowed in file names are replaced
+    /// with `_`.
+    pub fn path(&self, provider_name: &str, event_id: u16) -> PathBuf {
+        let provider: String = provider_name
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+
+        self.directory.join(format!(
+            "{}{}-{}.{}",
+            self.prefix,
+            provider,
+            event_id,
+            self.options.format.extension()
+        ))
+    }
+}
+
+impl EventSink for SplitTableSink {
+    fn write(&mut self, record: &EventRecord) -> Result<(), String> {
+        let key = (record.provider_name.clone(), record.event_id);
+        if !self.sinks.contains_key(&key) {
+            let path = self.path(&record.provider_name, record.event_id);
+            if let Some((provider_name, _)) = self.paths.get(&path) {
+                return Err(format!(
+                    "Events of providers '{}' and '{}' would both be written to {}",
+                    provider_name,
+                    record.provider_name,
+                    path.display()
+                ));
+            }
+
+            let file = File::create(&path)
+                .map_err(|error| format!("Failed to create {}: {}", path.display(), error))?;
+
+            let mut options = self.options.clone();
+            if let Some(columns) = self.columns_for.get(&key) {
+                options.columns = columns.clone();
+            }
+            self.paths.insert(path, key.clone());
+            self.sinks
+                .insert(key.clone(), TableSink::new(file, options));
+        }
+
+        self.sinks.get_mut(&key).unwrap().write(record)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        for sink in self.sinks.values_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use native_tls::{Certificate, TlsConnector, TlsStream};
+
+/// Network protocol of a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+}
+
+/// Settings of `Transport::Tls`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// Name the server certificate is validated against. Defaults to the host of the address.
+    pub domain: Option<String>,
+    /// PEM file with an additional trusted root certificate, e.g. of a private CA. The roots trusted by the system are
+    /// always used.
+    pub root_certificate: Option<PathBuf>,
+}
+
+/// Connection settings shared by the network sinks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkOptions {
+    /// Address of the server as `host:port`.
+    pub address: String,
+    pub transport: Transport,
+    pub tls: TlsOptions,
+    /// Timeout for connecting and for each write.
+    pub timeout: Duration,
+    /// Number of messages kept while the server is unreachable. When the queue is full, the oldest message is
+    /// dropped.
+    pub max_queued: usize,
+}
+
+impl NetworkOptions {
+    pub fn new(address: &str, transport: Transport) -> Self {
+        Self {
+            address: address.to_owned(),
+            transport,
+            tls: TlsOptions::default(),
+            timeout: Duration::from_secs(10),
+            max_queued: 10_000,
+        }
+    }
+}
+
+/// Open connection of a network sink.
+pub enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Connection {
+    /// Connect to the server. For UDP, this only binds a local socket and sets the default destination.
+    pub fn connect(options: &NetworkOptions) -> Result<Self, String> {
+        let addresses: Vec<_> = options
+            .address
+            .to_socket_addrs()
+            .map_err(|error| format!("Failed to resolve '{}': {}", options.address, error))?
+            .collect();
+
+        if options.transport == Transport::Udp {
+            let address = addresses
+                .first()
+                .ok_or_else(|| format!("Failed to resolve '{}'", options.address))?;
+            let local = if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local)
+                .and_then(|socket| socket.connect(address).map(|_| socket))
+                .map_err(|error| {
+                    format!(
+                        "Failed to open UDP socket to {}: {}",
+                        options.address, error
+                    )
+                })?;
+            return Ok(Connection::Udp(socket));
+        }
+
+        let mut last_error = format!("Failed to resolve '{}'", options.address);
+        let mut stream = None;
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, options.timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(error) => {
+                    last_error = format!("Failed to connect to {}: {}", options.address, error)
+                }
+            }
+        }
+        let stream = stream.ok_or(last_error)?;
+        stream
+            .set_write_timeout(Some(options.timeout))
+            .and_then(|_| stream.set_read_timeout(Some(options.timeout)))
+            .and_then(|_| stream.set_nodelay(true))
+            .map_err(|error| format!("Failed to configure connection: {}", error))?;
+
+        if options.transport == Transport::Tcp {
+            return Ok(Connection::Tcp(stream));
+        }
+
+        let mut builder = TlsConnector::builder();
+        if let Some(path) = &options.tls.root_certificate {
+            let pem = std::fs::read(path).map_err(|error| {
+                format!(
+                    "Failed to read root certificate {}: {}",
+                    path.display(),
+                    error
+                )
+            })?;
+            let certificate = Certificate::from_pem(&pem).map_err(|error| {
+                format!(
+                    "Failed to parse root certificate {}: {}",
+                    path.display(),
+                    error
+                )
+            })?;
+            builder.add_root_certificate(certificate);
+        }
+        let connector = builder
+            .build()
+            .map_err(|error| format!("Failed to create TLS connector: {}", error))?;
+
+        let domain = match &options.tls.domain {
+            Some(domain) => domain.as_str(),
+            None => host_of(&options.address),
+        };
+        let stream = connector
+            .connect(domain, stream)
+            .map_err(|error| format!("TLS handshake with {} failed: {}", options.address, error))?;
+
+        Ok(Connection::Tls(Box::new(stream)))
+    }
+
+    /// Send a datagram, or write the data to the stream.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        let result = match self {
+            Connection::Udp(socket) => socket.send(data).map(|_| ()),
+            Connection::Tcp(stream) => stream.write_all(data),
+            Connection::Tls(stream) => stream.write_all(data),
+        };
+
+        result.map_err(|error| format!("Failed to send data: {}", error))
+    }
+
+    pub fn is_datagram(&self) -> bool {
+        matches!(self, Connection::Udp(_))
+    }
+}
+
+/// Reads from the stream, or receives a datagram.
+impl Read for Connection {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Udp(socket) => socket.recv(buffer),
+            Connection::Tcp(stream) => stream.read(buffer),
+            Connection::Tls(stream) => stream.read(buffer),
+        }
+    }
+}
+
+/// Writes to the stream, or sends each buffer as a datagram.
+impl Write for Connection {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Udp(socket) => socket.send(buffer),
+            Connection::Tcp(stream) => stream.write(buffer),
+            Connection::Tls(stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Udp(_) => Ok(()),
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Host part of a `host:port` address, without the brackets of an IPv6 address.
+pub fn host_of(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Sender of framed messages that reconnects after errors and queues messages while the server is unreachable.
+///
+/// Each queued message is sent with one `Connection::send` per part, i.e. as one datagram per part over UDP, so it must
+/// already be framed for the transport. A message split into several parts is queued and dropped as a whole.
+pub struct QueuedSender {
+    options: NetworkOptions,
+    connection: Option<Connection>,
+    queue: VecDeque<Vec<Vec<u8>>>,
+    /// Number of parts of the first queued message that were already sent.
+    sent: usize,
+    dropped: u64,
+}
+
+impl QueuedSender {
+    /// Create a sender. The connection is opened by the first `send_queued`.
+    pub fn new(options: NetworkOptions) -> Self {
+        Self {
+            options,
+            connection: None,
+            queue: VecDeque::new(),
+            sent: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn options(&self) -> &NetworkOptions {
+        &self.options
+    }
+
+    /// Queue a message. If the queue is full, the oldest message is dropped.
+    pub fn enqueue(&mut self, message: Vec<u8>) {
+        self.enqueue_parts(vec![message]);
+    }
+
+    /// Queue a message that is sent in several parts, e.g. the chunks of a GELF message. If the queue is full, the
+    /// oldest message is dropped with all its parts.
+    pub fn enqueue_parts(&mut self, parts: Vec<Vec<u8>>) {
+        if self.options.max_queued == 0 {
+            self.dropped += 1;
+            return;
+        }
+
+        while self.queue.len() >= self.options.max_queued {
+            self.queue.pop_front();
+            self.sent = 0;
+            self.dropped += 1;
+        }
+        self.queue.push_back(parts);
+    }
+
+    /// Send all queued messages, connecting first if needed.
+    ///
+    /// On an error, the connection is closed and the messages not sent yet stay queued, so the next call reconnects
+    /// and continues with them, starting at the first part that was not sent.
+    pub fn send_queued(&mut self) -> Result<(), String> {
+        if self.queue.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.try_send_queued();
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    fn try_send_queued(&mut self) -> Result<(), String> {
+        if self.connection.is_none() {
+            self.connection = Some(Connection::connect(&self.options)?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+
+        while let Some(parts) = self.queue.front() {
+            for part in &parts[self.sent..] {
+                connection.send(part)?;
+                self.sent += 1;
+            }
+            self.queue.pop_front();
+            self.sent = 0;
+        }
+
+        connection
+            .flush()
+            .map_err(|error| format!("Failed to send data: {}", error))
+    }
+
+    /// Number of messages waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
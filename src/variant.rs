@@ -0,0 +1,303 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use windows_sys::core::GUID;
+use windows_sys::Win32::System::EventLog::EVT_HANDLE;
+
+/// Rust representation of a EVT_VARIANT, owning all of its data.
+///
+/// This enum and its implementation assumes that any
+/// data field of a variant referenced by pointer is not a nullpointer.
+///
+/// This is not guaranteed if the fields of rendering an event
+/// are fixed, e.g. when rendering a system event.
+///
+/// Bigger data fields are intentionally boxed. No guarantees are provided
+/// regarding the validity of the EvtHandle value.
+///
+pub enum EventVariantValue {
+    Null,
+    Bool(bool),
+    SByte(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Byte(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Single(f32),
+    Double(f64),
+    FileTime(DateTime<Utc>),
+    /// An invalid `SYSTEMTIME`, e.g. a zeroed one, is rendered as `Null`.
+    SysTime(NaiveDateTime),
+    Guid(Box<GUID>),
+    HexInt32(u32),
+    HexInt64(u64),
+    String(String),
+    AnsiString(String),
+    Binary(Vec<u8>),
+    /// String form, e.g. `S-1-5-18`. A SID is longer than `SID` if it has more than one subauthority, so it is
+    /// formatted while the render buffer is alive instead of being copied.
+    Sid(String),
+    SizeT(usize),
+    BoolArr(Vec<bool>),
+    SByteArr(Vec<i8>),
+    Int16Arr(Vec<i16>),
+    Int32Arr(Vec<i32>),
+    Int64Arr(Vec<i64>),
+    ByteArr(Vec<u8>),
+    UInt16Arr(Vec<u16>),
+    UInt32Arr(Vec<u32>),
+    UInt64Arr(Vec<u64>),
+    SingleArr(Vec<f32>),
+    DoubleArr(Vec<f64>),
+    FileTimeArr(Vec<DateTime<Utc>>),
+    SysTimeArr(Vec<NaiveDateTime>),
+    GuidArr(Vec<GUID>),
+    HexInt32Arr(Vec<u32>),
+    HexInt64Arr(Vec<u64>),
+    StringArr(Vec<String>),
+    AnsiStringArr(Vec<String>),
+    SidArr(Vec<String>),
+    SizeTArr(Vec<usize>),
+    EvtHandle(EVT_HANDLE),
+    Xml(String),
+    XmlArr(Vec<String>),
+    UnknownType(i32),
+    UnknownTypeArr(i32),
+}
+
+impl fmt::Debug for EventVariantValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventVariantValue::Null => write!(f, "Null"),
+            EventVariantValue::Bool(value) => f.debug_tuple("Bool").field(value).finish(),
+            EventVariantValue::SByte(value) => f.debug_tuple("SByte").field(value).finish(),
+            EventVariantValue::Int16(value) => f.debug_tuple("Int16").field(value).finish(),
+            EventVariantValue::Int32(value) => f.debug_tuple("Int32").field(value).finish(),
+            EventVariantValue::Int64(value) => f.debug_tuple("Int64").field(value).finish(),
+            EventVariantValue::Byte(value) => f.debug_tuple("Byte").field(value).finish(),
+            EventVariantValue::UInt16(value) => f.debug_tuple("UInt16").field(value).finish(),
+            EventVariantValue::UInt32(value) => f.debug_tuple("UInt32").field(value).finish(),
+            EventVariantValue::UInt64(value) => f.debug_tuple("UInt64").field(value).finish(),
+            EventVariantValue::Single(value) => f.debug_tuple("Single").field(value).finish(),
+            EventVariantValue::Double(value) => f.debug_tuple("Double").field(value).finish(),
+            EventVariantValue::FileTime(value) => f.debug_tuple("FileTime").field(value).finish(),
+            EventVariantValue::SysTime(value) => f.debug_tuple("SysTime").field(value).finish(),
+            EventVariantValue::Guid(value) => write!(f, "Guid({})", format_guid(value)),
+            EventVariantValue::HexInt32(value) => write!(f, "HexInt32(0x{:08X})", value),
+            EventVariantValue::HexInt64(value) => write!(f, "HexInt64(0x{:016X})", value),
+            EventVariantValue::String(value) => f.debug_tuple("String").field(value).finish(),
+            EventVariantValue::AnsiString(value) => {
+                f.debug_tuple("AnsiString").field(value).finish()
+            }
+            EventVariantValue::Binary(value) => write!(
+                f,
+                "Binary({:?})",
+                value
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            EventVariantValue::Sid(value) => write!(f, "Sid({})", value),
+            EventVariantValue::SizeT(value) => f.debug_tuple("SizeT").field(value).finish(),
+            EventVariantValue::BoolArr(value) => f.debug_tuple("BoolArr").field(value).finish(),
+            EventVariantValue::SByteArr(value) => f.debug_tuple("SByteArr").field(value).finish(),
+            EventVariantValue::Int16Arr(value) => f.debug_tuple("Int16Arr").field(value).finish(),
+            EventVariantValue::Int32Arr(value) => f.debug_tuple("Int32Arr").field(value).finish(),
+            EventVariantValue::Int64Arr(value) => f.debug_tuple("Int64Arr").field(value).finish(),
+            EventVariantValue::ByteArr(value) => f.debug_tuple("ByteArr").field(value).finish(),
+            EventVariantValue::UInt16Arr(value) => f.debug_tuple("UInt16Arr").field(value).finish(),
+            EventVariantValue::UInt32Arr(value) => f.debug_tuple("UInt32Arr").field(value).finish(),
+            EventVariantValue::UInt64Arr(value) => f.debug_tuple("UInt64Arr").field(value).finish(),
+            EventVariantValue::SingleArr(value) => f.debug_tuple("SingleArr").field(value).finish(),
+            EventVariantValue::DoubleArr(value) => f.debug_tuple("DoubleArr").field(value).finish(),
+            EventVariantValue::FileTimeArr(value) => {
+                f.debug_tuple("FileTimeArr").field(value).finish()
+            }
+            EventVariantValue::SysTimeArr(value) => {
+                f.debug_tuple("SysTimeArr").field(value).finish()
+            }
+            EventVariantValue::GuidArr(value) => {
+                let formatted: Vec<String> = value.iter().map(format_guid).collect();
+                write!(f, "GuidArr({:?})", formatted)
+            }
+            EventVariantValue::HexInt32Arr(value) => {
+                write!(
+                    f,
+                    "HexInt32Arr({:?})",
+                    value
+                        .iter()
+                        .map(|v| format!("0x{:08X}", v))
+                        .collect::<Vec<_>>()
+                )
+            }
+            EventVariantValue::HexInt64Arr(value) => {
+                write!(
+                    f,
+                    "HexInt64Arr({:?})",
+                    value
+                        .iter()
+                        .map(|v| format!("0x{:016X}", v))
+                        .collect::<Vec<_>>()
+                )
+            }
+            EventVariantValue::StringArr(value) => f.debug_tuple("StringArr").field(value).finish(),
+            EventVariantValue::AnsiStringArr(value) => {
+                f.debug_tuple("AnsiStringArr").field(value).finish()
+            }
+            EventVariantValue::SidArr(value) => f.debug_tuple("SidArr").field(value).finish(),
+            EventVariantValue::SizeTArr(value) => f.debug_tuple("SizeTArr").field(value).finish(),
+            EventVariantValue::EvtHandle(value) => f.debug_tuple("EvtHandle").field(value).finish(),
+            EventVariantValue::Xml(value) => f.debug_tuple("Xml").field(value).finish(),
+            EventVariantValue::XmlArr(value) => f.debug_tuple("XmlArr").field(value).finish(),
+            EventVariantValue::UnknownType(value) => {
+                f.debug_tuple("UnknownType").field(value).finish()
+            }
+            EventVariantValue::UnknownTypeArr(value) => {
+                f.debug_tuple("UnknownTypeArr").field(value).finish()
+            }
+        }
+    }
+}
+
+/// Text representation of a value, as it is inserted into event messages.
+///
+/// Array elements are separated by `", "`, `Null` and unknown types render as an empty string.
+impl fmt::Display for EventVariantValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join<T, F>(f: &mut fmt::Formatter<'_>, values: &[T], to_string: F) -> fmt::Result
+        where
+            F: Fn(&T) -> String,
+        {
+            let values: Vec<String> = values.iter().map(to_string).collect();
+            f.write_str(&values.join(", "))
+        }
+
+        let time_format = "%Y-%m-%dT%H:%M:%S%.fZ";
+
+        match self {
+            EventVariantValue::Null
+            | EventVariantValue::UnknownType(_)
+            | EventVariantValue::UnknownTypeArr(_) => Ok(()),
+            EventVariantValue::Bool(value) => write!(f, "{}", value),
+            EventVariantValue::SByte(value) => write!(f, "{}", value),
+            EventVariantValue::Int16(value) => write!(f, "{}", value),
+            EventVariantValue::Int32(value) => write!(f, "{}", value),
+            EventVariantValue::Int64(value) => write!(f, "{}", value),
+            EventVariantValue::Byte(value) => write!(f, "{}", value),
+            EventVariantValue::UInt16(value) => write!(f, "{}", value),
+            EventVariantValue::UInt32(value) => write!(f, "{}", value),
+            EventVariantValue::UInt64(value) => write!(f, "{}", value),
+            EventVariantValue::Single(value) => write!(f, "{}", value),
+            EventVariantValue::Double(value) => write!(f, "{}", value),
+            EventVariantValue::FileTime(value) => write!(f, "{}", value.format(time_format)),
+            EventVariantValue::SysTime(value) => write!(f, "{}", value.format(time_format)),
+            EventVariantValue::Guid(value) => f.write_str(&format_guid(value).to_uppercase()),
+            EventVariantValue::HexInt32(value) => write!(f, "0x{:X}", value),
+            EventVariantValue::HexInt64(value) => write!(f, "0x{:X}", value),
+            EventVariantValue::String(value)
+            | EventVariantValue::AnsiString(value)
+            | EventVariantValue::Xml(value) => f.write_str(value),
+            EventVariantValue::Binary(value) => {
+                value.iter().try_for_each(|b| write!(f, "{:02X}", b))
+            }
+            EventVariantValue::Sid(value) => f.write_str(value),
+            EventVariantValue::SizeT(value) => write!(f, "{}", value),
+            EventVariantValue::BoolArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::SByteArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::Int16Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::Int32Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::Int64Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::ByteArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::UInt16Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::UInt32Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::UInt64Arr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::SingleArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::DoubleArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::FileTimeArr(value) => {
+                join(f, value, |v| v.format(time_format).to_string())
+            }
+            EventVariantValue::SysTimeArr(value) => {
+                join(f, value, |v| v.format(time_format).to_string())
+            }
+            EventVariantValue::GuidArr(value) => join(f, value, |v| format_guid(v).to_uppercase()),
+            EventVariantValue::HexInt32Arr(value) => join(f, value, |v| format!("0x{:X}", v)),
+            EventVariantValue::HexInt64Arr(value) => join(f, value, |v| format!("0x{:X}", v)),
+            EventVariantValue::StringArr(value)
+            | EventVariantValue::AnsiStringArr(value)
+            | EventVariantValue::XmlArr(value) => join(f, value, |v| v.clone()),
+            EventVariantValue::SidArr(value) => join(f, value, |v| v.clone()),
+            EventVariantValue::SizeTArr(value) => join(f, value, |v| v.to_string()),
+            EventVariantValue::EvtHandle(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl EventVariantValue {
+    pub fn is_null(&self) -> bool {
+        matches!(self, EventVariantValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            EventVariantValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value of any unsigned integer variant that fits into a `u32`.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            EventVariantValue::Byte(value) => Some(*value as u32),
+            EventVariantValue::UInt16(value) => Some(*value as u32),
+            EventVariantValue::UInt32(value) | EventVariantValue::HexInt32(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value of any unsigned integer variant as `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            EventVariantValue::UInt64(value) | EventVariantValue::HexInt64(value) => Some(*value),
+            EventVariantValue::SizeT(value) => Some(*value as u64),
+            _ => self.as_u32().map(|value| value as u64),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            EventVariantValue::String(value)
+            | EventVariantValue::AnsiString(value)
+            | EventVariantValue::Xml(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_datetime(&self) -> Option<DateTime<Utc>> {
+        match self {
+            EventVariantValue::FileTime(value) => Some(*value),
+            EventVariantValue::SysTime(value) => Some(value.and_utc()),
+            _ => None,
+        }
+    }
+}
+
+pub fn format_guid(guid: &GUID) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}}}",
+        guid.data1,
+        guid.data2,
+        guid.data3,
+        guid.data4[0],
+        guid.data4[1],
+        guid.data4[2],
+        guid.data4[3],
+        guid.data4[4],
+        guid.data4[5],
+        guid.data4[6],
+        guid.data4[7]
+    )
+}
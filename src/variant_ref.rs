@@ -0,0 +1,445 @@
+use std::fmt;
+use std::slice::from_raw_parts;
+
+use windows_sys::core::{BOOL, GUID, PCSTR, PCWSTR};
+use windows_sys::Win32::Foundation::{FILETIME, SYSTEMTIME};
+use windows_sys::Win32::Security::{PSID, SID};
+use windows_sys::Win32::System::EventLog::*;
+
+use crate::conversions::{TryWindowsConversionTo, WindowsConversionTo};
+use crate::model::{format_sid, EventVariantValue};
+
+/// UTF-16 string borrowed from a rendered event, without the terminating NUL.
+///
+/// The length is determined when the string is borrowed, see `from_ptr`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct WideStr<'buf>(&'buf [u16]);
+
+impl<'buf> WideStr<'buf> {
+    pub fn new(units: &'buf [u16]) -> Self {
+        Self(units)
+    }
+
+    /// Borrow a NUL terminated string. A null pointer gives an empty string.
+    ///
+    /// The terminating NUL is searched for right away instead of on first access. Views are only created for the
+    /// property that is read, and every use of a string (decoding, comparing or getting its length) walks all of it
+    /// anyway, so a lazy length would not save a scan. It would however need a `Cell` for the cached length, which
+    /// would make the view lose `Copy`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to a NUL terminated string that lives for `'buf`.
+    pub unsafe fn from_ptr(ptr: PCWSTR) -> Self {
+        if ptr.is_null() {
+            return Self(&[]);
+        }
+
+        let mut len = 0;
+        while unsafe { *ptr.add(len) } != 0 {
+            len += 1;
+        }
+        Self(unsafe { from_raw_parts(ptr, len) })
+    }
+
+    pub fn as_slice(&self) -> &'buf [u16] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Decode the string, replacing invalid UTF-16 with `U+FFFD`.
+    pub fn to_string_lossy(self) -> String {
+        String::from_utf16_lossy(self.0)
+    }
+
+    /// Compare with a Rust string without decoding or allocating.
+    pub fn eq_str(&self, other: &str) -> bool {
+        self.0.iter().copied().eq(other.encode_utf16())
+    }
+}
+
+impl fmt::Debug for WideStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Display for WideStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        char::decode_utf16(self.0.iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+/// Array of strings borrowed from a rendered event.
+#[derive(Clone, Copy)]
+pub struct WideStrArray<'buf>(&'buf [PCWSTR]);
+
+impl<'buf> WideStrArray<'buf> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<WideStr<'buf>> {
+        self.0
+            .get(index)
+            .map(|ptr| unsafe { WideStr::from_ptr(*ptr) })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = WideStr<'buf>> + 'buf {
+        self.0.iter().map(|ptr| unsafe { WideStr::from_ptr(*ptr) })
+    }
+}
+
+impl fmt::Debug for WideStrArray<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Array of ANSI strings borrowed from a rendered event.
+#[derive(Clone, Copy)]
+pub struct AnsiStrArray<'buf>(&'buf [PCSTR]);
+
+impl<'buf> AnsiStrArray<'buf> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'buf [u8]> + 'buf {
+        self.0.iter().map(|ptr| unsafe { ansi_str(*ptr) })
+    }
+}
+
+impl fmt::Debug for AnsiStrArray<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(String::from_utf8_lossy))
+            .finish()
+    }
+}
+
+/// Array of SIDs borrowed from a rendered event.
+#[derive(Clone, Copy)]
+pub struct SidArray<'buf>(&'buf [PSID]);
+
+impl<'buf> SidArray<'buf> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'buf SID> + 'buf {
+        self.0.iter().map(|sid| unsafe { &*(*sid as *const SID) })
+    }
+}
+
+impl fmt::Debug for SidArray<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SidArray({})", self.len())
+    }
+}
+
+/// Borrowed view of an `EVT_VARIANT` in an `EventVariantBuffer`.
+///
+/// Unlike `EventVariantValue`, creating a view does not copy or decode anything: strings stay UTF-16 and arrays are
+/// slices of the render buffer. Use `to_owned_value` to keep a value beyond the lifetime of the buffer.
+#[derive(Clone, Copy)]
+pub enum EventVariantRef<'buf> {
+    Null,
+    Bool(bool),
+    SByte(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Byte(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Single(f32),
+    Double(f64),
+    /// Windows timestamp in 100 nanosecond intervals since January 1, 1601 (UTC).
+    FileTime(u64),
+    SysTime(&'buf SYSTEMTIME),
+    Guid(&'buf GUID),
+    HexInt32(u32),
+    HexInt64(u64),
+    String(WideStr<'buf>),
+    AnsiString(&'buf [u8]),
+    Binary(&'buf [u8]),
+    Sid(&'buf SID),
+    SizeT(usize),
+    BoolArr(&'buf [BOOL]),
+    SByteArr(&'buf [i8]),
+    Int16Arr(&'buf [i16]),
+    Int32Arr(&'buf [i32]),
+    Int64Arr(&'buf [i64]),
+    ByteArr(&'buf [u8]),
+    UInt16Arr(&'buf [u16]),
+    UInt32Arr(&'buf [u32]),
+    UInt64Arr(&'buf [u64]),
+    SingleArr(&'buf [f32]),
+    DoubleArr(&'buf [f64]),
+    FileTimeArr(&'buf [FILETIME]),
+    SysTimeArr(&'buf [SYSTEMTIME]),
+    GuidArr(&'buf [GUID]),
+    HexInt32Arr(&'buf [u32]),
+    HexInt64Arr(&'buf [u64]),
+    StringArr(WideStrArray<'buf>),
+    AnsiStringArr(AnsiStrArray<'buf>),
+    SidArr(SidArray<'buf>),
+    SizeTArr(&'buf [usize]),
+    EvtHandle(EVT_HANDLE),
+    Xml(WideStr<'buf>),
+    XmlArr(WideStrArray<'buf>),
+    UnknownType(i32),
+    UnknownTypeArr(i32),
+}
+
+impl<'buf> EventVariantRef<'buf> {
+    /// Create a view of a variant rendered by `EvtRender` or one of the `EvtGet*Property` functions.
+    ///
+    /// Null pointers, e.g. of optional system properties, give empty strings and arrays.
+    ///
+    /// # Safety
+    ///
+    /// All pointers of `variant` must be null or valid for `'buf`, which is the case for variants in the buffer they
+    /// were rendered into.
+    pub unsafe fn from_variant(variant: &'buf EVT_VARIANT) -> Self {
+        let is_array = (variant.Type & EVT_VARIANT_TYPE_ARRAY) != 0;
+        let value_type = (variant.Type & EVT_VARIANT_TYPE_MASK) as i32;
+        let count = variant.Count as usize;
+        let value = &variant.Anonymous;
+
+        unsafe {
+            #![allow(nonstandard_style)]
+            if is_array {
+                match value_type {
+                    EvtVarTypeString => Self::StringArr(WideStrArray(slice(
+                        value.StringArr as *const PCWSTR,
+                        count,
+                    ))),
+                    EvtVarTypeAnsiString => Self::AnsiStringArr(AnsiStrArray(slice(
+                        value.AnsiStringArr as *const PCSTR,
+                        count,
+                    ))),
+                    EvtVarTypeSByte => Self::SByteArr(slice(value.SByteArr, count)),
+                    EvtVarTypeByte => Self::ByteArr(slice(value.ByteArr, count)),
+                    EvtVarTypeInt16 => Self::Int16Arr(slice(value.Int16Arr, count)),
+                    EvtVarTypeUInt16 => Self::UInt16Arr(slice(value.UInt16Arr, count)),
+                    EvtVarTypeInt32 => Self::Int32Arr(slice(value.Int32Arr, count)),
+                    EvtVarTypeUInt32 => Self::UInt32Arr(slice(value.UInt32Arr, count)),
+                    EvtVarTypeInt64 => Self::Int64Arr(slice(value.Int64Arr, count)),
+                    EvtVarTypeUInt64 => Self::UInt64Arr(slice(value.UInt64Arr, count)),
+                    EvtVarTypeSingle => Self::SingleArr(slice(value.SingleArr, count)),
+                    EvtVarTypeDouble => Self::DoubleArr(slice(value.DoubleArr, count)),
+                    EvtVarTypeBoolean => Self::BoolArr(slice(value.BooleanArr, count)),
+                    EvtVarTypeGuid => Self::GuidArr(slice(value.GuidArr, count)),
+                    EvtVarTypeSizeT => Self::SizeTArr(slice(value.SizeTArr, count)),
+                    EvtVarTypeFileTime => Self::FileTimeArr(slice(value.FileTimeArr, count)),
+                    EvtVarTypeSysTime => Self::SysTimeArr(slice(value.SysTimeArr, count)),
+                    EvtVarTypeSid => Self::SidArr(SidArray(slice(value.SidArr, count))),
+                    EvtVarTypeHexInt32 => Self::HexInt32Arr(slice(value.UInt32Arr, count)),
+                    EvtVarTypeHexInt64 => Self::HexInt64Arr(slice(value.UInt64Arr, count)),
+                    EvtVarTypeEvtXml => Self::XmlArr(WideStrArray(slice(value.XmlValArr, count))),
+                    _ => Self::UnknownTypeArr(value_type),
+                }
+            } else {
+                match value_type {
+                    EvtVarTypeNull => Self::Null,
+                    EvtVarTypeString => Self::String(WideStr::from_ptr(value.StringVal)),
+                    EvtVarTypeAnsiString => Self::AnsiString(ansi_str(value.AnsiStringVal)),
+                    EvtVarTypeSByte => Self::SByte(value.SByteVal),
+                    EvtVarTypeByte => Self::Byte(value.ByteVal),
+                    EvtVarTypeInt16 => Self::Int16(value.Int16Val),
+                    EvtVarTypeUInt16 => Self::UInt16(value.UInt16Val),
+                    EvtVarTypeInt32 => Self::Int32(value.Int32Val),
+                    EvtVarTypeUInt32 => Self::UInt32(value.UInt32Val),
+                    EvtVarTypeInt64 => Self::Int64(value.Int64Val),
+                    EvtVarTypeUInt64 => Self::UInt64(value.UInt64Val),
+                    EvtVarTypeSingle => Self::Single(value.SingleVal),
+                    EvtVarTypeDouble => Self::Double(value.DoubleVal),
+                    EvtVarTypeBoolean => Self::Bool(value.BooleanVal != 0),
+                    EvtVarTypeBinary => Self::Binary(slice(value.BinaryVal, count)),
+                    EvtVarTypeGuid => match value.GuidVal.as_ref() {
+                        Some(guid) => Self::Guid(guid),
+                        None => Self::Null,
+                    },
+                    EvtVarTypeSizeT => Self::SizeT(value.SizeTVal),
+                    EvtVarTypeFileTime => Self::FileTime(value.FileTimeVal),
+                    EvtVarTypeSysTime => match value.SysTimeVal.as_ref() {
+                        Some(time) => Self::SysTime(time),
+                        None => Self::Null,
+                    },
+                    EvtVarTypeSid => match (value.SidVal as *const SID).as_ref() {
+                        Some(sid) => Self::Sid(sid),
+                        None => Self::Null,
+                    },
+                    EvtVarTypeHexInt32 => Self::HexInt32(value.UInt32Val),
+                    EvtVarTypeHexInt64 => Self::HexInt64(value.UInt64Val),
+                    EvtVarTypeEvtHandle => Self::EvtHandle(value.EvtHandleVal),
+                    EvtVarTypeEvtXml => Self::Xml(WideStr::from_ptr(value.XmlVal)),
+                    _ => Self::UnknownType(value_type),
+                }
+            }
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, EventVariantRef::Null)
+    }
+
+    /// Get a string or XML value without decoding it.
+    pub fn as_wide_str(&self) -> Option<WideStr<'buf>> {
+        match self {
+            EventVariantRef::String(value) | EventVariantRef::Xml(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value of any unsigned integer variant as `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            EventVariantRef::Byte(value) => Some(value as u64),
+            EventVariantRef::UInt16(value) => Some(value as u64),
+            EventVariantRef::UInt32(value) | EventVariantRef::HexInt32(value) => Some(value as u64),
+            EventVariantRef::UInt64(value) | EventVariantRef::HexInt64(value) => Some(value),
+            EventVariantRef::SizeT(value) => Some(value as u64),
+            _ => None,
+        }
+    }
+
+    /// Get binary data or a byte array without copying it.
+    pub fn as_bytes(&self) -> Option<&'buf [u8]> {
+        match self {
+            EventVariantRef::Binary(value) | EventVariantRef::ByteArr(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Copy the value into an `EventVariantValue`, decoding strings and timestamps.
+    ///
+    /// Invalid UTF-16 is replaced with `U+FFFD`, and ANSI strings are decoded as UTF-8 with replacement.
+    pub fn to_owned_value(self) -> EventVariantValue {
+        match self {
+            EventVariantRef::Null => EventVariantValue::Null,
+            EventVariantRef::Bool(value) => EventVariantValue::Bool(value),
+            EventVariantRef::SByte(value) => EventVariantValue::SByte(value),
+            EventVariantRef::Int16(value) => EventVariantValue::Int16(value),
+            EventVariantRef::Int32(value) => EventVariantValue::Int32(value),
+            EventVariantRef::Int64(value) => EventVariantValue::Int64(value),
+            EventVariantRef::Byte(value) => EventVariantValue::Byte(value),
+            EventVariantRef::UInt16(value) => EventVariantValue::UInt16(value),
+            EventVariantRef::UInt32(value) => EventVariantValue::UInt32(value),
+            EventVariantRef::UInt64(value) => EventVariantValue::UInt64(value),
+            EventVariantRef::Single(value) => EventVariantValue::Single(value),
+            EventVariantRef::Double(value) => EventVariantValue::Double(value),
+            EventVariantRef::FileTime(value) => EventVariantValue::FileTime(value.win_into()),
+            EventVariantRef::SysTime(value) => (*value)
+                .try_win_into()
+                .map_or(EventVariantValue::Null, EventVariantValue::SysTime),
+            EventVariantRef::Guid(value) => EventVariantValue::Guid(Box::new(*value)),
+            EventVariantRef::HexInt32(value) => EventVariantValue::HexInt32(value),
+            EventVariantRef::HexInt64(value) => EventVariantValue::HexInt64(value),
+            EventVariantRef::String(value) => EventVariantValue::String(value.to_string_lossy()),
+            EventVariantRef::AnsiString(value) => {
+                EventVariantValue::AnsiString(String::from_utf8_lossy(value).into_owned())
+            }
+            EventVariantRef::Binary(value) => EventVariantValue::Binary(value.to_vec()),
+            EventVariantRef::Sid(value) => EventVariantValue::Sid(format_sid(value)),
+            EventVariantRef::SizeT(value) => EventVariantValue::SizeT(value),
+            EventVariantRef::BoolArr(value) => {
+                EventVariantValue::BoolArr(value.iter().map(|b| *b != 0).collect())
+            }
+            EventVariantRef::SByteArr(value) => EventVariantValue::SByteArr(value.to_vec()),
+            EventVariantRef::Int16Arr(value) => EventVariantValue::Int16Arr(value.to_vec()),
+            EventVariantRef::Int32Arr(value) => EventVariantValue::Int32Arr(value.to_vec()),
+            EventVariantRef::Int64Arr(value) => EventVariantValue::Int64Arr(value.to_vec()),
+            EventVariantRef::ByteArr(value) => EventVariantValue::ByteArr(value.to_vec()),
+            EventVariantRef::UInt16Arr(value) => EventVariantValue::UInt16Arr(value.to_vec()),
+            EventVariantRef::UInt32Arr(value) => EventVariantValue::UInt32Arr(value.to_vec()),
+            EventVariantRef::UInt64Arr(value) => EventVariantValue::UInt64Arr(value.to_vec()),
+            EventVariantRef::SingleArr(value) => EventVariantValue::SingleArr(value.to_vec()),
+            EventVariantRef::DoubleArr(value) => EventVariantValue::DoubleArr(value.to_vec()),
+            EventVariantRef::FileTimeArr(value) => {
+                EventVariantValue::FileTimeArr(value.iter().map(|f| (*f).win_into()).collect())
+            }
+            EventVariantRef::SysTimeArr(value) => value
+                .iter()
+                .map(|s| (*s).try_win_into())
+                .collect::<Result<_, _>>()
+                .map_or(EventVariantValue::Null, EventVariantValue::SysTimeArr),
+            EventVariantRef::GuidArr(value) => EventVariantValue::GuidArr(value.to_vec()),
+            EventVariantRef::HexInt32Arr(value) => EventVariantValue::HexInt32Arr(value.to_vec()),
+            EventVariantRef::HexInt64Arr(value) => EventVariantValue::HexInt64Arr(value.to_vec()),
+            EventVariantRef::StringArr(value) => {
+                EventVariantValue::StringArr(value.iter().map(|s| s.to_string_lossy()).collect())
+            }
+            EventVariantRef::AnsiStringArr(value) => EventVariantValue::AnsiStringArr(
+                value
+                    .iter()
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+                    .collect(),
+            ),
+            EventVariantRef::SidArr(value) => {
+                EventVariantValue::SidArr(value.iter().map(format_sid).collect())
+            }
+            EventVariantRef::SizeTArr(value) => EventVariantValue::SizeTArr(value.to_vec()),
+            EventVariantRef::EvtHandle(value) => EventVariantValue::EvtHandle(value),
+            EventVariantRef::Xml(value) => EventVariantValue::Xml(value.to_string_lossy()),
+            EventVariantRef::XmlArr(value) => {
+                EventVariantValue::XmlArr(value.iter().map(|s| s.to_string_lossy()).collect())
+            }
+            EventVariantRef::UnknownType(value) => EventVariantValue::UnknownType(value),
+            EventVariantRef::UnknownTypeArr(value) => EventVariantValue::UnknownTypeArr(value),
+        }
+    }
+}
+
+/// Formats like the owned value, e.g. `String("Security")`.
+impl fmt::Debug for EventVariantRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_owned_value(), f)
+    }
+}
+
+/// Borrow an array of a variant. A null pointer gives an empty slice.
+unsafe fn slice<'buf, T>(ptr: *const T, count: usize) -> &'buf [T] {
+    if ptr.is_null() || count == 0 {
+        &[]
+    } else {
+        unsafe { from_raw_parts(ptr, count) }
+    }
+}
+
+/// Borrow a NUL terminated ANSI string, without the NUL. A null pointer gives an empty string.
+unsafe fn ansi_str<'buf>(ptr: PCSTR) -> &'buf [u8] {
+    if ptr.is_null() {
+        return &[];
+    }
+
+    let mut len = 0;
+    while unsafe { *ptr.add(len) } != 0 {
+        len += 1;
+    }
+    unsafe { from_raw_parts(ptr, len) }
+}